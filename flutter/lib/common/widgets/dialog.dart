@@ -1,4 +1,5 @@
 import 'dart:async';
+import 'dart:convert';
 
 import 'package:flutter/material.dart';
 import 'package:flutter/services.dart';
@@ -253,6 +254,362 @@ void changeWhiteList({Function()? callback}) async {
   });
 }
 
+void changeAccessList() async {
+  final listController =
+      TextEditingController(text: await bind.mainGetOption(key: 'access-list'));
+  final countryController = TextEditingController(
+      text: await bind.mainGetOption(key: 'geoip-country-database'));
+  final asnController = TextEditingController(
+      text: await bind.mainGetOption(key: 'geoip-asn-database'));
+  var msg = "";
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      final list = listController.text.trim();
+      final invalid = await bind.mainCheckAccessList(list: list);
+      if (invalid.isNotEmpty) {
+        setState(() => msg = "${translate("Invalid rule")} $invalid");
+        return;
+      }
+      await bind.mainSetOption(key: 'access-list', value: list);
+      await bind.mainSetOption(
+          key: 'geoip-country-database', value: countryController.text.trim());
+      await bind.mainSetOption(
+          key: 'geoip-asn-database', value: asnController.text.trim());
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Access list")),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate("access_list_tip")),
+          const SizedBox(height: 8.0),
+          TextField(
+              maxLines: null,
+              decoration: InputDecoration(
+                hintText: 'deny 203.0.113.7\nallow 192.168.0.0/16\nallow country:DE',
+                errorText: msg.isEmpty ? null : msg,
+              ),
+              controller: listController,
+              autofocus: true),
+          TextField(
+              decoration: InputDecoration(
+                  labelText: translate('Country database (MaxMind)')),
+              controller: countryController),
+          TextField(
+              decoration:
+                  InputDecoration(labelText: translate('ASN database (MaxMind)')),
+              controller: asnController),
+        ],
+      ),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        dialogButton("OK", onPressed: submit),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+void changeAuthorizedKeys() async {
+  List<dynamic> keys = jsonDecode(await bind.mainGetAuthorizedKeys());
+  final myKey = await bind.mainGetClientPublicKey();
+  final nameController = TextEditingController();
+  final idController = TextEditingController();
+  final keyController = TextEditingController();
+  var msg = "";
+  gFFI.dialogManager.show((setState, close, context) {
+    reload() async {
+      final v = jsonDecode(await bind.mainGetAuthorizedKeys());
+      setState(() => keys = v);
+    }
+
+    add() async {
+      final res = await bind.mainAddAuthorizedKey(
+          name: nameController.text.trim(),
+          id: idController.text.trim(),
+          pk: keyController.text.trim());
+      setState(() => msg = res);
+      if (res.isEmpty) {
+        nameController.clear();
+        idController.clear();
+        keyController.clear();
+        await reload();
+      }
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Trusted client keys")),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate("authorized_keys_tip")),
+          const SizedBox(height: 8.0),
+          ...keys.map((k) => Row(
+                children: [
+                  Expanded(
+                    child: Tooltip(
+                      message: k['pk'] ?? '',
+                      child: Text(
+                          ((k['name'] ?? '').isEmpty ? k['pk'] : k['name']) +
+                              ((k['id'] ?? '').isEmpty ? '' : ' (${k['id']})'),
+                          overflow: TextOverflow.ellipsis),
+                    ),
+                  ),
+                  IconButton(
+                    tooltip: translate('Revoke'),
+                    icon: const Icon(Icons.delete_outline, size: 18),
+                    onPressed: () async {
+                      await bind.mainRemoveAuthorizedKey(pk: k['pk']);
+                      await reload();
+                    },
+                  ),
+                ],
+              )),
+          TextField(
+              decoration: InputDecoration(labelText: translate('Name')),
+              controller: nameController),
+          TextField(
+              decoration: InputDecoration(labelText: translate('ID')),
+              controller: idController),
+          TextField(
+              decoration: InputDecoration(
+                labelText: translate('Public Key'),
+                errorText: msg.isEmpty ? null : translate(msg),
+              ),
+              controller: keyController),
+          const SizedBox(height: 12.0),
+          Text(translate("Public key of this device")),
+          SelectableText(myKey, style: const TextStyle(fontSize: 12)),
+        ],
+      ),
+      actions: [
+        dialogButton("Close", onPressed: close, isOutline: true),
+        dialogButton("Add", onPressed: add),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+void changeFileTransferRestrictions() async {
+  final rootsController = TextEditingController(
+      text: await bind.mainGetOption(key: 'file-transfer-roots'));
+  final extensionsController = TextEditingController(
+      text: await bind.mainGetOption(key: 'file-transfer-denied-extensions'));
+  var readOnly = await bind.mainGetOption(key: 'file-transfer-read-only') == 'Y';
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      final roots = rootsController.text
+          .split('\n')
+          .map((e) => e.trim())
+          .where((e) => e.isNotEmpty)
+          .join('\n');
+      final extensions = extensionsController.text
+          .split(RegExp(r"[\s,;]+"))
+          .where((e) => e.isNotEmpty)
+          .join(',');
+      await bind.mainSetOption(key: 'file-transfer-roots', value: roots);
+      await bind.mainSetOption(
+          key: 'file-transfer-denied-extensions', value: extensions);
+      await bind.mainSetOption(
+          key: 'file-transfer-read-only', value: readOnly ? 'Y' : '');
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("File transfer restrictions")),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate("file_transfer_roots_tip")),
+          TextField(maxLines: null, controller: rootsController),
+          const SizedBox(height: 8.0),
+          Text(translate("file_transfer_denied_extensions_tip")),
+          TextField(controller: extensionsController),
+          const SizedBox(height: 8.0),
+          CheckboxListTile(
+            contentPadding: EdgeInsets.zero,
+            value: readOnly,
+            onChanged: (v) => setState(() => readOnly = v ?? false),
+            title: Text(translate("File transfer is read-only")),
+          ),
+        ],
+      ),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        dialogButton("OK", onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+void changeSessionLimits() async {
+  final durationController = TextEditingController(
+      text: await bind.mainGetOption(key: 'session-max-duration'));
+  final hoursController =
+      TextEditingController(text: await bind.mainGetOption(key: 'access-hours'));
+  final weekdaysController = TextEditingController(
+      text: await bind.mainGetOption(key: 'access-weekdays'));
+  final expiry = int.tryParse(
+          await bind.mainGetOption(key: 'permanent-password-expiry')) ??
+      0;
+  final daysController = TextEditingController(
+      text: expiry > 0
+          ? (DateTime.fromMillisecondsSinceEpoch(expiry * 1000)
+                      .difference(DateTime.now())
+                      .inHours /
+                  24)
+              .ceil()
+              .clamp(0, 36500)
+              .toString()
+          : '');
+  var msg = "";
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      final duration = durationController.text.trim();
+      final days = daysController.text.trim();
+      if (duration.isNotEmpty && int.tryParse(duration) == null ||
+          days.isNotEmpty && int.tryParse(days) == null) {
+        setState(() => msg = 'Invalid number');
+        return;
+      }
+      final hours = hoursController.text.trim();
+      final hoursMatch = RegExp(
+          r"^([01][0-9]|2[0-3]):[0-5][0-9]-([01][0-9]|2[0-3]):[0-5][0-9]$");
+      // Empty items are skipped, as the host does.
+      bool every(String text, bool Function(String) valid) => text
+          .split(',')
+          .map((e) => e.trim())
+          .where((e) => e.isNotEmpty)
+          .every(valid);
+      if (!every(hours, hoursMatch.hasMatch)) {
+        setState(() => msg = 'access_hours_tip');
+        return;
+      }
+      final weekdays = weekdaysController.text.trim();
+      if (!every(weekdays, (e) => RegExp(r"^[1-7]$").hasMatch(e))) {
+        setState(() => msg = 'access_weekdays_tip');
+        return;
+      }
+      await bind.mainSetOption(key: 'session-max-duration', value: duration);
+      await bind.mainSetOption(key: 'access-hours', value: hours);
+      await bind.mainSetOption(key: 'access-weekdays', value: weekdays);
+      await bind.mainSetOption(
+          key: 'permanent-password-expiry',
+          value: days.isEmpty || int.parse(days) <= 0
+              ? ''
+              : (DateTime.now()
+                          .add(Duration(days: int.parse(days)))
+                          .millisecondsSinceEpoch ~/
+                      1000)
+                  .toString());
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Session limits")),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          TextField(
+              decoration: InputDecoration(
+                  labelText: translate('Maximum session duration (minutes)')),
+              controller: durationController),
+          TextField(
+              decoration: InputDecoration(
+                  labelText: translate('Access hours'),
+                  helperText: translate('access_hours_tip')),
+              controller: hoursController),
+          TextField(
+              decoration: InputDecoration(
+                  labelText: translate('Access weekdays'),
+                  helperText: translate('access_weekdays_tip')),
+              controller: weekdaysController),
+          TextField(
+              decoration: InputDecoration(
+                labelText: translate('Permanent password expires in (days)'),
+                errorText: msg.isEmpty ? null : translate(msg),
+              ),
+              controller: daysController),
+        ],
+      ),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        dialogButton("OK", onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+void setup2faDialog({Function()? callback}) async {
+  final Map<String, dynamic> provisioning =
+      jsonDecode(await bind.mainGenerateTwoFactorAuth());
+  final String secret = provisioning['secret'] ?? '';
+  final String url = provisioning['url'] ?? '';
+  final controller = TextEditingController();
+  var msg = "";
+  var isInProgress = false;
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      setState(() {
+        msg = "";
+        isInProgress = true;
+      });
+      final ok = await bind.mainEnableTwoFactorAuth(
+          secret: secret, code: controller.text.trim());
+      if (!ok) {
+        setState(() {
+          msg = 'wrong_2fa_code_tip';
+          isInProgress = false;
+        });
+        return;
+      }
+      callback?.call();
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Two-factor authentication")),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate("setup_2fa_tip")),
+          const SizedBox(height: 8.0),
+          SelectableText(secret,
+              style: const TextStyle(
+                  fontFamily: 'monospace', fontWeight: FontWeight.bold)),
+          const SizedBox(height: 4.0),
+          SelectableText(url, style: const TextStyle(fontSize: 12)),
+          const SizedBox(height: 8.0),
+          TextField(
+              decoration: InputDecoration(
+                labelText: translate('Verification code'),
+                errorText: msg.isEmpty ? null : translate(msg),
+              ),
+              keyboardType: TextInputType.number,
+              controller: controller,
+              autofocus: true),
+          const SizedBox(height: 4.0),
+          // NOT use Offstage to wrap LinearProgressIndicator
+          if (isInProgress) const LinearProgressIndicator(),
+        ],
+      ),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        dialogButton("OK", onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
 Future<String> changeDirectAccessPort(
     String currentIP, String currentPort) async {
   final controller = TextEditingController(text: currentPort);
@@ -533,6 +890,83 @@ void enterUserLoginAndPasswordDialog(
   );
 }
 
+void enter2faDialog(SessionID sessionId, OverlayDialogManager dialogManager,
+    String title) {
+  final controller = TextEditingController();
+  var trustThisDevice = false;
+  String? errorText =
+      title == 'Wrong 2FA Code' ? translate('wrong_2fa_code_tip') : null;
+  dialogManager.dismissAll();
+  dialogManager.show((setState, close, context) {
+    cancel() {
+      close();
+      closeConnection();
+    }
+
+    submit() {
+      final code = controller.text.trim();
+      if (!RegExp(r'^\d{6}$').hasMatch(code)) {
+        setState(() => errorText = translate('wrong_2fa_code_tip'));
+        return;
+      }
+      bind.sessionSendTwoFactorCode(
+          sessionId: sessionId, code: code, trustThisDevice: trustThisDevice);
+      close();
+      dialogManager.showLoading(translate('Logging in...'),
+          onCancel: closeConnection);
+    }
+
+    return CustomAlertDialog(
+      title: Row(
+        mainAxisAlignment: MainAxisAlignment.center,
+        children: [
+          Icon(Icons.security_rounded, color: MyTheme.accent),
+          Text(translate('2FA Required')).paddingOnly(left: 10),
+        ],
+      ),
+      content: Column(mainAxisSize: MainAxisSize.min, children: [
+        Align(
+          alignment: Alignment.centerLeft,
+          child: Text(translate('enter_2fa_code_tip')),
+        ).marginOnly(bottom: 8),
+        DialogTextField(
+          title: translate('Verification code'),
+          controller: controller,
+          prefixIcon: Icon(Icons.pin_outlined),
+          errorText: errorText,
+        ),
+        CheckboxListTile(
+          contentPadding: const EdgeInsets.all(0),
+          dense: true,
+          controlAffinity: ListTileControlAffinity.leading,
+          title: Text(translate('Trust this device')),
+          value: trustThisDevice,
+          onChanged: (v) {
+            if (v != null) {
+              setState(() => trustThisDevice = v);
+            }
+          },
+        ),
+      ]),
+      actions: [
+        dialogButton(
+          'Cancel',
+          icon: Icon(Icons.close_rounded),
+          onPressed: cancel,
+          isOutline: true,
+        ),
+        dialogButton(
+          'OK',
+          icon: Icon(Icons.done_rounded),
+          onPressed: submit,
+        ),
+      ],
+      onSubmit: submit,
+      onCancel: cancel,
+    );
+  });
+}
+
 _connectDialog(
   SessionID sessionId,
   OverlayDialogManager dialogManager, {
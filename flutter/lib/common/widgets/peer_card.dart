@@ -563,6 +563,23 @@ abstract class BasePeerCard extends StatelessWidget {
     );
   }
 
+  @protected
+  MenuEntryBase<String> _wolThroughPeerAction(String id) {
+    return MenuEntryButton<String>(
+      childBuilder: (TextStyle? style) => Text(
+        translate('WOL through a LAN peer'),
+        style: style,
+      ),
+      proc: () async {
+        showToast(translate('Waking up, please wait...'));
+        final err = await bind.mainWolThroughPeer(id: id);
+        showToast(err.isEmpty ? translate('Successful') : translate(err));
+      },
+      padding: menuPadding,
+      dismissOnClicked: true,
+    );
+  }
+
   /// Only available on Windows.
   @protected
   MenuEntryBase<String> _createShortCutAction(String id) {
@@ -980,6 +997,7 @@ class DiscoveredPeerCard extends BasePeerCard {
       menuItems.add(_rdpAction(context, peer.id));
     }
     menuItems.add(_wolAction(peer.id));
+    menuItems.add(_wolThroughPeerAction(peer.id));
     if (Platform.isWindows) {
       menuItems.add(_createShortCutAction(peer.id));
     }
@@ -480,6 +480,21 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         },
         child: Text(translate('Lock after session end'))));
   }
+  // forward gamepads
+  if (ffiModel.keyboard &&
+      Platform.isLinux &&
+      pi.platform == kPeerPlatformLinux) {
+    final option = 'forward-gamepad';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Forward gamepads'))));
+  }
   // privacy mode
   if (ffiModel.keyboard && pi.features.privacyMode) {
     final option = 'privacy-mode';
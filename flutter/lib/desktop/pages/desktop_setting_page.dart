@@ -398,7 +398,10 @@ class _GeneralState extends State<_General> {
                 label: device, onChanged: (value) {
               setDevice(value);
               setState(() {});
-            }))
+            })),
+        if (Platform.isLinux)
+          _OptionCheckBox(context, 'voice_call_virtual_mic_tip',
+              'allow-voice-call-virtual-mic'),
       ]);
     });
   }
@@ -604,6 +607,9 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
                 enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(context, 'Enable TCP Tunneling', 'enable-tunnel',
                 enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(
+                context, 'Enable WOL for other peers', 'enable-wake-on-lan',
+                enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(
                 context, 'Enable Remote Restart', 'enable-remote-restart',
                 enabled: enabled, fakeValue: fakeValue),
@@ -732,8 +738,16 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
       shareRdp(context, enabled),
       _OptionCheckBox(context, 'Deny LAN Discovery', 'enable-lan-discovery',
           reverse: true, enabled: enabled),
+      _OptionCheckBox(context, 'strict_encryption_tip', 'strict-encryption',
+          enabled: enabled),
       ...directIp(context),
       whitelist(),
+      _Button('Access list', changeAccessList, enabled: enabled),
+      twoFactorAuth(),
+      _Button('Trusted client keys', changeAuthorizedKeys, enabled: enabled),
+      _Button('File transfer restrictions', changeFileTransferRestrictions,
+          enabled: enabled),
+      _Button('Session limits', changeSessionLimits, enabled: enabled),
       ...autoDisconnect(context),
     ]);
   }
@@ -874,6 +888,53 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
     return tmpWrapper();
   }
 
+  Widget twoFactorAuth() {
+    bool enabled = !locked;
+    RxBool has2fa = false.obs;
+    update() async {
+      has2fa.value = await bind.mainHasTwoFactorAuth();
+    }
+
+    update();
+    onChanged(bool? checked) async {
+      if (has2fa.value) {
+        await bind.mainDisableTwoFactorAuth();
+        update();
+      } else {
+        setup2faDialog(callback: update);
+      }
+    }
+
+    return GestureDetector(
+      child: Tooltip(
+        message: translate('enable_2fa_tip'),
+        child: Obx(() => Row(
+              children: [
+                Checkbox(
+                        value: has2fa.value,
+                        onChanged: enabled ? onChanged : null)
+                    .marginOnly(right: 5),
+                Expanded(
+                    child: Text(
+                  translate('Enable 2FA'),
+                  style: TextStyle(color: _disabledTextColor(context, enabled)),
+                )),
+                Offstage(
+                  offstage: !has2fa.value,
+                  child: TextButton(
+                    onPressed: enabled
+                        ? () => bind.mainClearTrustedDevices()
+                        : null,
+                    child: Text(translate('Clear trusted devices')),
+                  ),
+                ),
+              ],
+            )),
+      ),
+      onTap: enabled ? () => onChanged(!has2fa.value) : null,
+    ).marginOnly(left: _kCheckBoxLeftMargin);
+  }
+
   Widget hide_cm(bool enabled) {
     return ChangeNotifierProvider.value(
         value: gFFI.serverModel,
@@ -1007,6 +1068,13 @@ class _NetworkState extends State<_Network> with AutomaticKeepAliveClientMixin {
                     _Button('Socks5 Proxy', changeSocks5Proxy,
                         enabled: enabled),
                   ]),
+                  _Card(title: 'WebSocket', children: [
+                    _OptionCheckBox(context, 'Use WebSocket', 'use-websocket',
+                        enabled: enabled),
+                    _OptionCheckBox(
+                        context, 'Use TLS on port 443', 'websocket-tls',
+                        enabled: enabled),
+                  ]),
                 ]),
               ),
             ]).marginOnly(bottom: _kListViewBottomMargin));
@@ -1879,6 +1947,7 @@ class _ComboBox extends StatelessWidget {
 
 void changeSocks5Proxy() async {
   var socks = await bind.mainGetSocks();
+  String pacUrl = await bind.mainGetOption(key: 'proxy-pac-url');
 
   String proxy = '';
   String proxyMsg = '';
@@ -1892,6 +1961,7 @@ void changeSocks5Proxy() async {
   var proxyController = TextEditingController(text: proxy);
   var userController = TextEditingController(text: username);
   var pwdController = TextEditingController(text: password);
+  var pacController = TextEditingController(text: pacUrl);
   RxBool obscure = true.obs;
 
   var isInProgress = false;
@@ -1910,6 +1980,7 @@ void changeSocks5Proxy() async {
       proxy = proxyController.text.trim();
       username = userController.text.trim();
       password = pwdController.text.trim();
+      pacUrl = pacController.text.trim();
 
       if (proxy.isNotEmpty) {
         proxyMsg = translate(await bind.mainTestIfValidServer(server: proxy));
@@ -1922,6 +1993,7 @@ void changeSocks5Proxy() async {
       }
       await bind.mainSetSocks(
           proxy: proxy, username: username, password: password);
+      await bind.mainSetOption(key: 'proxy-pac-url', value: pacUrl);
       close();
     }
 
@@ -1943,6 +2015,7 @@ void changeSocks5Proxy() async {
                 Expanded(
                   child: TextField(
                     decoration: InputDecoration(
+                        hintText: 'host:1080, http://host:8080',
                         errorText: proxyMsg.isNotEmpty ? proxyMsg : null),
                     controller: proxyController,
                     autofocus: true,
@@ -1986,6 +2059,23 @@ void changeSocks5Proxy() async {
                       )),
                 ),
               ],
+            ).marginOnly(bottom: 8),
+            Row(
+              children: [
+                ConstrainedBox(
+                    constraints: const BoxConstraints(minWidth: 140),
+                    child: Text(
+                      '${translate("PAC URL")}:',
+                      textAlign: TextAlign.right,
+                    ).marginOnly(right: 10)),
+                Expanded(
+                  child: TextField(
+                    decoration: InputDecoration(
+                        hintText: 'http://host/proxy.pac'),
+                    controller: pacController,
+                  ),
+                ),
+              ],
             ),
             // NOT use Offstage to wrap LinearProgressIndicator
             if (isInProgress)
@@ -254,7 +254,8 @@ class _ConnectionTabPageState extends State<ConnectionTabPage> {
                   children: [
                     icon,
                     Tooltip(
-                      message: '$msgConn\n$msgFingerprint',
+                      message:
+                          '$msgConn\n${translate(secure ? 'e2e_encrypted_tip' : 'e2e_unencrypted_tip')}\n$msgFingerprint',
                       child: SvgPicture.asset(
                         'assets/${connectionType.secure.value}${connectionType.direct.value}.svg',
                         width: themeConf.iconSize,
@@ -885,12 +885,18 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         ffi: widget.ffi,
         color: _ToolbarTheme.blueColor,
         hoverColor: _ToolbarTheme.hoverBlueColor,
+        onOpen: () {
+          if (perms['audio'] != false) {
+            bind.sessionGetRemoteAudioInputs(sessionId: ffi.sessionId);
+          }
+        },
         menuChildren: [
           _screenAdjustor.adjustWindow(context),
           viewStyle(),
           scrollStyle(),
           imageQuality(),
           codec(),
+          audioInput(),
           _ResolutionsMenu(
             id: widget.id,
             ffi: widget.ffi,
@@ -1002,6 +1008,37 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         });
   }
 
+  audioInput() {
+    return Obx(() {
+      if (perms['audio'] == false || ffiModel.audioInputs.isEmpty) {
+        return Offstage();
+      }
+      onChanged(String? value) {
+        if (value == null) return;
+        bind.sessionSelectRemoteAudioInput(
+            sessionId: ffi.sessionId, input: value);
+      }
+
+      return _SubmenuButton(
+          ffi: widget.ffi,
+          child: Text(translate('Audio Input')),
+          menuChildren: [
+            RdoMenuButton<String>(
+                value: '',
+                groupValue: ffiModel.audioInput.value,
+                onChanged: onChanged,
+                child: Text(translate('Default')),
+                ffi: ffi),
+            ...ffiModel.audioInputs.map((e) => RdoMenuButton<String>(
+                value: e,
+                groupValue: ffiModel.audioInput.value,
+                onChanged: onChanged,
+                child: Text(e),
+                ffi: ffi)),
+          ]);
+    });
+  }
+
   toggles() {
     return futureBuilder(
         future: toolbarDisplayToggle(context, id, ffi),
@@ -1682,6 +1719,7 @@ class _IconSubmenuButton extends StatefulWidget {
   final Color hoverColor;
   final List<Widget> menuChildren;
   final MenuStyle? menuStyle;
+  final VoidCallback? onOpen;
   final FFI ffi;
 
   _IconSubmenuButton(
@@ -1693,7 +1731,8 @@ class _IconSubmenuButton extends StatefulWidget {
       required this.hoverColor,
       required this.menuChildren,
       required this.ffi,
-      this.menuStyle})
+      this.menuStyle,
+      this.onOpen})
       : super(key: key);
 
   @override
@@ -1722,6 +1761,7 @@ class _IconSubmenuButtonState extends State<_IconSubmenuButton> {
             onHover: (value) => setState(() {
                   hover = value;
                 }),
+            onOpen: widget.onOpen,
             child: Tooltip(
                 message: translate(widget.tooltip),
                 child: Material(
@@ -340,6 +340,10 @@ class InputModel {
 
   void onPointHoverImage(PointerHoverEvent e) {
     _stopFling = true;
+    if (_isStylus(e)) {
+      handleStylus(e, 'hover');
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (!isPhysicalMouse.value) {
       isPhysicalMouse.value = true;
@@ -484,6 +488,10 @@ class InputModel {
   void onPointDownImage(PointerDownEvent e) {
     debugPrint("onPointDownImage ${e.kind}");
     _stopFling = true;
+    if (_isStylus(e)) {
+      handleStylus(e, 'down');
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) {
       if (isPhysicalMouse.value) {
         isPhysicalMouse.value = false;
@@ -495,6 +503,10 @@ class InputModel {
   }
 
   void onPointUpImage(PointerUpEvent e) {
+    if (_isStylus(e)) {
+      handleStylus(e, 'up');
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (isPhysicalMouse.value) {
       handleMouse(_getMouseEvent(e, _kMouseEventUp), e.position);
@@ -502,6 +514,10 @@ class InputModel {
   }
 
   void onPointMoveImage(PointerMoveEvent e) {
+    if (_isStylus(e)) {
+      handleStylus(e, 'move');
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (isPhysicalMouse.value) {
       handleMouse(_getMouseEvent(e, _kMouseEventMove), e.position);
@@ -610,6 +626,43 @@ class InputModel {
         sessionId: sessionId, msg: json.encode(modify(evt)));
   }
 
+  bool _isStylus(PointerEvent e) =>
+      e.kind == ui.PointerDeviceKind.stylus ||
+      e.kind == ui.PointerDeviceKind.invertedStylus;
+
+  /// Send pen input with pressure and tilt, [type] is 'down', 'move', 'up' or 'hover'.
+  void handleStylus(PointerEvent e, String type) {
+    if (!keyboardPerm) return;
+    final x = e.position.dx;
+    final y = max(0.0, e.position.dy);
+    if (_checkPeerControlProtected(x, y)) {
+      return;
+    }
+    final pos = handlePointerDevicePos(kPointerEventKindStylus, x, y, true, type);
+    if (pos == null) {
+      return;
+    }
+    final pressureRange = e.pressureMax - e.pressureMin;
+    final pressure = pressureRange > 0
+        ? (e.pressure - e.pressureMin) / pressureRange
+        : (type == 'down' || type == 'move' ? 1.0 : 0.0);
+    // [tilt] is the angle from the perpendicular, [orientation] is its direction.
+    final tilt = e.tilt * 180 / pi;
+    final evtValue = {
+      'x': pos.x,
+      'y': pos.y,
+      'pressure': pressure,
+      'tilt_x': (tilt * sin(e.orientation)).round(),
+      'tilt_y': (-tilt * cos(e.orientation)).round(),
+      'eraser': e.kind == ui.PointerDeviceKind.invertedStylus,
+      'button': (e.buttons & kPrimaryStylusButton) != 0,
+      'button2': (e.buttons & kSecondaryStylusButton) != 0,
+    };
+    final evt = PointerEventToRust(kPointerEventKindStylus, type, evtValue).toJson();
+    bind.sessionSendPointer(
+        sessionId: sessionId, msg: json.encode(modify(evt)));
+  }
+
   bool _checkPeerControlProtected(double x, double y) {
     final cursorModel = parent.target!.cursorModel;
     if (cursorModel.isPeerControlProtected) {
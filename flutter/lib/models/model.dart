@@ -102,6 +102,9 @@ class FfiModel with ChangeNotifier {
   RxBool waitForImageDialogShow = true.obs;
   Timer? waitForImageTimer;
   RxBool waitForFirstImage = true.obs;
+  // Sound inputs of the remote side, and the one captured in this session.
+  final audioInputs = <String>[].obs;
+  final audioInput = ''.obs;
 
   Map<String, bool> get permissions => _permissions;
 
@@ -152,6 +155,15 @@ class FfiModel with ChangeNotifier {
 
   bool get keyboard => _permissions['keyboard'] != false;
 
+  updateAudioInputs(Map<String, dynamic> evt) {
+    try {
+      audioInputs.value = List<String>.from(jsonDecode(evt['inputs']));
+    } catch (e) {
+      debugPrint('Failed to decode audio inputs: $e');
+    }
+    audioInput.value = evt['current'];
+  }
+
   clear() {
     _pi = PeerInfo();
     _display = Display();
@@ -280,6 +292,8 @@ class FfiModel with ChangeNotifier {
         closeConnection(id: peer_id);
       } else if (name == 'portable_service_running') {
         parent.target?.elevationModel.onPortableServiceRunning(evt);
+      } else if (name == 'audio_inputs') {
+        updateAudioInputs(evt);
       } else if (name == 'on_url_scheme_received') {
         // currently comes from "_url" ipc of mac and dbus of linux
         onUrlSchemeReceived(evt);
@@ -410,6 +424,8 @@ class FfiModel with ChangeNotifier {
       wrongPasswordDialog(sessionId, dialogManager, type, title, text);
     } else if (type == 'input-password') {
       enterPasswordDialog(sessionId, dialogManager);
+    } else if (type == 'input-2fa') {
+      enter2faDialog(sessionId, dialogManager, title);
     } else if (type == 'session-login' || type == 'session-re-login') {
       enterUserLoginDialog(sessionId, dialogManager);
     } else if (type == 'session-login-password' ||
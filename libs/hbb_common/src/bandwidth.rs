@@ -0,0 +1,165 @@
+//! Hard bandwidth caps, with token buckets in the send and receive paths of `tcp::FramedStream`.
+//!
+//! The limits are in kbit/s, empty or 0 for none. `upload-limit` and `download-limit` in the
+//! options cap all the connections of the process together, `connection-upload-limit` and
+//! `connection-download-limit` cap each one. A session to a peer may have its own
+//! `upload-limit` and `download-limit` in the options of its `PeerConfig`.
+//!
+//! Whatever is sent, video, audio, file blocks or port-forward data, takes from the same buckets
+//! in the order it is sent. A send which overdraws a bucket waits until it is refilled, so the
+//! delay seen by `VideoQoS` grows and it lowers the quality. A read which overdraws one delays the
+//! next read, and TCP holds the peer back.
+
+use crate::config::{Config, PeerConfig};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// The burst allowed after idling.
+const BURST: Duration = Duration::from_millis(200);
+
+lazy_static::lazy_static! {
+    static ref UPLOAD: TokenBucket = Default::default();
+    static ref DOWNLOAD: TokenBucket = Default::default();
+}
+
+#[inline]
+fn get_limit(value: &str) -> u64 {
+    value.trim().parse().unwrap_or(0)
+}
+
+#[derive(Debug, Default)]
+pub struct TokenBucket {
+    // Bytes per second, 0 for no limit.
+    rate: AtomicU64,
+    // The tokens, negative if overdrawn, and when they were counted.
+    state: Mutex<Option<(f64, Instant)>>,
+}
+
+impl TokenBucket {
+    pub fn new(kbps: u64) -> Self {
+        let bucket = Self::default();
+        bucket.set_limit(kbps);
+        bucket
+    }
+
+    pub fn set_limit(&self, kbps: u64) {
+        self.rate.store(kbps * 1000 / 8, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Take `n` bytes right away, and return how long to wait before they may pass.
+    pub fn take(&self, n: usize) -> Duration {
+        let rate = self.rate();
+        if rate == 0 {
+            return Duration::ZERO;
+        }
+        let rate = rate as f64;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = state.get_or_insert((0., now));
+        let burst = rate * BURST.as_secs_f64();
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(burst);
+        *last = now;
+        *tokens -= n as f64;
+        if *tokens >= 0. {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / rate)
+        }
+    }
+}
+
+/// The buckets a stream takes from, its own and the global ones.
+#[derive(Debug)]
+pub struct Shaper {
+    upload: TokenBucket,
+    download: TokenBucket,
+    // Until when the next message is not read.
+    download_ready: Mutex<Option<Instant>>,
+}
+
+impl Shaper {
+    fn new(upload_kbps: u64, download_kbps: u64) -> Self {
+        UPLOAD.set_limit(get_limit(&Config::get_option("upload-limit")));
+        DOWNLOAD.set_limit(get_limit(&Config::get_option("download-limit")));
+        Self {
+            upload: TokenBucket::new(upload_kbps),
+            download: TokenBucket::new(download_kbps),
+            download_ready: Default::default(),
+        }
+    }
+
+    /// For an incoming connection.
+    pub fn for_connection() -> Self {
+        Self::new(
+            get_limit(&Config::get_option("connection-upload-limit")),
+            get_limit(&Config::get_option("connection-download-limit")),
+        )
+    }
+
+    /// For a session to the peer `id`.
+    pub fn for_peer(id: &str) -> Self {
+        let options = PeerConfig::load(id).options;
+        let limit = |key: &str| match options.get(key).map(|x| get_limit(x)) {
+            Some(limit) if limit > 0 => limit,
+            _ => get_limit(&Config::get_option(&format!("connection-{key}"))),
+        };
+        Self::new(limit("upload-limit"), limit("download-limit"))
+    }
+
+    pub async fn upload(&self, n: usize) {
+        let wait = self.upload.take(n).max(UPLOAD.take(n));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Wait for the bytes read last, before the next read, so nothing read is lost if the wait
+    /// is cancelled.
+    pub async fn wait_download(&self) {
+        let ready = *self.download_ready.lock().unwrap();
+        if let Some(ready) = ready {
+            tokio::time::sleep_until(ready.into()).await;
+        }
+    }
+
+    pub fn downloaded(&self, n: usize) {
+        let wait = self.download.take(n).max(DOWNLOAD.take(n));
+        if !wait.is_zero() {
+            *self.download_ready.lock().unwrap() = Some(Instant::now() + wait);
+        }
+    }
+
+    /// The upload rate in bytes per second, 0 for no limit.
+    pub fn upload_rate(&self) -> u64 {
+        match (self.upload.rate(), UPLOAD.rate()) {
+            (0, rate) | (rate, 0) => rate,
+            (a, b) => a.min(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let bucket = TokenBucket::new(8);
+        // 1000 bytes per second, nothing saved at first.
+        let wait = bucket.take(500);
+        assert!(wait > Duration::from_millis(490) && wait <= Duration::from_millis(500));
+        let wait = bucket.take(500);
+        assert!(wait > Duration::from_millis(990) && wait <= Duration::from_millis(1000));
+        assert_eq!(TokenBucket::new(0).take(1 << 20), Duration::ZERO);
+    }
+}
@@ -20,11 +20,8 @@ use sodiumoxide::crypto::sign;
 
 use crate::{
     compress::{compress, decompress},
-    log,
-    password_security::{
-        decrypt_str_or_original, decrypt_vec_or_original, encrypt_str_or_original,
-        encrypt_vec_or_original, symmetric_crypt,
-    },
+    keyring, log,
+    password_security::{decrypt_str_or_original, encrypt_str_or_original, symmetric_crypt},
 };
 
 pub const RENDEZVOUS_TIMEOUT: u64 = 12_000;
@@ -156,6 +153,7 @@ macro_rules! serde_field_bool {
 pub enum NetworkType {
     Direct,
     ProxySocks,
+    ProxyHttp,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
@@ -178,6 +176,50 @@ pub struct Config {
     key_confirmed: bool,
     #[serde(default, deserialize_with = "deserialize_hashmap_string_bool")]
     keys_confirmed: HashMap<String, bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "String::is_empty",
+        deserialize_with = "deserialize_string"
+    )]
+    totp_secret: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_vec_trusteddevice"
+    )]
+    trusted_devices: Vec<TrustedDevice>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_vec_authorizedkey"
+    )]
+    authorized_keys: Vec<AuthorizedKey>,
+}
+
+// A client public key allowed to log in without the password.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct AuthorizedKey {
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub name: String,
+    // The ID of the client holding the key, empty if not bound to an ID.
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub id: String,
+    // Base64 of the sign public key.
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub pk: String,
+}
+
+// A client that passed two-factor authentication and may skip it until `expiry`.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct TrustedDevice {
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub id: String,
+    // Hash of the token handed to the client, the token itself is never stored.
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub token_hash: String,
+    // Milliseconds since epoch.
+    #[serde(default, deserialize_with = "deserialize_i64")]
+    pub expiry: i64,
 }
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
@@ -198,6 +240,10 @@ pub struct Config2 {
     #[serde(default, deserialize_with = "deserialize_i32")]
     nat_type: i32,
     #[serde(default, deserialize_with = "deserialize_i32")]
+    nat_class: i32,
+    #[serde(default, deserialize_with = "deserialize_i32")]
+    nat_port_delta: i32,
+    #[serde(default, deserialize_with = "deserialize_i32")]
     serial: i32,
 
     #[serde(default)]
@@ -284,6 +330,13 @@ pub struct PeerConfig {
         skip_serializing_if = "String::is_empty"
     )]
     pub reverse_mouse_wheel: String,
+    // Fingerprint of the host key, pinned on the first direct connection to this address.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub pinned_fingerprint: String,
 
     #[serde(
         default,
@@ -328,6 +381,7 @@ impl Default for PeerConfig {
             keyboard_mode: Default::default(),
             view_only: Default::default(),
             reverse_mouse_wheel: Self::default_reverse_mouse_wheel(),
+            pinned_fingerprint: Default::default(),
             custom_resolutions: Default::default(),
             options: Self::default_options(),
             ui_flutter: Default::default(),
@@ -360,6 +414,22 @@ pub fn get_online_state() -> i64 {
     *ONLINE.lock().unwrap().values().max().unwrap_or(&0)
 }
 
+/// Parse a comma separated list of rendezvous servers, each optionally with a weight for the
+/// selection, e.g. `a.com*3,b.com:21116`.
+pub fn parse_rendezvous_servers(s: &str) -> Vec<(String, u32)> {
+    s.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| match x.rsplit_once('*') {
+            Some((host, weight)) => (
+                host.trim().to_owned(),
+                weight.trim().parse::<u32>().unwrap_or(1).max(1),
+            ),
+            None => (x.to_owned(), 1),
+        })
+        .collect()
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 fn patch(path: PathBuf) -> PathBuf {
     if let Some(_tmp) = path.to_str() {
@@ -404,8 +474,8 @@ impl Config2 {
     fn load() -> Config2 {
         let mut config = Config::load_::<Config2>("2");
         if let Some(mut socks) = config.socks {
-            let (password, _, store) =
-                decrypt_str_or_original(&socks.password, PASSWORD_ENC_VERSION);
+            let (password, store) =
+                keyring::load_str("socks-password", &socks.password, PASSWORD_ENC_VERSION);
             socks.password = password;
             config.socks = Some(socks);
             if store {
@@ -422,8 +492,12 @@ impl Config2 {
     fn store(&self) {
         let mut config = self.clone();
         if let Some(mut socks) = config.socks {
-            socks.password =
-                encrypt_str_or_original(&socks.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
+            socks.password = keyring::store_str(
+                "socks-password",
+                &socks.password,
+                PASSWORD_ENC_VERSION,
+                ENCRYPT_MAX_LEN,
+            );
             config.socks = Some(socks);
         }
         Config::store_(&config, "2");
@@ -490,9 +564,17 @@ impl Config {
     fn load() -> Config {
         let mut config = Config::load_::<Config>("");
         let mut store = false;
-        let (password, _, store1) = decrypt_str_or_original(&config.password, PASSWORD_ENC_VERSION);
+        let (password, store1) =
+            keyring::load_str("password", &config.password, PASSWORD_ENC_VERSION);
         config.password = password;
         store |= store1;
+        let (totp_secret, store3) =
+            keyring::load_str("totp-secret", &config.totp_secret, PASSWORD_ENC_VERSION);
+        config.totp_secret = totp_secret;
+        store |= store3;
+        let (sk, store4) = keyring::load_raw("key-pair", &config.key_pair.0);
+        config.key_pair.0 = sk;
+        store |= store4;
         let mut id_valid = false;
         let (id, encrypted, store2) = decrypt_str_or_original(&config.enc_id, PASSWORD_ENC_VERSION);
         if encrypted {
@@ -532,10 +614,21 @@ impl Config {
 
     fn store(&self) {
         let mut config = self.clone();
-        config.password =
-            encrypt_str_or_original(&config.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
+        config.password = keyring::store_str(
+            "password",
+            &config.password,
+            PASSWORD_ENC_VERSION,
+            ENCRYPT_MAX_LEN,
+        );
         config.enc_id = encrypt_str_or_original(&config.id, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
         config.id = "".to_owned();
+        config.totp_secret = keyring::store_str(
+            "totp-secret",
+            &config.totp_secret,
+            PASSWORD_ENC_VERSION,
+            ENCRYPT_MAX_LEN,
+        );
+        config.key_pair.0 = keyring::store_raw("key-pair", &config.key_pair.0);
         Config::store_(&config, "");
     }
 
@@ -663,7 +756,7 @@ impl Config {
     pub fn get_rendezvous_server() -> String {
         let mut rendezvous_server = EXE_RENDEZVOUS_SERVER.read().unwrap().clone();
         if rendezvous_server.is_empty() {
-            rendezvous_server = Self::get_option("custom-rendezvous-server");
+            rendezvous_server = Self::get_custom_rendezvous_server();
         }
         if rendezvous_server.is_empty() {
             rendezvous_server = PROD_RENDEZVOUS_SERVER.read().unwrap().clone();
@@ -690,7 +783,10 @@ impl Config {
         }
         let s = Self::get_option("custom-rendezvous-server");
         if !s.is_empty() {
-            return vec![s];
+            return parse_rendezvous_servers(&s)
+                .drain(..)
+                .map(|(host, _)| host)
+                .collect();
         }
         let s = PROD_RENDEZVOUS_SERVER.read().unwrap().clone();
         if !s.is_empty() {
@@ -710,6 +806,34 @@ impl Config {
         return RENDEZVOUS_SERVERS.iter().map(|x| x.to_string()).collect();
     }
 
+    // The fastest of the custom servers, see `update_latency`, or the first.
+    fn get_custom_rendezvous_server() -> String {
+        let servers = parse_rendezvous_servers(&Self::get_option("custom-rendezvous-server"));
+        let fastest = CONFIG2.read().unwrap().rendezvous_server.clone();
+        let same = |host: &str| {
+            crate::socket_client::check_port(host, RENDEZVOUS_PORT)
+                == crate::socket_client::check_port(&fastest, RENDEZVOUS_PORT)
+        };
+        if !fastest.is_empty() && servers.iter().any(|(host, _)| same(host)) {
+            return fastest;
+        }
+        servers
+            .into_iter()
+            .next()
+            .map(|(host, _)| host)
+            .unwrap_or_default()
+    }
+
+    /// The weight of a custom rendezvous server, 1 by default.
+    pub fn get_rendezvous_server_weight(host: &str) -> u32 {
+        let host = crate::socket_client::check_port(host, RENDEZVOUS_PORT);
+        parse_rendezvous_servers(&Self::get_option("custom-rendezvous-server"))
+            .into_iter()
+            .find(|(x, _)| crate::socket_client::check_port(x, RENDEZVOUS_PORT) == host)
+            .map(|(_, weight)| weight)
+            .unwrap_or(1)
+    }
+
     pub fn reset_online() {
         *ONLINE.lock().unwrap() = Default::default();
     }
@@ -719,8 +843,13 @@ impl Config {
         let mut host = "".to_owned();
         let mut delay = i64::MAX;
         for (tmp_host, tmp_delay) in ONLINE.lock().unwrap().iter() {
-            if tmp_delay > &0 && tmp_delay < &delay {
-                delay = *tmp_delay;
+            if *tmp_delay <= 0 {
+                continue;
+            }
+            // A server of weight n is preferred unless it is n times slower.
+            let tmp_delay = tmp_delay / Self::get_rendezvous_server_weight(tmp_host) as i64;
+            if tmp_delay < delay {
+                delay = tmp_delay;
                 host = tmp_host.to_string();
             }
         }
@@ -757,6 +886,22 @@ impl Config {
         CONFIG2.read().unwrap().nat_type
     }
 
+    pub fn set_nat_info(class: i32, port_delta: i32) {
+        let mut config = CONFIG2.write().unwrap();
+        if (class, port_delta) == (config.nat_class, config.nat_port_delta) {
+            return;
+        }
+        config.nat_class = class;
+        config.nat_port_delta = port_delta;
+        config.store();
+    }
+
+    /// The `NatInfo` class and port delta.
+    pub fn get_nat_info() -> (i32, i32) {
+        let config = CONFIG2.read().unwrap();
+        (config.nat_class, config.nat_port_delta)
+    }
+
     pub fn set_serial(serial: i32) {
         let mut config = CONFIG2.write().unwrap();
         if serial == config.serial {
@@ -839,10 +984,17 @@ impl Config {
             return p.clone();
         }
         let mut config = Config::load_::<Config>("");
+        config.key_pair.0 = keyring::load_raw("key-pair", &config.key_pair.0).0;
         if config.key_pair.0.is_empty() {
             let (pk, sk) = sign::gen_keypair();
             let key_pair = (sk.0.to_vec(), pk.0.into());
             config.key_pair = key_pair.clone();
+            if keyring::is_unreadable("key-pair") {
+                // Do not replace the key in the keyring, it may be unlocked next time.
+                log::error!("Using a temporary key pair");
+                *lock = Some(key_pair.clone());
+                return key_pair;
+            }
             std::thread::spawn(|| {
                 let mut config = CONFIG.write().unwrap();
                 config.key_pair = key_pair;
@@ -929,6 +1081,47 @@ impl Config {
         CONFIG.read().unwrap().password.clone()
     }
 
+    pub fn set_totp_secret(secret: &str) {
+        let mut config = CONFIG.write().unwrap();
+        if secret == config.totp_secret {
+            return;
+        }
+        config.totp_secret = secret.into();
+        // A new secret invalidates every device trusted with the old one.
+        config.trusted_devices.clear();
+        config.store();
+    }
+
+    pub fn get_totp_secret() -> String {
+        CONFIG.read().unwrap().totp_secret.clone()
+    }
+
+    pub fn set_trusted_devices(devices: Vec<TrustedDevice>) {
+        let mut config = CONFIG.write().unwrap();
+        if devices == config.trusted_devices {
+            return;
+        }
+        config.trusted_devices = devices;
+        config.store();
+    }
+
+    pub fn get_trusted_devices() -> Vec<TrustedDevice> {
+        CONFIG.read().unwrap().trusted_devices.clone()
+    }
+
+    pub fn set_authorized_keys(keys: Vec<AuthorizedKey>) {
+        let mut config = CONFIG.write().unwrap();
+        if keys == config.authorized_keys {
+            return;
+        }
+        config.authorized_keys = keys;
+        config.store();
+    }
+
+    pub fn get_authorized_keys() -> Vec<AuthorizedKey> {
+        CONFIG.read().unwrap().authorized_keys.clone()
+    }
+
     pub fn set_salt(salt: &str) {
         let mut config = CONFIG.write().unwrap();
         if salt == config.salt {
@@ -963,6 +1156,7 @@ impl Config {
     pub fn get_network_type() -> NetworkType {
         match &CONFIG2.read().unwrap().socks {
             None => NetworkType::Direct,
+            Some(s) if crate::proxy::is_http(&s.proxy) => NetworkType::ProxyHttp,
             Some(_) => NetworkType::ProxySocks,
         }
     }
@@ -1001,11 +1195,19 @@ impl PeerConfig {
             Ok(config) => {
                 let mut config: PeerConfig = config;
                 let mut store = false;
-                let (password, _, store2) =
-                    decrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION);
+                let (password, store2) = keyring::load_vec(
+                    &Self::keyring_account(id),
+                    &config.password,
+                    PASSWORD_ENC_VERSION,
+                );
                 config.password = password;
                 store = store || store2;
-                for opt in ["rdp_password", "os-username", "os-password"] {
+                for opt in [
+                    "rdp_password",
+                    "os-username",
+                    "os-password",
+                    "trusted-device-token",
+                ] {
                     if let Some(v) = config.options.get_mut(opt) {
                         let (encrypted, _, store2) =
                             decrypt_str_or_original(v, PASSWORD_ENC_VERSION);
@@ -1033,9 +1235,18 @@ impl PeerConfig {
     pub fn store(&self, id: &str) {
         let _lock = CONFIG.read().unwrap();
         let mut config = self.clone();
-        config.password =
-            encrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
-        for opt in ["rdp_password", "os-username", "os-password"] {
+        config.password = keyring::store_vec(
+            &Self::keyring_account(id),
+            &config.password,
+            PASSWORD_ENC_VERSION,
+            ENCRYPT_MAX_LEN,
+        );
+        for opt in [
+            "rdp_password",
+            "os-username",
+            "os-password",
+            "trusted-device-token",
+        ] {
             if let Some(v) = config.options.get_mut(opt) {
                 *v = encrypt_str_or_original(v, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN)
             }
@@ -1048,6 +1259,12 @@ impl PeerConfig {
 
     pub fn remove(id: &str) {
         fs::remove_file(Self::path(id)).ok();
+        keyring::delete(&Self::keyring_account(id));
+    }
+
+    #[inline]
+    fn keyring_account(id: &str) -> String {
+        format!("peer-password-{}", id)
     }
 
     fn path(id: &str) -> PathBuf {
@@ -1390,6 +1607,9 @@ pub struct DiscoveryPeer {
     pub online: bool,
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     pub ip_mac: HashMap<String, String>,
+    // The direct access port advertised with mDNS, 0 if unknown or disabled.
+    #[serde(default, deserialize_with = "deserialize_i32")]
+    pub direct_port: i32,
 }
 
 impl DiscoveryPeer {
@@ -1753,6 +1973,7 @@ impl Group {
 deserialize_default!(deserialize_string, String);
 deserialize_default!(deserialize_bool, bool);
 deserialize_default!(deserialize_i32, i32);
+deserialize_default!(deserialize_i64, i64);
 deserialize_default!(deserialize_vec_u8, Vec<u8>);
 deserialize_default!(deserialize_vec_string, Vec<String>);
 deserialize_default!(deserialize_vec_i32_string_i32, Vec<(i32, String, i32)>);
@@ -1760,6 +1981,8 @@ deserialize_default!(deserialize_vec_discoverypeer, Vec<DiscoveryPeer>);
 deserialize_default!(deserialize_vec_abpeer, Vec<AbPeer>);
 deserialize_default!(deserialize_vec_groupuser, Vec<GroupUser>);
 deserialize_default!(deserialize_vec_grouppeer, Vec<GroupPeer>);
+deserialize_default!(deserialize_vec_trusteddevice, Vec<TrustedDevice>);
+deserialize_default!(deserialize_vec_authorizedkey, Vec<AuthorizedKey>);
 deserialize_default!(deserialize_keypair, KeyPair);
 deserialize_default!(deserialize_size, Size);
 deserialize_default!(deserialize_hashmap_string_string, HashMap<String, String>);
@@ -1770,6 +1993,19 @@ deserialize_default!(deserialize_hashmap_resolutions, HashMap<String, Resolution
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_rendezvous_servers() {
+        assert_eq!(
+            parse_rendezvous_servers(" a.com*3, b.com:21116 ,,c.com*0"),
+            vec![
+                ("a.com".to_owned(), 3),
+                ("b.com:21116".to_owned(), 1),
+                ("c.com".to_owned(), 1)
+            ]
+        );
+        assert_eq!(parse_rendezvous_servers(""), vec![]);
+    }
+
     #[test]
     fn test_serialize() {
         let cfg: Config = Default::default();
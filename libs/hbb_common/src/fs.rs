@@ -1,6 +1,6 @@
 #[cfg(windows)]
 use std::os::windows::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde_derive::{Deserialize, Serialize};
@@ -117,6 +117,61 @@ pub fn get_path(path: &str) -> PathBuf {
     Path::new(path).to_path_buf()
 }
 
+/// Resolve the longest existing part of an absolute `path` without `..`, the rest may not exist
+/// yet.
+pub fn resolve_path(path: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(mut p) = std::fs::canonicalize(existing) {
+            for c in rest.iter().rev() {
+                p.push(c);
+            }
+            return Some(p);
+        }
+        rest.push(existing.file_name()?);
+        existing = existing.parent()?;
+    }
+}
+
+// The path of an opened file, symbolic links resolved.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn opened_path(file: &File) -> Option<PathBuf> {
+    use std::os::unix::io::AsRawFd;
+    std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).ok()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn opened_path(file: &File) -> Option<PathBuf> {
+    use std::os::unix::{ffi::OsStrExt, io::AsRawFd};
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETPATH, buf.as_mut_ptr()) } == -1 {
+        return None;
+    }
+    let len = buf.iter().position(|&c| c == 0)?;
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(&buf[..len])))
+}
+
+#[cfg(windows)]
+fn opened_path(file: &File) -> Option<PathBuf> {
+    let mut buf = vec![0u16; 32768];
+    let len = unsafe {
+        winapi::um::fileapi::GetFinalPathNameByHandleW(
+            file.as_raw_handle() as _,
+            buf.as_mut_ptr(),
+            buf.len() as _,
+            0,
+        )
+    } as usize;
+    if len == 0 || len >= buf.len() {
+        return None;
+    }
+    Some(PathBuf::from(std::ffi::OsString::from_wide(&buf[..len])))
+}
+
 #[inline]
 pub fn get_home_as_string() -> String {
     get_string(&Config::get_home())
@@ -220,6 +275,9 @@ pub struct TransferJob {
     file_skipped: bool,
     file_is_waiting: bool,
     default_overwrite_strategy: Option<bool>,
+    // directories the opened files must be in, server only
+    #[serde(skip_serializing)]
+    roots: Option<Vec<PathBuf>>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -329,6 +387,31 @@ impl TransferJob {
         &self.files
     }
 
+    #[inline]
+    pub fn set_roots(&mut self, roots: Option<Vec<PathBuf>>) {
+        self.roots = roots;
+    }
+
+    // Checked before anything is created for `path`.
+    fn check_path(&self, path: &Path) -> ResultType<()> {
+        self.check_in_roots(resolve_path(path))
+    }
+
+    // The path was checked before the file is opened, check where the opened file really is.
+    fn check_opened(&self, file: &File) -> ResultType<()> {
+        self.check_in_roots(opened_path(file))
+    }
+
+    fn check_in_roots(&self, path: Option<PathBuf>) -> ResultType<()> {
+        let Some(roots) = self.roots.as_ref() else {
+            return Ok(());
+        };
+        match path {
+            Some(path) if roots.iter().any(|r| path.starts_with(r)) => Ok(()),
+            _ => bail!("Access to this path is not allowed"),
+        }
+    }
+
     #[inline]
     pub fn set_files(&mut self, files: Vec<FileEntry>) {
         self.files = files;
@@ -400,11 +483,20 @@ impl TransferJob {
             self.file_num = block.file_num;
             let entry = &self.files[file_num];
             let path = self.join(&entry.name);
+            self.check_path(&path)?;
             if let Some(p) = path.parent() {
                 std::fs::create_dir_all(p).ok();
             }
             let path = format!("{}.download", get_string(&path));
-            self.file = Some(File::create(&path).await?);
+            // Truncate only after the check, not to clobber a file outside the roots.
+            let file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .open(&path)
+                .await?;
+            self.check_opened(&file)?;
+            file.set_len(0).await?;
+            self.file = Some(file);
         }
         if block.compressed {
             let tmp = decompress(&block.data);
@@ -443,7 +535,11 @@ impl TransferJob {
         }
         let name = &self.files[file_num].name;
         if self.file.is_none() {
-            match File::open(self.join(name)).await {
+            let opened = match File::open(self.join(name)).await {
+                Ok(file) => self.check_opened(&file).map(|_| file),
+                Err(err) => Err(err.into()),
+            };
+            match opened {
                 Ok(file) => {
                     self.file = Some(file);
                     self.file_confirmed = false;
@@ -453,7 +549,7 @@ impl TransferJob {
                     self.file_num += 1;
                     self.file_confirmed = false;
                     self.file_is_waiting = false;
-                    return Err(err.into());
+                    return Err(err);
                 }
             }
         }
@@ -465,7 +561,13 @@ impl TransferJob {
             return Ok(None);
         }
         const BUF_SIZE: usize = 128 * 1024;
-        let mut buf: Vec<u8> = vec![0; BUF_SIZE];
+        const MIN_BUF_SIZE: usize = 4 * 1024;
+        // Under a bandwidth cap, a block takes about 100ms, not to hold the other messages back.
+        let buf_size = match stream.shaper().map(|shaper| shaper.upload_rate()) {
+            Some(rate) if rate > 0 => (rate as usize / 10).clamp(MIN_BUF_SIZE, BUF_SIZE),
+            _ => BUF_SIZE,
+        };
+        let mut buf: Vec<u8> = vec![0; buf_size];
         let mut compressed = false;
         let mut offset: usize = 0;
         loop {
@@ -485,7 +587,7 @@ impl TransferJob {
                 }
                 Ok(n) => {
                     offset += n;
-                    if n == 0 || offset == BUF_SIZE {
+                    if n == 0 || offset == buf_size {
                         break;
                     }
                 }
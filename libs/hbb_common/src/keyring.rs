@@ -0,0 +1,250 @@
+//! Secrets kept in the OS keyring instead of the config files.
+//!
+//! Only the Secret Service on Linux is supported, with the `keyring` feature. The config files
+//! then hold `PLACEHOLDER` in place of the secret, and values found in the files are moved into
+//! the keyring the next time they are loaded. If the keyring is not available, e.g. for the
+//! service running outside of a user session, secrets stay in the files, obfuscated as before.
+
+use crate::{
+    log,
+    password_security::{
+        decrypt_str_or_original, decrypt_vec_or_original, encrypt_str_or_original,
+        encrypt_vec_or_original,
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+pub const PLACEHOLDER: &str = "#keyring";
+
+lazy_static::lazy_static! {
+    // Accounts whose secret could not be read, their placeholder must not be overwritten.
+    static ref UNREADABLE: Mutex<HashSet<String>> = Default::default();
+    // The secrets known to be in the keyring, to skip writing them again with every store.
+    // Empty if known to be deleted.
+    static ref CACHE: Mutex<HashMap<String, Vec<u8>>> = Default::default();
+}
+
+#[cfg(all(target_os = "linux", feature = "keyring"))]
+mod backend {
+    use crate::{config::APP_NAME, log};
+    use secret_service::{
+        blocking::{Collection, SecretService},
+        EncryptionType,
+    };
+    use std::collections::HashMap;
+
+    lazy_static::lazy_static! {
+        static ref AVAILABLE: bool = SecretService::connect(EncryptionType::Dh)
+            .map_err(|e| log::info!("Secret Service not available: {}", e))
+            .is_ok();
+    }
+
+    pub fn is_available() -> bool {
+        *AVAILABLE
+    }
+
+    fn with_collection<T>(
+        f: impl FnOnce(&Collection, HashMap<&str, &str>) -> Option<T>,
+        account: &str,
+    ) -> Option<T> {
+        if !is_available() {
+            return None;
+        }
+        let ss = SecretService::connect(EncryptionType::Dh).ok()?;
+        let collection = match ss.get_default_collection() {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("Failed to open the default keyring: {}", e);
+                return None;
+            }
+        };
+        if collection.is_locked().unwrap_or(true) {
+            if let Err(e) = collection.unlock() {
+                log::error!("Failed to unlock the keyring: {}", e);
+                return None;
+            }
+        }
+        let app_name = APP_NAME.read().unwrap().clone();
+        let attributes = HashMap::from([("service", app_name.as_str()), ("account", account)]);
+        f(&collection, attributes)
+    }
+
+    pub fn get(account: &str) -> Option<Vec<u8>> {
+        with_collection(
+            |collection, attributes| {
+                let items = collection.search_items(attributes).ok()?;
+                items.first()?.get_secret().ok()
+            },
+            account,
+        )
+    }
+
+    pub fn set(account: &str, secret: &[u8]) -> bool {
+        with_collection(
+            |collection, attributes| {
+                let label = format!("{} {}", APP_NAME.read().unwrap(), account);
+                collection
+                    .create_item(&label, attributes, secret, true, "application/octet-stream")
+                    .map_err(|e| log::error!("Failed to store {} in the keyring: {}", account, e))
+                    .ok()
+            },
+            account,
+        )
+        .is_some()
+    }
+
+    pub fn delete(account: &str) {
+        with_collection(
+            |collection, attributes| {
+                for item in collection.search_items(attributes).ok()? {
+                    item.delete().ok();
+                }
+                Some(())
+            },
+            account,
+        );
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "keyring")))]
+mod backend {
+    pub fn is_available() -> bool {
+        false
+    }
+
+    pub fn get(_account: &str) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub fn set(_account: &str, _secret: &[u8]) -> bool {
+        false
+    }
+
+    pub fn delete(_account: &str) {}
+}
+
+pub use backend::{delete, is_available};
+
+fn load_vec_(account: &str, stored: &[u8]) -> Option<(Vec<u8>, bool)> {
+    if stored != PLACEHOLDER.as_bytes() {
+        return None;
+    }
+    match backend::get(account) {
+        Some(v) => {
+            UNREADABLE.lock().unwrap().remove(account);
+            CACHE.lock().unwrap().insert(account.to_owned(), v.clone());
+            Some((v, false))
+        }
+        None => {
+            log::error!("Failed to read {} from the keyring", account);
+            UNREADABLE.lock().unwrap().insert(account.to_owned());
+            Some((vec![], false))
+        }
+    }
+}
+
+// The placeholder to write into the file, `None` to keep the secret in the file.
+fn store_vec_(account: &str, secret: &[u8]) -> Option<Vec<u8>> {
+    if secret.is_empty() {
+        if is_unreadable(account) {
+            return Some(PLACEHOLDER.as_bytes().to_vec());
+        }
+        // An empty value is cached once the secret is deleted.
+        if CACHE.lock().unwrap().get(account).map(|v| v.is_empty()) != Some(true) && is_available()
+        {
+            backend::delete(account);
+            CACHE.lock().unwrap().insert(account.to_owned(), vec![]);
+        }
+        return None;
+    }
+    if CACHE.lock().unwrap().get(account).map(|v| &v[..]) == Some(secret) {
+        return Some(PLACEHOLDER.as_bytes().to_vec());
+    }
+    if backend::set(account, secret) {
+        UNREADABLE.lock().unwrap().remove(account);
+        CACHE
+            .lock()
+            .unwrap()
+            .insert(account.to_owned(), secret.to_owned());
+        return Some(PLACEHOLDER.as_bytes().to_vec());
+    }
+    None
+}
+
+/// Load a secret stored under `account`, returns the secret and whether the file should be
+/// stored again to move the secret into the keyring.
+pub fn load_str(account: &str, stored: &str, version: &str) -> (String, bool) {
+    if let Some((v, store)) = load_vec_(account, stored.as_bytes()) {
+        return (String::from_utf8_lossy(&v).to_string(), store);
+    }
+    let (v, _, store) = decrypt_str_or_original(stored, version);
+    let migrate = !v.is_empty() && is_available();
+    (v, store || migrate)
+}
+
+/// The value to write into the file for `secret`.
+pub fn store_str(account: &str, secret: &str, version: &str, max_len: usize) -> String {
+    match store_vec_(account, secret.as_bytes()) {
+        Some(v) => String::from_utf8_lossy(&v).to_string(),
+        None => encrypt_str_or_original(secret, version, max_len),
+    }
+}
+
+pub fn load_vec(account: &str, stored: &[u8], version: &str) -> (Vec<u8>, bool) {
+    if let Some(v) = load_vec_(account, stored) {
+        return v;
+    }
+    let (v, _, store) = decrypt_vec_or_original(stored, version);
+    let migrate = !v.is_empty() && is_available();
+    (v, store || migrate)
+}
+
+pub fn store_vec(account: &str, secret: &[u8], version: &str, max_len: usize) -> Vec<u8> {
+    store_vec_(account, secret).unwrap_or_else(|| encrypt_vec_or_original(secret, version, max_len))
+}
+
+/// Like `load_vec`, for secrets which are not obfuscated in the file.
+pub fn load_raw(account: &str, stored: &[u8]) -> (Vec<u8>, bool) {
+    load_vec_(account, stored)
+        .unwrap_or_else(|| (stored.to_owned(), !stored.is_empty() && is_available()))
+}
+
+pub fn store_raw(account: &str, secret: &[u8]) -> Vec<u8> {
+    store_vec_(account, secret).unwrap_or_else(|| secret.to_owned())
+}
+
+/// The secret of `account` is in the keyring, but could not be read.
+pub fn is_unreadable(account: &str) -> bool {
+    UNREADABLE.lock().unwrap().contains(account)
+}
+
+#[cfg(all(test, target_os = "linux", feature = "keyring"))]
+mod tests {
+    use super::*;
+
+    // Needs a Secret Service on the session bus, run it with e.g.
+    // dbus-run-session -- sh -c 'echo | gnome-keyring-daemon --unlock &&
+    //     cargo test -p hbb_common --features keyring -- --ignored test_migrate'
+    #[test]
+    #[ignore]
+    fn test_migrate() {
+        assert!(is_available(), "No Secret Service on the session bus");
+        let account = "test-account";
+        let stored = encrypt_str_or_original("secret", "00", 128);
+        let (secret, store) = load_str(account, &stored, "00");
+        assert_eq!(secret, "secret");
+        assert!(store);
+        let stored = store_str(account, &secret, "00", 128);
+        assert_eq!(stored, PLACEHOLDER);
+        CACHE.lock().unwrap().clear();
+        assert_eq!(
+            load_str(account, &stored, "00"),
+            ("secret".to_owned(), false)
+        );
+        assert_eq!(store_str(account, "", "00", 128), "");
+        assert_eq!(backend::get(account), None);
+    }
+}
@@ -16,17 +16,21 @@ use std::{
 };
 pub use tokio;
 pub use tokio_util;
+pub mod proxy;
 pub mod socket_client;
 pub mod tcp;
 pub mod udp;
+pub mod websocket;
 pub use env_logger;
 pub use log;
+pub mod bandwidth;
 pub mod bytes_codec;
 #[cfg(feature = "quic")]
 pub mod quic;
 pub use anyhow::{self, bail};
 pub use futures_util;
 pub mod config;
+pub mod keyring;
 pub mod fs;
 pub use lazy_static;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -50,9 +54,6 @@ pub use sysinfo;
 pub use toml;
 pub use uuid;
 
-#[cfg(feature = "quic")]
-pub type Stream = quic::Connection;
-#[cfg(not(feature = "quic"))]
 pub type Stream = tcp::FramedStream;
 pub type SessionID = uuid::Uuid;
 
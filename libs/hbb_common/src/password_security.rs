@@ -60,9 +60,19 @@ pub fn permanent_enabled() -> bool {
     verification_method() != VerificationMethod::OnlyUseTemporaryPassword
 }
 
+/// Whether the permanent password has expired.
+pub fn permanent_expired() -> bool {
+    let expiry: i64 = Config::get_option("permanent-password-expiry")
+        .parse()
+        .unwrap_or(0);
+    expiry > 0 && crate::get_time() / 1000 >= expiry
+}
+
 pub fn has_valid_password() -> bool {
     temporary_enabled() && !temporary_password().is_empty()
-        || permanent_enabled() && !Config::get_permanent_password().is_empty()
+        || permanent_enabled()
+            && !permanent_expired()
+            && !Config::get_permanent_password().is_empty()
 }
 
 pub fn approve_mode() -> ApproveMode {
@@ -0,0 +1,427 @@
+//! HTTP CONNECT proxies and proxy auto-config (PAC).
+//!
+//! The configured proxy (`Socks5Server`) is an HTTP proxy if it starts with `http://`, otherwise
+//! a SOCKS5 proxy. If the `proxy-pac-url` option is set, a PAC script, either a local file or
+//! fetched over plain HTTP, picks the proxy for each target instead, with the credentials of the
+//! configured proxy. PAC scripts are only evaluated with the `pac` feature.
+//!
+//! Without either, `HTTPS_PROXY` is used for the targets `NO_PROXY` does not match.
+//!
+//! UDP can not go through an HTTP proxy, so the rendezvous connection falls back to TCP when the
+//! rendezvous server is reached through one.
+
+use crate::{
+    bail,
+    config::{Config, Socks5Server},
+    log, timeout, ResultType,
+};
+use sodiumoxide::base64;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const HTTP_SCHEME: &str = "http://";
+const PAC_OPTION: &str = "proxy-pac-url";
+const PAC_REFRESH_INTERVAL: Duration = Duration::from_secs(600);
+const PAC_TIMEOUT: u64 = 3_000;
+const MAX_RESPONSE_HEADER: usize = 8192;
+
+lazy_static::lazy_static! {
+    // url, fetch time and the script, empty if it could not be fetched.
+    static ref PAC_SCRIPT: Mutex<Option<(String, Instant, String)>> = Default::default();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Proxy {
+    Socks5(Socks5Server),
+    Http(Socks5Server),
+}
+
+impl From<Socks5Server> for Proxy {
+    fn from(conf: Socks5Server) -> Self {
+        if is_http(&conf.proxy) {
+            Self::Http(conf)
+        } else {
+            Self::Socks5(conf)
+        }
+    }
+}
+
+#[inline]
+pub fn is_http(proxy: &str) -> bool {
+    proxy
+        .get(..HTTP_SCHEME.len())
+        .map_or(false, |s| s.eq_ignore_ascii_case(HTTP_SCHEME))
+}
+
+/// `host:port` of an HTTP proxy url, port 80 if missing.
+pub fn http_proxy_addr(proxy: &str) -> String {
+    let addr = if is_http(proxy) {
+        &proxy[HTTP_SCHEME.len()..]
+    } else {
+        proxy
+    };
+    let addr = addr.split('/').next().unwrap_or_default();
+    crate::socket_client::check_port(addr, 80)
+}
+
+/// The proxy to connect to `target` ("host:port") with, `None` to connect directly.
+pub async fn for_target(target: &str) -> Option<Proxy> {
+    let conf = Config::get_socks();
+    let pac_url = Config::get_option(PAC_OPTION);
+    if !pac_url.is_empty() {
+        if let Some(proxy) = from_pac(&pac_url, target, conf.as_ref()).await {
+            return proxy;
+        }
+    }
+    conf.map(Proxy::from).or_else(|| from_env(target))
+}
+
+fn from_env(target: &str) -> Option<Proxy> {
+    let var = |names: [&str; 2]| names.iter().find_map(|name| std::env::var(name).ok());
+    let url = var(["HTTPS_PROXY", "https_proxy"])?;
+    let no_proxy = var(["NO_PROXY", "no_proxy"]).unwrap_or_default();
+    parse_env_proxy(&url, &no_proxy, split_host(target))
+}
+
+// `url` is "[scheme://][user:password@]host[:port]", http if the scheme is missing.
+fn parse_env_proxy(url: &str, no_proxy: &str, host: &str) -> Option<Proxy> {
+    let url = url.trim();
+    if url.is_empty() {
+        return None;
+    }
+    let bypass = no_proxy
+        .split(',')
+        .map(|x| x.trim().trim_start_matches('.'))
+        .filter(|x| !x.is_empty())
+        .any(|x| x == "*" || host == x || host.ends_with(&format!(".{x}")));
+    if bypass {
+        return None;
+    }
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+    let (credentials, addr) = rest.rsplit_once('@').unwrap_or(("", rest));
+    let (username, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+    let addr = addr.trim_end_matches('/');
+    let conf = |proxy: String| Socks5Server {
+        proxy,
+        username: username.to_owned(),
+        password: password.to_owned(),
+    };
+    match scheme.to_lowercase().as_str() {
+        "http" => Some(Proxy::Http(conf(format!("{HTTP_SCHEME}{addr}")))),
+        "socks5" | "socks5h" => Some(Proxy::Socks5(conf(addr.to_owned()))),
+        _ => {
+            log::warn!("Unsupported proxy in HTTPS_PROXY: {}", scheme);
+            None
+        }
+    }
+}
+
+/// Open a tunnel to `target` ("host:port") through the HTTP proxy connected to by `stream`.
+pub async fn http_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: &str,
+    username: &str,
+    password: &str,
+) -> ResultType<()> {
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if !username.is_empty() {
+        let credentials = format!("{username}:{password}");
+        request += &format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::encode(credentials, base64::Variant::Original)
+        );
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes()).await?;
+    // Read byte by byte, anything after the header belongs to the tunnel.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > MAX_RESPONSE_HEADER {
+            bail!("Invalid response from the HTTP proxy");
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => bail!("HTTP proxy refused the connection: {}", status),
+    }
+}
+
+// `None` if the script can not decide, `Some(None)` to connect directly.
+async fn from_pac(
+    pac_url: &str,
+    target: &str,
+    conf: Option<&Socks5Server>,
+) -> Option<Option<Proxy>> {
+    let script = get_pac_script(pac_url).await;
+    if script.is_empty() {
+        return None;
+    }
+    let host = split_host(target).to_owned();
+    let url = format!("https://{target}/");
+    let result = tokio::task::spawn_blocking(move || pac::find_proxy(&script, &url, &host))
+        .await
+        .ok()?;
+    match result {
+        Ok(result) => parse_pac_result(&result, conf),
+        Err(err) => {
+            log::error!("Failed to evaluate the PAC script: {}", err);
+            None
+        }
+    }
+}
+
+fn split_host(target: &str) -> &str {
+    let host = match target.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => target,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+async fn get_pac_script(pac_url: &str) -> String {
+    if let Some((url, time, script)) = PAC_SCRIPT.lock().unwrap().as_ref() {
+        if url == pac_url && time.elapsed() < PAC_REFRESH_INTERVAL {
+            return script.clone();
+        }
+    }
+    let script = match fetch_pac_script(pac_url).await {
+        Ok(script) => script,
+        Err(err) => {
+            log::error!("Failed to fetch the PAC script {}: {}", pac_url, err);
+            "".to_owned()
+        }
+    };
+    *PAC_SCRIPT.lock().unwrap() = Some((pac_url.to_owned(), Instant::now(), script.clone()));
+    script
+}
+
+async fn fetch_pac_script(pac_url: &str) -> ResultType<String> {
+    if !is_http(pac_url) {
+        let path = pac_url.strip_prefix("file://").unwrap_or(pac_url);
+        return Ok(tokio::fs::read_to_string(path).await?);
+    }
+    let rest = &pac_url[HTTP_SCHEME.len()..];
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    // The PAC script is fetched directly, it decides about the proxy.
+    let mut stream = timeout(
+        PAC_TIMEOUT,
+        tokio::net::TcpStream::connect(crate::socket_client::check_port(host, 80)),
+    )
+    .await??;
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    timeout(PAC_TIMEOUT, stream.read_to_end(&mut response)).await??;
+    let response = String::from_utf8_lossy(&response);
+    let Some((header, body)) = response.split_once("\r\n\r\n") else {
+        bail!("Invalid HTTP response");
+    };
+    let status = header.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        bail!("{}", status);
+    }
+    Ok(body.to_owned())
+}
+
+// The first usable entry of a result like "PROXY a:8080; SOCKS5 b:1080; DIRECT".
+fn parse_pac_result(result: &str, conf: Option<&Socks5Server>) -> Option<Option<Proxy>> {
+    let (username, password) = conf
+        .map(|c| (c.username.clone(), c.password.clone()))
+        .unwrap_or_default();
+    for entry in result.split(';') {
+        let mut parts = entry.split_whitespace();
+        let kind = parts.next().unwrap_or_default().to_uppercase();
+        let addr = parts.next().unwrap_or_default();
+        let server = |proxy: String| Socks5Server {
+            proxy,
+            username: username.clone(),
+            password: password.clone(),
+        };
+        match kind.as_str() {
+            "DIRECT" => return Some(None),
+            "PROXY" | "HTTP" if !addr.is_empty() => {
+                return Some(Some(Proxy::Http(server(format!("{HTTP_SCHEME}{addr}")))))
+            }
+            "SOCKS" | "SOCKS5" if !addr.is_empty() => {
+                return Some(Some(Proxy::Socks5(server(addr.to_owned()))))
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(feature = "pac")]
+mod pac {
+    use crate::{bail, ResultType};
+    use boa_engine::{
+        native_function::{NativeFunction, NativeFunctionPointer},
+        Context, JsArgs, JsResult, JsString, JsValue, Source,
+    };
+    use std::net::{IpAddr, ToSocketAddrs};
+
+    // The helpers PAC scripts expect, those which need the network are native.
+    const PRELUDE: &str = r#"
+function isPlainHostName(host) { return host.indexOf('.') < 0; }
+function dnsDomainIs(host, domain) {
+    return host.length >= domain.length && host.substring(host.length - domain.length) == domain;
+}
+function localHostOrDomainIs(host, hostdom) {
+    return host == hostdom || hostdom.lastIndexOf(host + '.', 0) == 0;
+}
+function isResolvable(host) { return dnsResolve(host) != null; }
+function dnsDomainLevels(host) { return host.split('.').length - 1; }
+function convert_addr(ipchars) {
+    var bytes = ipchars.split('.');
+    return ((bytes[0] & 0xff) << 24) | ((bytes[1] & 0xff) << 16) | ((bytes[2] & 0xff) << 8)
+        | (bytes[3] & 0xff);
+}
+function isInNet(ipaddr, pattern, maskstr) {
+    var test = /^\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}$/.test(ipaddr);
+    var host = test ? ipaddr : dnsResolve(ipaddr);
+    if (host == null) return false;
+    return (convert_addr(host) & convert_addr(maskstr)) == (convert_addr(pattern) & convert_addr(maskstr));
+}
+function shExpMatch(url, pattern) {
+    pattern = pattern.replace(/\./g, '\\.').replace(/\*/g, '.*').replace(/\?/g, '.');
+    return new RegExp('^' + pattern + '$').test(url);
+}
+"#;
+
+    fn dns_resolve(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        let host = args
+            .get_or_undefined(0)
+            .to_string(context)?
+            .to_std_string_escaped();
+        let ip = (host.as_str(), 0)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.find(|a| a.is_ipv4()));
+        Ok(match ip {
+            Some(addr) => JsString::from(addr.ip().to_string().as_str()).into(),
+            None => JsValue::null(),
+        })
+    }
+
+    fn my_ip_address(_: &JsValue, _: &[JsValue], _: &mut Context<'_>) -> JsResult<JsValue> {
+        let ip = crate::config::Config::get_any_listen_addr(true).ip();
+        let ip = std::net::UdpSocket::bind((ip, 0))
+            .and_then(|s| s.connect("8.8.8.8:53").map(|_| s))
+            .and_then(|s| s.local_addr())
+            .map(|a| a.ip())
+            .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+        Ok(JsString::from(ip.to_string().as_str()).into())
+    }
+
+    fn eval(context: &mut Context<'_>, code: &str) -> ResultType<JsValue> {
+        match context.eval(Source::from_bytes(code)) {
+            Ok(v) => Ok(v),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    pub fn find_proxy(script: &str, url: &str, host: &str) -> ResultType<String> {
+        let mut context = Context::default();
+        let natives: [(&str, NativeFunctionPointer); 2] =
+            [("dnsResolve", dns_resolve), ("myIpAddress", my_ip_address)];
+        for (name, f) in natives {
+            if context
+                .register_global_callable(name, 1, NativeFunction::from_fn_ptr(f))
+                .is_err()
+            {
+                bail!("Failed to register {}", name);
+            }
+        }
+        eval(&mut context, PRELUDE)?;
+        eval(&mut context, script)?;
+        let call = format!(
+            "FindProxyForURL({}, {})",
+            serde_json::to_string(url)?,
+            serde_json::to_string(host)?
+        );
+        match eval(&mut context, &call)?.as_string() {
+            Some(s) => Ok(s.to_std_string_escaped()),
+            None => bail!("FindProxyForURL did not return a string"),
+        }
+    }
+}
+
+#[cfg(not(feature = "pac"))]
+mod pac {
+    use crate::{bail, ResultType};
+
+    pub fn find_proxy(_script: &str, _url: &str, _host: &str) -> ResultType<String> {
+        bail!("PAC scripts are not supported in this build");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pac_result() {
+        let conf = Socks5Server {
+            proxy: "".to_owned(),
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+        };
+        assert_eq!(parse_pac_result("DIRECT", None), Some(None));
+        assert_eq!(
+            parse_pac_result("HTTPS a:443; PROXY a:8080; DIRECT", Some(&conf)),
+            Some(Some(Proxy::Http(Socks5Server {
+                proxy: "http://a:8080".to_owned(),
+                ..conf.clone()
+            })))
+        );
+        assert!(matches!(
+            parse_pac_result(" socks5 b:1080", None),
+            Some(Some(Proxy::Socks5(_)))
+        ));
+        assert_eq!(parse_pac_result("QUIC a:443", None), None);
+    }
+
+    #[test]
+    fn test_http_proxy_addr() {
+        assert!(is_http("HTTP://a"));
+        assert!(!is_http("a:1080"));
+        assert_eq!(http_proxy_addr("http://a:3128/"), "a:3128");
+        assert_eq!(http_proxy_addr("http://a"), "a:80");
+        assert_eq!(split_host("[::1]:443"), "::1");
+        assert_eq!(split_host("a.com:21116"), "a.com");
+    }
+
+    #[test]
+    fn test_parse_env_proxy() {
+        assert_eq!(
+            parse_env_proxy("http://user:pass@a:3128/", "", "b.com"),
+            Some(Proxy::Http(Socks5Server {
+                proxy: "http://a:3128".to_owned(),
+                username: "user".to_owned(),
+                password: "pass".to_owned(),
+            }))
+        );
+        assert!(matches!(
+            parse_env_proxy("socks5h://a:1080", "", "b.com"),
+            Some(Proxy::Socks5(_))
+        ));
+        assert!(matches!(
+            parse_env_proxy("a:3128", "", "b.com"),
+            Some(Proxy::Http(_))
+        ));
+        assert_eq!(
+            parse_env_proxy("a:3128", "localhost,.b.com", "x.b.com"),
+            None
+        );
+        assert_eq!(parse_env_proxy("https://a:443", "", "b.com"), None);
+    }
+}
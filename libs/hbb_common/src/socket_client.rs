@@ -1,8 +1,9 @@
 use crate::{
-    config::{Config, NetworkType},
+    config::{self, Config, NetworkType},
+    proxy::{self, Proxy},
     tcp::FramedStream,
     udp::FramedSocket,
-    ResultType,
+    websocket, ResultType,
 };
 use anyhow::Context;
 use std::net::SocketAddr;
@@ -50,7 +51,19 @@ pub fn increase_port<T: std::string::ToString>(host: T, offset: i32) -> String {
 }
 
 pub fn test_if_valid_server(host: &str) -> String {
-    let host = check_port(host, 0);
+    // A list of rendezvous servers.
+    if host.contains(',') || host.contains('*') {
+        return config::parse_rendezvous_servers(host)
+            .iter()
+            .map(|(host, _)| test_if_valid_server(host))
+            .find(|err| !err.is_empty())
+            .unwrap_or_default();
+    }
+    let host = if proxy::is_http(host) {
+        proxy::http_proxy_addr(host)
+    } else {
+        check_port(host, 0)
+    };
 
     use std::net::ToSocketAddrs;
     match Config::get_network_type() {
@@ -58,7 +71,7 @@ pub fn test_if_valid_server(host: &str) -> String {
             Err(err) => err.to_string(),
             Ok(_) => "".to_owned(),
         },
-        NetworkType::ProxySocks => match &host.into_target_addr() {
+        NetworkType::ProxySocks | NetworkType::ProxyHttp => match &host.into_target_addr() {
             Err(err) => err.to_string(),
             Ok(_) => "".to_owned(),
         },
@@ -106,16 +119,45 @@ pub async fn connect_tcp_local<
     local: Option<SocketAddr>,
     ms_timeout: u64,
 ) -> ResultType<FramedStream> {
-    if let Some(conf) = Config::get_socks() {
-        return FramedStream::connect(
-            conf.proxy.as_str(),
-            target,
-            local,
-            conf.username.as_str(),
-            conf.password.as_str(),
-            ms_timeout,
-        )
-        .await;
+    if websocket::is_enabled() {
+        return websocket::connect(&target.to_string(), local, ms_timeout).await;
+    }
+    connect_tcp_native(target, local, ms_timeout).await
+}
+
+/// Like `connect_tcp_local`, never over WebSocket.
+pub async fn connect_tcp_native<
+    't,
+    T: IntoTargetAddr<'t> + ToSocketAddrs + IsResolvedSocketAddr + std::fmt::Display,
+>(
+    target: T,
+    local: Option<SocketAddr>,
+    ms_timeout: u64,
+) -> ResultType<FramedStream> {
+    match proxy::for_target(&target.to_string()).await {
+        Some(Proxy::Socks5(conf)) => {
+            return FramedStream::connect(
+                conf.proxy.as_str(),
+                target,
+                local,
+                conf.username.as_str(),
+                conf.password.as_str(),
+                ms_timeout,
+            )
+            .await;
+        }
+        Some(Proxy::Http(conf)) => {
+            return FramedStream::connect_http(
+                conf.proxy.as_str(),
+                target,
+                local,
+                conf.username.as_str(),
+                conf.password.as_str(),
+                ms_timeout,
+            )
+            .await;
+        }
+        None => {}
     }
     if let Some(target) = target.resolve() {
         if let Some(local) = local {
@@ -171,7 +213,7 @@ pub async fn new_udp_for(
     target: &str,
     ms_timeout: u64,
 ) -> ResultType<(FramedSocket, TargetAddr<'static>)> {
-    let (ipv4, target) = if NetworkType::Direct == Config::get_network_type() {
+    let (ipv4, target) = if NetworkType::ProxySocks != Config::get_network_type() {
         let addr = test_target(target).await?;
         (addr.is_ipv4(), addr.into_target_addr()?)
     } else {
@@ -184,7 +226,8 @@ pub async fn new_udp_for(
 }
 
 async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<FramedSocket> {
-    match Config::get_socks() {
+    // UDP does not go through an HTTP proxy.
+    match Config::get_socks().filter(|conf| !proxy::is_http(&conf.proxy)) {
         None => Ok(FramedSocket::new(local).await?),
         Some(conf) => {
             let socket = FramedSocket::new_proxy(
@@ -203,7 +246,7 @@ async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<Fram
 pub async fn rebind_udp_for(
     target: &str,
 ) -> ResultType<Option<(FramedSocket, TargetAddr<'static>)>> {
-    if Config::get_network_type() != NetworkType::Direct {
+    if Config::get_network_type() == NetworkType::ProxySocks {
         return Ok(None);
     }
     let addr = test_target(target).await?;
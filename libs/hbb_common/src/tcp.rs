@@ -1,7 +1,7 @@
-use crate::{bail, bytes_codec::BytesCodec, ResultType};
+use crate::{bail, bandwidth::Shaper, bytes_codec::BytesCodec, ResultType};
 use anyhow::Context as AnyhowCtx;
 use bytes::{BufMut, Bytes, BytesMut};
-use futures::{SinkExt, StreamExt};
+use futures::{Sink, SinkExt, Stream, StreamExt};
 use protobuf::Message;
 use sodiumoxide::crypto::{
     box_,
@@ -18,36 +18,29 @@ use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
     net::{lookup_host, TcpListener, TcpSocket, ToSocketAddrs},
 };
-use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr, ToProxyAddrs};
+use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr, TargetAddr, ToProxyAddrs};
 use tokio_util::codec::Framed;
 
 pub trait TcpStreamTrait: AsyncRead + AsyncWrite + Unpin {}
 pub struct DynTcpStream(Box<dyn TcpStreamTrait + Send + Sync>);
 
-#[derive(Clone)]
-pub struct Encrypt(Key, u64, u64);
-
-pub struct FramedStream(
-    Framed<DynTcpStream, BytesCodec>,
-    SocketAddr,
-    Option<Encrypt>,
-    u64,
-);
-
-impl Deref for FramedStream {
-    type Target = Framed<DynTcpStream, BytesCodec>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+/// A transport which keeps the boundaries of messages itself, e.g. WebSocket, so they are sent
+/// without the length header of `BytesCodec`.
+pub trait MessageStreamTrait:
+    Stream<Item = io::Result<BytesMut>> + Sink<Bytes, Error = io::Error> + Unpin
+{
 }
 
-impl DerefMut for FramedStream {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
+enum Transport {
+    Framed(Framed<DynTcpStream, BytesCodec>),
+    Message(Box<dyn MessageStreamTrait + Send + Sync>),
 }
 
+#[derive(Clone)]
+pub struct Encrypt(Key, u64, u64);
+
+pub struct FramedStream(Transport, SocketAddr, Option<Encrypt>, u64, Option<Shaper>);
+
 impl Deref for DynTcpStream {
     type Target = Box<dyn TcpStreamTrait + Send + Sync>;
 
@@ -97,12 +90,7 @@ impl FramedStream {
                 {
                     stream.set_nodelay(true).ok();
                     let addr = stream.local_addr()?;
-                    return Ok(Self(
-                        Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
-                        addr,
-                        None,
-                        0,
-                    ));
+                    return Ok(Self(Transport::new(stream), addr, None, 0, None));
                 }
             }
         }
@@ -146,12 +134,41 @@ impl FramedStream {
                 .await??
             };
             let addr = stream.local_addr()?;
-            return Ok(Self(
-                Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
-                addr,
-                None,
-                0,
-            ));
+            return Ok(Self(Transport::new(stream), addr, None, 0, None));
+        }
+        bail!("could not resolve to any address");
+    }
+
+    /// Like `connect`, through an HTTP proxy with the CONNECT method.
+    pub async fn connect_http<'t, T: IntoTargetAddr<'t>>(
+        proxy: &str,
+        target: T,
+        local_addr: Option<SocketAddr>,
+        username: &str,
+        password: &str,
+        ms_timeout: u64,
+    ) -> ResultType<Self> {
+        let target = match target.into_target_addr()? {
+            TargetAddr::Ip(addr) => addr.to_string(),
+            TargetAddr::Domain(host, port) => format!("{host}:{port}"),
+        };
+        let proxy = crate::proxy::http_proxy_addr(proxy);
+        if let Some(proxy) = lookup_host(&proxy).await?.next() {
+            let local = if let Some(addr) = local_addr {
+                addr
+            } else {
+                crate::config::Config::get_any_listen_addr(proxy.is_ipv4())
+            };
+            let mut stream =
+                super::timeout(ms_timeout, new_socket(local, true)?.connect(proxy)).await??;
+            stream.set_nodelay(true).ok();
+            super::timeout(
+                ms_timeout,
+                crate::proxy::http_connect(&mut stream, &target, username, password),
+            )
+            .await??;
+            let addr = stream.local_addr()?;
+            return Ok(Self::from(stream, addr));
         }
         bail!("could not resolve to any address");
     }
@@ -164,17 +181,40 @@ impl FramedStream {
         self.3 = ms;
     }
 
+    /// Cap the bandwidth, see `bandwidth`.
+    pub fn set_shaper(&mut self, shaper: Shaper) {
+        self.4 = Some(shaper);
+    }
+
+    #[inline]
+    pub fn shaper(&self) -> Option<&Shaper> {
+        self.4.as_ref()
+    }
+
     pub fn from(stream: impl TcpStreamTrait + Send + Sync + 'static, addr: SocketAddr) -> Self {
-        Self(
-            Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
-            addr,
-            None,
-            0,
-        )
+        Self(Transport::new(stream), addr, None, 0, None)
+    }
+
+    pub fn from_messages(
+        stream: impl MessageStreamTrait + Send + Sync + 'static,
+        addr: SocketAddr,
+    ) -> Self {
+        Self(Transport::Message(Box::new(stream)), addr, None, 0, None)
+    }
+
+    /// The underlying stream, to run another protocol over it.
+    pub fn into_raw(self) -> ResultType<DynTcpStream> {
+        match self.0 {
+            Transport::Framed(framed) => Ok(framed.into_inner()),
+            Transport::Message(_) => bail!("Not a byte stream"),
+        }
     }
 
     pub fn set_raw(&mut self) {
-        self.0.codec_mut().set_raw();
+        // Messages have no header to leave out.
+        if let Transport::Framed(framed) = &mut self.0 {
+            framed.codec_mut().set_raw();
+        }
         self.2 = None;
     }
 
@@ -199,6 +239,9 @@ impl FramedStream {
 
     #[inline]
     pub async fn send_bytes(&mut self, bytes: Bytes) -> ResultType<()> {
+        if let Some(shaper) = self.4.as_ref() {
+            shaper.upload(bytes.len()).await;
+        }
         if self.3 > 0 {
             super::timeout(self.3, self.0.send(bytes)).await??;
         } else {
@@ -209,8 +252,14 @@ impl FramedStream {
 
     #[inline]
     pub async fn next(&mut self) -> Option<Result<BytesMut, Error>> {
+        if let Some(shaper) = self.4.as_ref() {
+            shaper.wait_download().await;
+        }
         let mut res = self.0.next().await;
         if let Some(Ok(bytes)) = res.as_mut() {
+            if let Some(shaper) = self.4.as_ref() {
+                shaper.downloaded(bytes.len());
+            }
             if let Some(key) = self.2.as_mut() {
                 if let Err(err) = key.dec(bytes) {
                     return Some(Err(err));
@@ -324,6 +373,63 @@ impl AsyncWrite for DynTcpStream {
 
 impl<R: AsyncRead + AsyncWrite + Unpin> TcpStreamTrait for R {}
 
+impl<R> MessageStreamTrait for R where
+    R: Stream<Item = io::Result<BytesMut>> + Sink<Bytes, Error = io::Error> + Unpin
+{
+}
+
+impl Transport {
+    fn new(stream: impl TcpStreamTrait + Send + Sync + 'static) -> Self {
+        Self::Framed(Framed::new(
+            DynTcpStream(Box::new(stream)),
+            BytesCodec::new(),
+        ))
+    }
+}
+
+impl Stream for Transport {
+    type Item = io::Result<BytesMut>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            Self::Framed(framed) => framed.poll_next_unpin(cx),
+            Self::Message(stream) => stream.poll_next_unpin(cx),
+        }
+    }
+}
+
+impl Sink<Bytes> for Transport {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Framed(framed) => framed.poll_ready_unpin(cx),
+            Self::Message(stream) => stream.poll_ready_unpin(cx),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> io::Result<()> {
+        match self.get_mut() {
+            Self::Framed(framed) => framed.start_send_unpin(item),
+            Self::Message(stream) => stream.start_send_unpin(item),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Framed(framed) => framed.poll_flush_unpin(cx),
+            Self::Message(stream) => stream.poll_flush_unpin(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Framed(framed) => framed.poll_close_unpin(cx),
+            Self::Message(stream) => stream.poll_close_unpin(cx),
+        }
+    }
+}
+
 impl Encrypt {
     pub fn new(key: Key) -> Self {
         Self(key, 0, 0)
@@ -0,0 +1,201 @@
+//! WebSocket transport for networks which block the native ports.
+//!
+//! Each message of `tcp::FramedStream` is carried as one binary WebSocket message without the
+//! length header of `BytesCodec`, as the WebSocket ports of hbbs and hbbr expect, so everything
+//! above the stream is unchanged. With `use-websocket` set to `Y`, TCP connections go
+//! to the WebSocket ports of the servers instead, `RENDEZVOUS_PORT + 2` for the rendezvous server
+//! and `RELAY_PORT + 2` for the relay server. With `websocket-tls` also set, they go to
+//! `wss://host/ws/id` and `wss://host/ws/relay` on port 443, e.g. behind a reverse proxy.
+//! Connections to peers keep their port and never use TLS.
+
+use crate::{
+    bail,
+    config::{Config, RELAY_PORT, RENDEZVOUS_PORT},
+    tcp::FramedStream,
+    ResultType,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{ready, Sink, Stream};
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message as WsMessage},
+    WebSocketStream,
+};
+
+const TLS_PORT: u16 = 443;
+
+#[inline]
+pub fn is_enabled() -> bool {
+    Config::get_option("use-websocket") == "Y"
+}
+
+#[inline]
+fn is_tls() -> bool {
+    Config::get_option("websocket-tls") == "Y"
+}
+
+/// The WebSocket url for `target` ("host:port").
+pub fn url(target: &str, tls: bool) -> String {
+    let (host, port) = match target.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<i32>().unwrap_or(0)),
+        None => (target, 0),
+    };
+    // The online and NAT test port of the rendezvous server is served by the same endpoint.
+    let path = if port == RENDEZVOUS_PORT || port == RENDEZVOUS_PORT - 1 {
+        Some(("id", RENDEZVOUS_PORT))
+    } else if port == RELAY_PORT {
+        Some(("relay", RELAY_PORT))
+    } else {
+        None
+    };
+    match (tls, path) {
+        (true, Some((path, _))) => format!("wss://{host}/ws/{path}"),
+        (false, Some((_, port))) => format!("ws://{host}:{}", port + 2),
+        // Peers have no certificate.
+        (_, None) => format!("ws://{target}"),
+    }
+}
+
+// The address to open the TCP connection to.
+fn url_addr(url: &str) -> ResultType<String> {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        bail!("Invalid websocket url: {}", url);
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    let port = if scheme == "wss" { TLS_PORT } else { 80 };
+    Ok(crate::socket_client::check_port(host, port as _))
+}
+
+/// Connect to `target` ("host:port") over WebSocket, through the configured proxy if any.
+pub async fn connect(
+    target: &str,
+    local: Option<SocketAddr>,
+    ms_timeout: u64,
+) -> ResultType<FramedStream> {
+    let url = url(target, is_tls());
+    let stream =
+        crate::socket_client::connect_tcp_native(url_addr(&url)?, local, ms_timeout).await?;
+    let addr = stream.local_addr();
+    let (ws, _) = crate::timeout(
+        ms_timeout,
+        tokio_tungstenite::client_async_tls_with_config(url, stream.into_raw()?, None, None),
+    )
+    .await??;
+    Ok(FramedStream::from_messages(WsStream(ws), addr))
+}
+
+/// Accept a WebSocket connection on a stream whose upgrade request has not been read yet.
+pub async fn accept<S>(
+    stream: S,
+    local_addr: SocketAddr,
+    ms_timeout: u64,
+) -> ResultType<FramedStream>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    let ws = crate::timeout(ms_timeout, tokio_tungstenite::accept_async(stream)).await??;
+    Ok(FramedStream::from_messages(WsStream(ws), local_addr))
+}
+
+/// Messages over binary WebSocket messages.
+pub struct WsStream<S>(WebSocketStream<S>);
+
+fn to_io_error(err: WsError) -> io::Error {
+    match err {
+        WsError::Io(err) => err,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => ErrorKind::BrokenPipe.into(),
+        err => io::Error::new(ErrorKind::Other, err),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for WsStream<S> {
+    type Item = io::Result<BytesMut>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.0).poll_next(cx)) {
+                Some(Ok(WsMessage::Binary(data))) => {
+                    return Poll::Ready(Some(Ok(BytesMut::from(&data[..]))))
+                }
+                Some(Ok(WsMessage::Close(_))) | None => return Poll::Ready(None),
+                // Pings are answered by tungstenite.
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Some(Err(to_io_error(err)))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Bytes> for WsStream<S> {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_ready(cx).map_err(to_io_error)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> io::Result<()> {
+        Pin::new(&mut self.0)
+            .start_send(WsMessage::Binary(item.to_vec()))
+            .map_err(to_io_error)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx).map_err(to_io_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx).map_err(to_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        assert_eq!(url("a.com:21116", false), "ws://a.com:21118");
+        assert_eq!(url("a.com:21115", false), "ws://a.com:21118");
+        assert_eq!(url("a.com:21117", false), "ws://a.com:21119");
+        assert_eq!(url("1.2.3.4:21118", false), "ws://1.2.3.4:21118");
+        assert_eq!(url("[::1]:21116", false), "ws://[::1]:21118");
+        assert_eq!(url("a.com:21116", true), "wss://a.com/ws/id");
+        assert_eq!(url("a.com:21117", true), "wss://a.com/ws/relay");
+        assert_eq!(url("1.2.3.4:21118", true), "ws://1.2.3.4:21118");
+        assert_eq!(url_addr("wss://a.com/ws/id").unwrap(), "a.com:443");
+        assert_eq!(url_addr("ws://a.com:21118").unwrap(), "a.com:21118");
+    }
+
+    #[test]
+    fn test_stream() {
+        test_stream_async();
+    }
+
+    #[tokio::main(flavor = "current_thread")]
+    async fn test_stream_async() {
+        use futures::{SinkExt, StreamExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = accept(stream, addr, 1000).await.unwrap();
+            let msg = stream.next().await.unwrap().unwrap();
+            assert_eq!(&msg[..], b"abc");
+            stream.send_bytes(msg.freeze()).await.unwrap();
+        });
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut ws, _) = tokio_tungstenite::client_async(format!("ws://{addr}"), stream)
+            .await
+            .unwrap();
+        // A bare message, as hbbs and hbbr send it, without the header of `BytesCodec`.
+        ws.send(WsMessage::Binary(b"abc".to_vec())).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, WsMessage::Binary(b"abc".to_vec()));
+    }
+}
@@ -5,7 +5,7 @@ use hbb_common::{
     bail, chrono,
     config::Config,
     log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    message_proto::{message, video_frame, AudioFormat, AudioFrame, EncodedVideoFrame, Message},
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
@@ -18,7 +18,7 @@ use std::{
     sync::mpsc::Sender,
     time::Instant,
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
 
@@ -31,6 +31,7 @@ pub struct RecorderContext {
     pub width: usize,
     pub height: usize,
     pub format: CodecFormat,
+    pub audio_format: Option<AudioFormat>,
     pub tx: Option<Sender<RecordState>>,
 }
 
@@ -70,6 +71,7 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    fn write_audio(&mut self, frame: &AudioFrame) -> bool;
 }
 
 #[derive(Debug)]
@@ -199,13 +201,65 @@ impl Recorder {
     }
 }
 
+// The OpusHead header which Matroska requires as the codec private of an Opus track.
+fn opus_head(f: &AudioFormat) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(f.channels as _);
+    // pre-skip, frames are placed by their capture time and no samples are skipped
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.extend_from_slice(&f.sample_rate.to_le_bytes());
+    // output gain
+    head.extend_from_slice(&0i16.to_le_bytes());
+    // channel mapping family 0, mono or stereo
+    head.push(0);
+    head
+}
+
+fn add_opus_track(webm: &mut Segment<Writer<File>>, f: &AudioFormat) -> ResultType<AudioTrack> {
+    let at = webm.add_audio_track(
+        f.sample_rate as _,
+        f.channels as _,
+        None,
+        mux::AudioCodecId::Opus,
+    );
+    if !webm.set_codec_private(at.track_number(), &opus_head(f)) {
+        bail!("Failed to set opus codec private");
+    }
+    Ok(at)
+}
+
+struct OpusTrack {
+    at: AudioTrack,
+    last_pts: Option<i64>,
+}
+
+impl OpusTrack {
+    // `base` is the pts of the first written key frame, audio is placed on the video timeline.
+    fn write(&mut self, frame: &AudioFrame, base: Option<i64>) -> bool {
+        // Audio without capture time can not be placed on the video timeline.
+        let (Some(pts), Some(base)) = (frame.pts, base) else {
+            return false;
+        };
+        let pts = pts - base;
+        if pts < 0 || self.last_pts.map_or(false, |last| pts <= last) {
+            return false;
+        }
+        self.last_pts = Some(pts);
+        self.at.add_frame(&frame.data, pts as u64 * 1_000_000, true)
+    }
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
+    at: Option<OpusTrack>,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     key: bool,
     written: bool,
     start: Instant,
+    // pts of the first written key frame, audio and video timestamps are relative to it
+    pts_base: Option<i64>,
 }
 
 impl RecorderApi for WebmRecorder {
@@ -234,13 +288,22 @@ impl RecorderApi for WebmRecorder {
                 mux::VideoCodecId::VP8
             },
         );
+        let at = match ctx.audio_format.as_ref() {
+            Some(f) => Some(OpusTrack {
+                at: add_opus_track(&mut webm, f)?,
+                last_pts: None,
+            }),
+            None => None,
+        };
         Ok(WebmRecorder {
             vt,
+            at,
             webm: Some(webm),
             ctx,
             key: false,
             written: false,
             start: Instant::now(),
+            pts_base: None,
         })
     }
 
@@ -249,9 +312,11 @@ impl RecorderApi for WebmRecorder {
             self.key = true;
         }
         if self.key {
+            let base = *self.pts_base.get_or_insert(frame.pts);
+            let pts = (frame.pts - base).max(0);
             let ok = self
                 .vt
-                .add_frame(&frame.data, frame.pts as u64 * 1_000_000, frame.key);
+                .add_frame(&frame.data, pts as u64 * 1_000_000, frame.key);
             if ok {
                 self.written = true;
             }
@@ -260,6 +325,11 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, frame: &AudioFrame) -> bool {
+        let base = self.pts_base;
+        self.at.as_mut().map_or(false, |at| at.write(frame, base))
+    }
 }
 
 impl Drop for WebmRecorder {
@@ -274,13 +344,48 @@ impl Drop for WebmRecorder {
     }
 }
 
+// The hardware muxer takes a single video stream, the audio goes to a WebM file next to it,
+// on the same timeline.
+#[cfg(feature = "hwcodec")]
+struct HwAudio {
+    at: OpusTrack,
+    webm: Option<Segment<Writer<File>>>,
+    filename: String,
+    written: bool,
+}
+
+#[cfg(feature = "hwcodec")]
+impl HwAudio {
+    fn new(video_filename: &str, f: &AudioFormat) -> ResultType<Self> {
+        let filename = PathBuf::from(video_filename)
+            .with_extension("audio.webm")
+            .to_string_lossy()
+            .to_string();
+        let mut webm = match mux::Segment::new(mux::Writer::new(File::create(&filename)?)) {
+            Some(v) => v,
+            None => bail!("Failed to create webm mux"),
+        };
+        Ok(HwAudio {
+            at: OpusTrack {
+                at: add_opus_track(&mut webm, f)?,
+                last_pts: None,
+            },
+            webm: Some(webm),
+            filename,
+            written: false,
+        })
+    }
+}
+
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Muxer,
+    audio: Option<HwAudio>,
     ctx: RecorderContext,
     written: bool,
     key: bool,
     start: Instant,
+    pts_base: Option<i64>,
 }
 
 #[cfg(feature = "hwcodec")]
@@ -294,12 +399,18 @@ impl RecorderApi for HwRecorder {
             framerate: crate::hwcodec::DEFAULT_TIME_BASE[1] as _,
         })
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
+        let audio = match ctx.audio_format.as_ref() {
+            Some(f) => Some(HwAudio::new(&ctx.filename, f)?),
+            None => None,
+        };
         Ok(HwRecorder {
             muxer,
+            audio,
             ctx,
             written: false,
             key: false,
             start: Instant::now(),
+            pts_base: None,
         })
     }
 
@@ -308,6 +419,7 @@ impl RecorderApi for HwRecorder {
             self.key = true;
         }
         if self.key {
+            self.pts_base.get_or_insert(frame.pts);
             let ok = self.muxer.write_video(&frame.data, frame.key).is_ok();
             if ok {
                 self.written = true;
@@ -317,6 +429,16 @@ impl RecorderApi for HwRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, frame: &AudioFrame) -> bool {
+        let base = self.pts_base;
+        let Some(audio) = self.audio.as_mut() else {
+            return false;
+        };
+        let ok = audio.at.write(frame, base);
+        audio.written |= ok;
+        ok
+    }
 }
 
 #[cfg(feature = "hwcodec")]
@@ -324,7 +446,14 @@ impl Drop for HwRecorder {
     fn drop(&mut self) {
         self.muxer.write_tail().ok();
         let mut state = RecordState::WriteTail;
-        if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
+        let remove = !self.written || self.start.elapsed().as_secs() < MIN_SECS;
+        if let Some(mut audio) = self.audio.take() {
+            audio.webm.take().map(|webm| webm.finalize(None));
+            if remove || !audio.written {
+                std::fs::remove_file(&audio.filename).ok();
+            }
+        }
+        if remove {
             std::fs::remove_file(&self.ctx.filename).ok();
             state = RecordState::RemoveFile;
         }
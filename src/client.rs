@@ -23,9 +23,13 @@ use ringbuf::{ring_buffer::RbBase, Rb};
 use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+use av_sync::AudioJitterBuffer;
+pub use av_sync::MediaSync;
 pub use file_trait::FileManager;
 #[cfg(windows)]
 use hbb_common::tokio;
+#[cfg(feature = "quic")]
+use hbb_common::quic;
 #[cfg(not(feature = "flutter"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use hbb_common::tokio::sync::mpsc::UnboundedSender;
@@ -37,6 +41,7 @@ use hbb_common::{
         Config, LocalConfig, PeerConfig, PeerInfoSerde, Resolution, CONNECT_TIMEOUT, READ_TIMEOUT,
         RELAY_PORT,
     },
+    futures::future::select_ok,
     get_version_number, log,
     message_proto::{option_message::BoolOption, *},
     protobuf::Message as _,
@@ -47,7 +52,7 @@ use hbb_common::{
     sodiumoxide::crypto::{box_, secretbox, sign},
     tcp::FramedStream,
     timeout,
-    tokio::time::Duration,
+    tokio::{net::UdpSocket, time::Duration},
     AddrMangle, ResultType, Stream,
 };
 pub use helper::*;
@@ -70,13 +75,19 @@ use crate::{check_clipboard, ClipboardContext, CLIPBOARD_INTERVAL};
 
 pub use super::lang::*;
 
+pub mod av_sync;
 pub mod file_trait;
+#[cfg(target_os = "linux")]
+pub mod gamepad;
 pub mod helper;
 pub mod io_loop;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
 pub const VIDEO_QUEUE_SIZE: usize = 120;
+const AUDIO_SYNC_INTERVAL: Duration = Duration::from_millis(10);
+#[cfg(feature = "quic")]
+pub(crate) const UDP_PUNCH_TIMEOUT: u64 = 5_000;
 
 #[cfg(all(target_os = "linux", feature = "linux_headless"))]
 #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
@@ -95,6 +106,9 @@ pub const LOGIN_MSG_PASSWORD_EMPTY: &str = "Empty Password";
 pub const LOGIN_MSG_PASSWORD_WRONG: &str = "Wrong Password";
 pub const LOGIN_MSG_NO_PASSWORD_ACCESS: &str = "No Password Access";
 pub const LOGIN_MSG_OFFLINE: &str = "Offline";
+pub const LOGIN_MSG_STRICT_ENCRYPTION: &str = "strict_encryption_failed_tip";
+pub const LOGIN_MSG_2FA_REQUIRED: &str = "2FA Required";
+pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
 pub const LOGIN_SCREEN_WAYLAND: &str = "Wayland login screen is not supported";
 #[cfg(target_os = "linux")]
 pub const SCRAP_UBUNTU_HIGHER_REQUIRED: &str = "Wayland requires Ubuntu 21.04 or higher version.";
@@ -118,6 +132,30 @@ pub(crate) struct ClientClipboardContext {
 /// Client of the remote desktop.
 pub struct Client;
 
+// The answer of a rendezvous server to a punch hole request.
+enum Punched {
+    Peer {
+        my_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        peer_nat_type: NatType,
+        peer_nat_info: NatInfo,
+        peer_udp_addr: Option<SocketAddr>,
+        is_local: bool,
+        signed_id_pk: Vec<u8>,
+        relay_server: String,
+        time_used: u64,
+    },
+    // The peer can not be punched to and waits on the relay server.
+    Relay {
+        uuid: String,
+        relay_server: String,
+        signed_id_pk: Vec<u8>,
+        ipv4: bool,
+    },
+    // Refused in a way no other server would do better, e.g. a wrong key.
+    Failed(hbb_common::anyhow::Error),
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 struct TextClipboardState {
     is_required: bool,
@@ -232,10 +270,19 @@ impl Client {
                     return Err(err);
                 }
             }
-            Ok(x) => Ok(x),
+            Ok((mut conn, direct, pk)) => {
+                conn.set_shaper(hbb_common::bandwidth::Shaper::for_peer(peer));
+                Ok((conn, direct, pk))
+            }
         }
     }
 
+    /// Whether the peer is connected by its address instead of its id.
+    #[inline]
+    pub fn is_address(peer: &str) -> bool {
+        hbb_common::is_ip_str(peer) || hbb_common::is_domain_port_str(peer)
+    }
+
     /// Start a new connection.
     async fn _start(
         peer: &str,
@@ -245,6 +292,7 @@ impl Client {
         interface: impl Interface,
     ) -> ResultType<(Stream, bool, Option<Vec<u8>>)> {
         // to-do: remember the port for each peer, so that we can retry easier
+        // The handshake of these connections is done in the message loop, see `secure_direct_connection`.
         if hbb_common::is_ip_str(peer) {
             return Ok((
                 socket_client::connect_tcp(
@@ -264,27 +312,136 @@ impl Client {
                 None,
             ));
         }
-        let (mut rendezvous_server, servers, contained) = crate::get_rendezvous_server(1_000).await;
-        let mut socket = socket_client::connect_tcp(&*rendezvous_server, CONNECT_TIMEOUT).await;
+        if !interface.is_force_relay() {
+            crate::nat::refresh_udp_endpoint();
+        }
+        let (rendezvous_server, mut servers, contained) = crate::get_rendezvous_server(1_000).await;
         debug_assert!(!servers.contains(&rendezvous_server));
-        if socket.is_err() && !servers.is_empty() {
-            log::info!("try the other servers: {:?}", servers);
-            for server in servers {
-                socket = socket_client::connect_tcp(&*server, CONNECT_TIMEOUT).await;
-                if socket.is_ok() {
-                    rendezvous_server = server;
-                    break;
+        // The others are tried by weight, in the order of the config for the same weight.
+        servers.sort_by_key(|x| std::cmp::Reverse(Config::get_rendezvous_server_weight(x)));
+        let mut candidates = vec![rendezvous_server];
+        candidates.extend(servers);
+        let my_nat_type = crate::get_nat_type(100).await;
+        let my_nat_info = crate::get_nat_info(100).await;
+        // Not to wait for the STUN server, it is only there if learned in time.
+        let udp = Self::udp_endpoint(interface.is_force_relay());
+        let udp_addr = udp.as_ref().map(|(_, addr)| *addr);
+        let mut first_err = None;
+        let mut i = 0;
+        let punched = loop {
+            let Some(rendezvous_server) = candidates.get(i) else {
+                return Err(first_err.unwrap_or_else(|| anyhow!("No rendezvous server")));
+            };
+            match Self::punch_hole(
+                rendezvous_server,
+                peer,
+                key,
+                token,
+                conn_type,
+                my_nat_type,
+                &my_nat_info,
+                udp_addr,
+                &interface,
+            )
+            .await
+            {
+                Ok(punched) => break punched,
+                Err(err) => {
+                    log::info!("Failed to punch hole via {}: {}", rendezvous_server, err);
+                    // The peer may be registered with some of the federated servers only.
+                    if i == 0 && candidates.len() > 2 {
+                        if let Some(found) =
+                            crate::rendezvous_mediator::find_peer_server(peer, &candidates[1..])
+                                .await
+                        {
+                            log::info!("{} is online with {}", peer, found);
+                            candidates.retain(|x| x != &found);
+                            candidates.insert(1, found);
+                        }
+                    }
+                    first_err.get_or_insert(err);
+                    i += 1;
                 }
             }
+        };
+        let rendezvous_server = candidates.swap_remove(i);
+        if i > 0 || !contained {
             crate::refresh_rendezvous_server();
-        } else if !contained {
-            crate::refresh_rendezvous_server();
         }
+        match punched {
+            Punched::Failed(err) => Err(err),
+            Punched::Relay {
+                uuid,
+                relay_server,
+                signed_id_pk,
+                ipv4,
+            } => {
+                let mut conn =
+                    Self::create_relay(peer, uuid, relay_server, key, conn_type, ipv4).await?;
+                let pk = Self::secure_connection(peer, signed_id_pk, key, &mut conn).await?;
+                Ok((conn, false, pk))
+            }
+            Punched::Peer {
+                my_addr,
+                peer_addr,
+                peer_nat_type,
+                peer_nat_info,
+                peer_udp_addr,
+                is_local,
+                signed_id_pk,
+                relay_server,
+                time_used,
+            } => {
+                log::info!(
+                    "{} ms used to punch hole, relay_server: {}, {}",
+                    time_used,
+                    relay_server,
+                    if is_local {
+                        "is_local: true".to_owned()
+                    } else {
+                        format!("nat_type: {:?}", peer_nat_type)
+                    }
+                );
+                Self::connect(
+                    my_addr,
+                    peer_addr,
+                    peer,
+                    signed_id_pk,
+                    &relay_server,
+                    &rendezvous_server,
+                    time_used,
+                    peer_nat_type,
+                    &peer_nat_info,
+                    udp.map(|(socket, _)| socket).zip(peer_udp_addr),
+                    my_nat_type,
+                    is_local,
+                    key,
+                    token,
+                    conn_type,
+                    interface,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Punch a hole to the peer through one rendezvous server.
+    ///
+    /// An error means the server is unreachable or does not know the peer, another one may do.
+    async fn punch_hole(
+        rendezvous_server: &str,
+        peer: &str,
+        key: &str,
+        token: &str,
+        conn_type: ConnType,
+        my_nat_type: i32,
+        my_nat_info: &NatInfo,
+        udp_addr: Option<SocketAddr>,
+        interface: &impl Interface,
+    ) -> ResultType<Punched> {
+        let mut socket = socket_client::connect_tcp(rendezvous_server, CONNECT_TIMEOUT).await?;
         log::info!("rendezvous server: {}", rendezvous_server);
-        let mut socket = socket?;
         let my_addr = socket.local_addr();
-        let mut signed_id_pk = Vec::new();
-        let mut relay_server = "".to_owned();
 
         if !key.is_empty() && !token.is_empty() {
             // mainly for the security of token
@@ -292,18 +449,17 @@ impl Client {
         }
 
         let start = std::time::Instant::now();
-        let mut peer_addr = Config::get_any_listen_addr(true);
-        let mut peer_nat_type = NatType::UNKNOWN_NAT;
-        let my_nat_type = crate::get_nat_type(100).await;
-        let mut is_local = false;
         for i in 1..=3 {
             log::info!("#{} punch attempt with {}, id: {}", i, my_addr, peer);
             let mut msg_out = RendezvousMessage::new();
             use hbb_common::protobuf::Enum;
-            let nat_type = if interface.is_force_relay() {
-                NatType::SYMMETRIC
+            let (nat_type, nat_info) = if interface.is_force_relay() {
+                (NatType::SYMMETRIC, NatInfo::new())
             } else {
-                NatType::from_i32(my_nat_type).unwrap_or(NatType::UNKNOWN_NAT)
+                (
+                    NatType::from_i32(my_nat_type).unwrap_or(NatType::UNKNOWN_NAT),
+                    my_nat_info.clone(),
+                )
             };
             msg_out.set_punch_hole_request(PunchHoleRequest {
                 id: peer.to_owned(),
@@ -311,6 +467,10 @@ impl Client {
                 nat_type: nat_type.into(),
                 licence_key: key.to_owned(),
                 conn_type: conn_type.into(),
+                nat_info: hbb_common::protobuf::MessageField::some(nat_info),
+                udp_socket_addr: udp_addr
+                    .map(|addr| AddrMangle::encode(addr).into())
+                    .unwrap_or_default(),
                 ..Default::default()
             });
             socket.send(&msg_out).await?;
@@ -321,7 +481,7 @@ impl Client {
                     Some(rendezvous_message::Union::PunchHoleResponse(ph)) => {
                         if ph.socket_addr.is_empty() {
                             if !ph.other_failure.is_empty() {
-                                bail!(ph.other_failure);
+                                return Ok(Punched::Failed(anyhow!(ph.other_failure)));
                             }
                             match ph.failure.enum_value() {
                                 Ok(punch_hole_response::Failure::ID_NOT_EXIST) => {
@@ -331,21 +491,28 @@ impl Client {
                                     bail!("Remote desktop is offline");
                                 }
                                 Ok(punch_hole_response::Failure::LICENSE_MISMATCH) => {
-                                    bail!("Key mismatch");
+                                    return Ok(Punched::Failed(anyhow!("Key mismatch")));
                                 }
                                 Ok(punch_hole_response::Failure::LICENSE_OVERUSE) => {
-                                    bail!("Key overuse");
+                                    return Ok(Punched::Failed(anyhow!("Key overuse")));
                                 }
                                 _ => bail!("other punch hole failure"),
                             }
                         } else {
-                            peer_nat_type = ph.nat_type();
-                            is_local = ph.is_local();
-                            signed_id_pk = ph.pk.into();
-                            relay_server = ph.relay_server;
-                            peer_addr = AddrMangle::decode(&ph.socket_addr);
+                            let peer_addr = AddrMangle::decode(&ph.socket_addr);
                             log::info!("Hole Punched {} = {}", peer, peer_addr);
-                            break;
+                            return Ok(Punched::Peer {
+                                my_addr,
+                                peer_addr,
+                                peer_nat_type: ph.nat_type(),
+                                peer_nat_info: ph.nat_info.clone().unwrap_or_default(),
+                                peer_udp_addr: (!ph.udp_socket_addr.is_empty())
+                                    .then(|| AddrMangle::decode(&ph.udp_socket_addr)),
+                                is_local: ph.is_local(),
+                                signed_id_pk: ph.pk.into(),
+                                relay_server: ph.relay_server,
+                                time_used: start.elapsed().as_millis() as u64,
+                            });
                         }
                     }
                     Some(rendezvous_message::Union::RelayResponse(rr)) => {
@@ -354,19 +521,12 @@ impl Client {
                             start.elapsed(),
                             rr.relay_server
                         );
-                        signed_id_pk = rr.pk().into();
-                        let mut conn = Self::create_relay(
-                            peer,
-                            rr.uuid,
-                            rr.relay_server,
-                            key,
-                            conn_type,
-                            my_addr.is_ipv4(),
-                        )
-                        .await?;
-                        let pk =
-                            Self::secure_connection(peer, signed_id_pk, key, &mut conn).await?;
-                        return Ok((conn, false, pk));
+                        return Ok(Punched::Relay {
+                            signed_id_pk: rr.pk().into(),
+                            uuid: rr.uuid,
+                            relay_server: rr.relay_server,
+                            ipv4: my_addr.is_ipv4(),
+                        });
                     }
                     _ => {
                         log::error!("Unexpected protobuf msg received: {:?}", msg_in);
@@ -374,38 +534,15 @@ impl Client {
                 }
             }
         }
-        drop(socket);
-        if peer_addr.port() == 0 {
-            bail!("Failed to connect via rendezvous server");
+        bail!("Failed to connect via rendezvous server");
+    }
+
+    // A UDP port to punch a QUIC connection through, if the TCP holes do not open.
+    fn udp_endpoint(force_relay: bool) -> Option<(UdpSocket, SocketAddr)> {
+        if force_relay {
+            return None;
         }
-        let time_used = start.elapsed().as_millis() as u64;
-        log::info!(
-            "{} ms used to punch hole, relay_server: {}, {}",
-            time_used,
-            relay_server,
-            if is_local {
-                "is_local: true".to_owned()
-            } else {
-                format!("nat_type: {:?}", peer_nat_type)
-            }
-        );
-        Self::connect(
-            my_addr,
-            peer_addr,
-            peer,
-            signed_id_pk,
-            &relay_server,
-            &rendezvous_server,
-            time_used,
-            peer_nat_type,
-            my_nat_type,
-            is_local,
-            key,
-            token,
-            conn_type,
-            interface,
-        )
-        .await
+        crate::nat::take_udp_endpoint()
     }
 
     /// Connect to the peer.
@@ -418,6 +555,8 @@ impl Client {
         rendezvous_server: &str,
         punch_time_used: u64,
         peer_nat_type: NatType,
+        peer_nat_info: &NatInfo,
+        udp: Option<(UdpSocket, SocketAddr)>,
         my_nat_type: i32,
         is_local: bool,
         key: &str,
@@ -460,8 +599,32 @@ impl Client {
         log::info!("peer address: {}, timeout: {}", peer, connect_timeout);
         let start = std::time::Instant::now();
         // NOTICE: Socks5 is be used event in intranet. Which may be not a good way.
-        let mut conn =
-            socket_client::connect_tcp_local(peer, Some(local_addr), connect_timeout).await;
+        // A symmetric NAT of the peer maps the next ports for us, try them as well.
+        let mut conn = select_ok(
+            crate::nat::candidate_addrs(peer, peer_nat_info)
+                .into_iter()
+                .map(|addr| {
+                    Box::pin(socket_client::connect_tcp_local(
+                        addr,
+                        Some(local_addr),
+                        connect_timeout,
+                    ))
+                }),
+        )
+        .await
+        .map(|(conn, _)| conn);
+        #[cfg(feature = "quic")]
+        if conn.is_err() && !interface.is_force_relay() {
+            if let Some((socket, peer_udp_addr)) = udp {
+                log::info!("TCP punching failed, try QUIC to {}", peer_udp_addr);
+                match quic::connect(socket, peer_udp_addr, UDP_PUNCH_TIMEOUT).await {
+                    Ok(stream) => conn = Ok(stream),
+                    Err(err) => log::info!("Failed to connect over QUIC: {}", err),
+                }
+            }
+        }
+        #[cfg(not(feature = "quic"))]
+        let _ = udp;
         let mut direct = !conn.is_err();
         interface.update_direct(Some(direct));
         if interface.is_force_relay() || conn.is_err() {
@@ -498,26 +661,23 @@ impl Client {
     }
 
     /// Establish secure connection with the server.
-    async fn secure_connection(
+    pub(crate) async fn secure_connection(
         peer_id: &str,
         signed_id_pk: Vec<u8>,
         key: &str,
         conn: &mut Stream,
     ) -> ResultType<Option<Vec<u8>>> {
-        let rs_pk = get_rs_pk(if key.is_empty() {
-            hbb_common::config::RS_PUB_KEY
-        } else {
-            key
-        });
+        let rs_pks = crate::trusted_keys::valid_keys(key, &crate::trusted_keys::get_stored().await);
         let mut sign_pk = None;
         let mut option_pk = None;
         if !signed_id_pk.is_empty() {
-            if let Some(rs_pk) = rs_pk {
-                if let Ok((id, pk)) = decode_id_pk(&signed_id_pk, &rs_pk) {
-                    if id == peer_id {
-                        sign_pk = Some(sign::PublicKey(pk));
-                        option_pk = Some(pk.to_vec());
-                    }
+            if let Some((id, pk)) = rs_pks
+                .iter()
+                .find_map(|rs_pk| decode_id_pk(&signed_id_pk, rs_pk).ok())
+            {
+                if id == peer_id {
+                    sign_pk = Some(sign::PublicKey(pk));
+                    option_pk = Some(pk.to_vec());
                 }
             }
             if sign_pk.is_none() {
@@ -527,6 +687,10 @@ impl Client {
         let sign_pk = match sign_pk {
             Some(v) => v,
             None => {
+                // Direct connections are secured later by the host key in `SignedId`.
+                if crate::common::is_strict_encryption() && !Self::is_address(peer_id) {
+                    bail!(LOGIN_MSG_STRICT_ENCRYPTION);
+                }
                 // send an empty message out in case server is setting up secure and waiting for first message
                 conn.send(&Message::new()).await?;
                 return Ok(option_pk);
@@ -573,11 +737,68 @@ impl Client {
                 bail!("Reset by the peer");
             }
         }
+        if crate::common::is_strict_encryption() && !conn.is_secured() {
+            bail!(LOGIN_MSG_STRICT_ENCRYPTION);
+        }
         Ok(option_pk)
     }
 
+    /// Establish secure connection with a host connected by its address.
+    ///
+    /// The host key can not be verified by the rendezvous server,
+    /// so it is pinned on first use and must not change later.
+    pub async fn secure_direct_connection(
+        peer: &str,
+        si: SignedId,
+        conn: &mut Stream,
+    ) -> ResultType<Vec<u8>> {
+        let Some(sign_pk) = sign::PublicKey::from_slice(&si.pk) else {
+            bail!("Handshake failed: invalid public sign key length from peer");
+        };
+        let (_, their_pk_b) = decode_id_pk(&si.id, &sign_pk)?;
+        let fingerprint = crate::common::pk_to_fingerprint(si.pk.to_vec());
+        let mut config = PeerConfig::load(peer);
+        if config.pinned_fingerprint.is_empty() {
+            log::info!("Pin the key of {}: {}", peer, fingerprint);
+            config.pinned_fingerprint = fingerprint;
+            config.store(peer);
+        } else if config.pinned_fingerprint != fingerprint {
+            log::error!(
+                "The key of {} changed from {} to {}",
+                peer,
+                config.pinned_fingerprint,
+                fingerprint
+            );
+            bail!("host_key_changed_tip");
+        }
+        let (asymmetric_value, symmetric_value, key) = create_symmetric_key_msg(their_pk_b);
+        let mut msg_out = Message::new();
+        msg_out.set_public_key(PublicKey {
+            asymmetric_value,
+            symmetric_value,
+            ..Default::default()
+        });
+        timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
+        conn.set_key(key);
+        Ok(si.pk.to_vec())
+    }
+
+    /// Check that a host with a pinned key has proved it before the login starts.
+    ///
+    /// Otherwise a host in the middle could skip `SignedId` to get a plain session.
+    pub fn check_pinned_key(peer: &str, secured: bool) -> ResultType<()> {
+        if !secured
+            && Self::is_address(peer)
+            && !PeerConfig::load(peer).pinned_fingerprint.is_empty()
+        {
+            log::error!("The pinned key of {} is not verified", peer);
+            bail!("host_key_changed_tip");
+        }
+        Ok(())
+    }
+
     /// Request a relay connection to the server.
-    async fn request_relay(
+    pub(crate) async fn request_relay(
         peer: &str,
         relay_server: String,
         rendezvous_server: &str,
@@ -641,7 +862,7 @@ impl Client {
     }
 
     /// Create a relay connection to the server.
-    async fn create_relay(
+    pub(crate) async fn create_relay(
         peer: &str,
         uuid: String,
         relay_server: String,
@@ -772,6 +993,10 @@ pub struct AudioHandler {
     device_channel: u16,
     #[cfg(not(any(target_os = "android", target_os = "linux")))]
     ready: Arc<std::sync::Mutex<bool>>,
+    sync: Option<Arc<MediaSync>>,
+    jitter_buffer: AudioJitterBuffer,
+    #[cfg(not(target_os = "android"))]
+    output_device: Option<String>,
 }
 
 #[cfg(not(any(target_os = "android", target_os = "linux")))]
@@ -787,6 +1012,21 @@ impl Default for AudioBuffer {
 }
 
 impl AudioHandler {
+    /// Create an audio handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `sync` - Synchronize the audio with the video of the same session.
+    /// * `output_device` - The device to play on, `None` for the default output device.
+    pub fn new(sync: Option<Arc<MediaSync>>, _output_device: Option<String>) -> Self {
+        Self {
+            sync,
+            #[cfg(not(target_os = "android"))]
+            output_device: _output_device,
+            ..Default::default()
+        }
+    }
+
     /// Start the audio playback.
     #[cfg(target_os = "linux")]
     fn start_audio(&mut self, format0: AudioFormat) -> ResultType<()> {
@@ -804,14 +1044,14 @@ impl AudioHandler {
         }
 
         self.simple = Some(Simple::new(
-            None,                   // Use the default server
-            &crate::get_app_name(), // Our application’s name
-            Direction::Playback,    // We want a playback stream
-            None,                   // Use the default device
-            "playback",             // Description of our stream
-            &spec,                  // Our sample format
-            None,                   // Use default channel map
-            None,                   // Use default buffering attributes
+            None,                          // Use the default server
+            &crate::get_app_name(),        // Our application’s name
+            Direction::Playback,           // We want a playback stream
+            self.output_device.as_deref(), // None to use the default device
+            "playback",                    // Description of our stream
+            &spec,                         // Our sample format
+            None,                          // Use default channel map
+            None,                          // Use default buffering attributes
         )?);
         self.sample_rate = (format0.sample_rate, format0.sample_rate);
         Ok(())
@@ -831,11 +1071,17 @@ impl AudioHandler {
     /// Start the audio playback.
     #[cfg(not(any(target_os = "android", target_os = "linux")))]
     fn start_audio(&mut self, format0: AudioFormat) -> ResultType<()> {
-        let device = AUDIO_HOST
-            .default_output_device()
-            .with_context(|| "Failed to get default output device")?;
+        let device = match &self.output_device {
+            Some(name) => AUDIO_HOST
+                .output_devices()?
+                .find(|d| d.name().map_or(false, |n| &n == name))
+                .with_context(|| format!("Failed to get output device {}", name))?,
+            None => AUDIO_HOST
+                .default_output_device()
+                .with_context(|| "Failed to get default output device")?,
+        };
         log::info!(
-            "Using default output device: \"{}\"",
+            "Using output device: \"{}\"",
             device.name().unwrap_or("".to_owned())
         );
         let config = device.default_output_config().map_err(|e| anyhow!(e))?;
@@ -879,6 +1125,10 @@ impl AudioHandler {
                 let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
                 self.audio_decoder = Some((d, buffer));
                 self.channels = f.channels as _;
+                self.jitter_buffer.clear();
+                if let Some(sync) = self.sync.as_ref() {
+                    sync.set_audio_format(f.clone());
+                }
                 allow_err!(self.start_audio(f));
             }
             Err(err) => {
@@ -887,9 +1137,37 @@ impl AudioHandler {
         }
     }
 
-    /// Handle audio frame and play it.
+    /// Handle audio frame, it is played once the video with the same pts is rendered.
     #[inline]
     pub fn handle_frame(&mut self, frame: AudioFrame) {
+        if self.sync.is_some() {
+            self.jitter_buffer.push(frame);
+            self.play_due_frames();
+        } else {
+            self.play_frame(frame);
+        }
+    }
+
+    /// Whether frames are waiting in the jitter buffer.
+    #[inline]
+    pub fn has_pending(&self) -> bool {
+        !self.jitter_buffer.is_empty()
+    }
+
+    /// Play the buffered frames that are due according to the video clock.
+    pub fn play_due_frames(&mut self) {
+        let video_pts = self.sync.as_ref().and_then(|s| s.video_pts());
+        for frame in self.jitter_buffer.pop(video_pts) {
+            self.play_frame(frame);
+        }
+    }
+
+    fn play_frame(&mut self, frame: AudioFrame) {
+        if let Some(sync) = &self.sync {
+            if let Some(r) = sync.recorder.lock().unwrap().as_mut() {
+                r.write_audio(&frame);
+            }
+        }
         #[cfg(not(any(target_os = "android", target_os = "linux")))]
         if self.audio_stream.is_none() || !self.ready.lock().unwrap().clone() {
             return;
@@ -996,21 +1274,34 @@ impl AudioHandler {
 pub struct VideoHandler {
     decoder: Decoder,
     pub rgb: ImageRgb,
-    recorder: Arc<Mutex<Option<Recorder>>>,
+    sync: Arc<MediaSync>,
     record: bool,
 }
 
 impl VideoHandler {
     /// Create a new video handler.
-    pub fn new() -> Self {
+    pub fn new(sync: Arc<MediaSync>) -> Self {
         VideoHandler {
             decoder: Decoder::new(),
             rgb: ImageRgb::new(ImageFormat::ARGB, crate::DST_STRIDE_RGBA),
-            recorder: Default::default(),
+            sync,
             record: false,
         }
     }
 
+    /// The pts of the last encoded frame in `vf`.
+    fn last_pts(vf: &VideoFrame) -> Option<i64> {
+        let frames = match &vf.union {
+            Some(video_frame::Union::Vp8s(f))
+            | Some(video_frame::Union::Vp9s(f))
+            | Some(video_frame::Union::Av1s(f))
+            | Some(video_frame::Union::H264s(f))
+            | Some(video_frame::Union::H265s(f)) => f,
+            _ => return None,
+        };
+        frames.frames.last().map(|f| f.pts)
+    }
+
     /// Handle a new video frame.
     #[inline]
     pub fn handle_frame(&mut self, vf: VideoFrame) -> ResultType<bool> {
@@ -1018,7 +1309,8 @@ impl VideoHandler {
             Some(frame) => {
                 let res = self.decoder.handle_video_frame(frame, &mut self.rgb);
                 if self.record {
-                    self.recorder
+                    self.sync
+                        .recorder
                         .lock()
                         .unwrap()
                         .as_mut()
@@ -1033,13 +1325,14 @@ impl VideoHandler {
     /// Reset the decoder.
     pub fn reset(&mut self) {
         self.decoder = Decoder::new();
+        self.sync.reset_video();
     }
 
     /// Start or stop screen record.
     pub fn record_screen(&mut self, start: bool, w: i32, h: i32, id: String) {
         self.record = false;
-        if start {
-            self.recorder = Recorder::new(RecorderContext {
+        let recorder = if start {
+            Recorder::new(RecorderContext {
                 server: false,
                 id,
                 default_dir: crate::ui_interface::default_video_save_directory(),
@@ -1047,12 +1340,14 @@ impl VideoHandler {
                 width: w as _,
                 height: h as _,
                 format: scrap::CodecFormat::VP9,
+                audio_format: self.sync.audio_format(),
                 tx: None,
             })
-            .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
+            .ok()
         } else {
-            self.recorder = Default::default();
-        }
+            None
+        };
+        *self.sync.recorder.lock().unwrap() = recorder;
 
         self.record = start;
     }
@@ -1068,6 +1363,8 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    // Log in only to wake a machine on the LAN of the peer.
+    pub wake_on_lan: Option<WakeOnLan>,
     pub version: i64,
     features: Option<Features>,
     pub session_id: u64, // used for local <-> server communication
@@ -1720,6 +2017,19 @@ impl LoginConfigHandler {
         serde_json::to_string::<HashMap<String, String>>(&x).unwrap_or_default()
     }
 
+    /// Sign the challenge with our key, the host skips the password if it trusts the key.
+    fn sign_challenge(&self, my_id: &str) -> (Vec<u8>, Vec<u8>) {
+        if !self.hash.key_auth || self.hash.challenge.is_empty() {
+            return Default::default();
+        }
+        let (sk, pk) = Config::get_key_pair();
+        let Ok(sk) = <[u8; sign::SECRETKEYBYTES]>::try_from(sk.as_slice()) else {
+            return Default::default();
+        };
+        let msg = crate::common::key_auth_message(&self.hash.challenge, my_id, &self.id);
+        (pk, sign::sign(&msg, &sign::SecretKey(sk)))
+    }
+
     /// Create a [`Message`] for login.
     fn create_login_msg(
         &self,
@@ -1731,6 +2041,7 @@ impl LoginConfigHandler {
         let my_id = Config::get_id_or(crate::DEVICE_ID.lock().unwrap().clone());
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let my_id = Config::get_id();
+        let (public_key, key_signature) = self.sign_challenge(&my_id);
         let mut lr = LoginRequest {
             username: self.id.clone(),
             password: password.into(),
@@ -1745,6 +2056,9 @@ impl LoginConfigHandler {
                 ..Default::default()
             })
             .into(),
+            trusted_device_token: self.get_option("trusted-device-token"),
+            public_key: public_key.into(),
+            key_signature: key_signature.into(),
             ..Default::default()
         };
         match self.conn_type {
@@ -1760,6 +2074,9 @@ impl LoginConfigHandler {
             }),
             _ => {}
         }
+        if let Some(wol) = self.wake_on_lan.clone() {
+            lr.set_wake_on_lan(wol);
+        }
 
         let mut msg_out = Message::new();
         msg_out.set_login_request(lr);
@@ -1825,11 +2142,13 @@ where
     let fps = Arc::new(AtomicUsize::new(0));
     let decode_fps = fps.clone();
     let mut skip_beginning = 0;
+    let sync = Arc::new(MediaSync::default());
+    let audio_sync = sync.clone();
 
     std::thread::spawn(move || {
         #[cfg(windows)]
         sync_cpu_usage();
-        let mut video_handler = VideoHandler::new();
+        let mut video_handler = VideoHandler::new(sync);
         loop {
             if let Ok(data) = video_receiver.recv() {
                 match data {
@@ -1844,8 +2163,12 @@ where
                             }
                         };
                         let start = std::time::Instant::now();
+                        let pts = VideoHandler::last_pts(&vf);
                         if let Ok(true) = video_handler.handle_frame(vf) {
                             video_callback(&mut video_handler.rgb);
+                            if let Some(pts) = pts {
+                                video_handler.sync.on_video_rendered(pts);
+                            }
                             // fps calculation
                             // The first frame will be very slow
                             if skip_beginning < 5 {
@@ -1881,23 +2204,41 @@ where
         }
         log::info!("Video decoder loop exits");
     });
-    let audio_sender = start_audio_thread();
+    let audio_sender = start_audio_thread(Some(audio_sync), None);
     return (video_sender, audio_sender, video_queue_cloned, decode_fps);
 }
 
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
-pub fn start_audio_thread() -> MediaSender {
+///
+/// # Arguments
+///
+/// * `sync` - Shared with the video thread to play audio in step with the video, `None` to play audio as it arrives.
+/// * `output_device` - The device to play on, `None` for the default output device.
+pub fn start_audio_thread(
+    sync: Option<Arc<MediaSync>>,
+    output_device: Option<String>,
+) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
-        let mut audio_handler = AudioHandler::default();
+        let mut audio_handler = AudioHandler::new(sync, output_device);
         loop {
-            if let Ok(data) = audio_receiver.recv() {
+            let data = if audio_handler.has_pending() {
+                match audio_receiver.recv_timeout(AUDIO_SYNC_INTERVAL) {
+                    Ok(data) => Ok(Some(data)),
+                    Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => Err(()),
+                }
+            } else {
+                audio_receiver.recv().map(Some).map_err(|_| ())
+            };
+            if let Ok(data) = data {
                 match data {
-                    MediaData::AudioFrame(af) => {
+                    None => audio_handler.play_due_frames(),
+                    Some(MediaData::AudioFrame(af)) => {
                         audio_handler.handle_frame(*af);
                     }
-                    MediaData::AudioFormat(f) => {
+                    Some(MediaData::AudioFormat(f)) => {
                         log::debug!("recved audio format, sample rate={}", f.sample_rate);
                         audio_handler.handle_format(f);
                     }
@@ -2208,6 +2549,12 @@ lazy_static::lazy_static! {
             text: "Please wait for the remote side to accept your session request...",
             link: "",
             try_again: true,
+        }), (LOGIN_MSG_STRICT_ENCRYPTION, LoginErrorMsgBox{
+            msgtype: "error",
+            title: "Login Error",
+            text: LOGIN_MSG_STRICT_ENCRYPTION,
+            link: "",
+            try_again: false,
         })]);
         Arc::new(map)
     };
@@ -2229,6 +2576,15 @@ pub fn handle_login_error(
         lc.write().unwrap().password = Default::default();
         interface.msgbox("re-input-password", err, "Do you want to enter again?", "");
         true
+    } else if err == LOGIN_MSG_2FA_REQUIRED || err == LOGIN_MSG_2FA_WRONG {
+        // The token is expired or revoked if the host still asks for the code.
+        if !lc.read().unwrap().get_option("trusted-device-token").is_empty() {
+            lc.write()
+                .unwrap()
+                .set_option("trusted-device-token".to_owned(), "".to_owned());
+        }
+        interface.msgbox("input-2fa", err, "", "");
+        true
     } else if LOGIN_ERROR_MAP.contains_key(err) {
         if let Some(msgbox_info) = LOGIN_ERROR_MAP.get(err) {
             interface.msgbox(
@@ -2310,8 +2666,11 @@ pub async fn handle_hash(
     }
     lc.write().unwrap().password = password.clone();
     let password = if password.is_empty() {
-        // login without password, the remote side can click accept
-        interface.msgbox("input-password", "Password Required", "", "");
+        // The host asks for the password if it does not trust our key.
+        if !hash.key_auth {
+            // login without password, the remote side can click accept
+            interface.msgbox("input-password", "Password Required", "", "");
+        }
         Vec::new()
     } else {
         let mut hasher = Sha256::new();
@@ -2505,6 +2864,8 @@ pub enum Data {
     ElevateWithLogon(String, String),
     NewVoiceCall,
     CloseVoiceCall,
+    #[cfg(target_os = "linux")]
+    ForwardGamepad(bool),
 }
 
 /// Keycode for key events.
@@ -2671,15 +3032,6 @@ fn get_pk(pk: &[u8]) -> Option<[u8; 32]> {
     }
 }
 
-#[inline]
-fn get_rs_pk(str_base64: &str) -> Option<sign::PublicKey> {
-    if let Ok(pk) = crate::decode64(str_base64) {
-        get_pk(&pk).map(|x| sign::PublicKey(x))
-    } else {
-        None
-    }
-}
-
 fn decode_id_pk(signed: &[u8], key: &sign::PublicKey) -> ResultType<(String, [u8; 32])> {
     let res = IdPk::parse_from_bytes(
         &sign::verify(signed, key).map_err(|_| anyhow!("Signature mismatch"))?,
@@ -2700,11 +3052,11 @@ fn create_symmetric_key_msg(their_pk_b: [u8; 32]) -> (Bytes, Bytes, secretbox::K
     (Vec::from(our_pk_b.0).into(), sealed_key.into(), key)
 }
 
-async fn secure_punch_connection(conn: &mut FramedStream, key: &str) -> ResultType<()> {
-    let rs_pk = get_rs_pk(key);
-    let Some(rs_pk) = rs_pk else {
+pub(crate) async fn secure_punch_connection(conn: &mut FramedStream, key: &str) -> ResultType<()> {
+    let rs_pks = crate::trusted_keys::valid_keys(key, &crate::trusted_keys::get_stored().await);
+    if rs_pks.is_empty() {
         bail!("Handshake failed: invalid public key from rendezvous server");
-    };
+    }
     match timeout(READ_TIMEOUT, conn.next()).await? {
         Some(Ok(bytes)) => {
             if let Ok(msg_in) = RendezvousMessage::parse_from_bytes(&bytes) {
@@ -2713,8 +3065,10 @@ async fn secure_punch_connection(conn: &mut FramedStream, key: &str) -> ResultTy
                         if ex.keys.len() != 1 {
                             bail!("Handshake failed: invalid key exchange message");
                         }
-                        let their_pk_b = sign::verify(&ex.keys[0], &rs_pk)
-                            .map_err(|_| anyhow!("Signature mismatch in key exchange"))?;
+                        let their_pk_b = rs_pks
+                            .iter()
+                            .find_map(|rs_pk| sign::verify(&ex.keys[0], rs_pk).ok())
+                            .context("Signature mismatch in key exchange")?;
                         let (asymmetric_value, symmetric_value, key) = create_symmetric_key_msg(
                             get_pk(&their_pk_b)
                                 .context("Wrong their public length in key exchange")?,
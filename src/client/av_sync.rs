@@ -0,0 +1,178 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hbb_common::message_proto::{AudioFormat, AudioFrame};
+use scrap::record::Recorder;
+
+// Audio may be played this much ahead of the rendered video.
+const MAX_AUDIO_LEAD_MS: i64 = 20;
+// Audio older than this, relative to the rendered video, is dropped to catch up.
+const MAX_AUDIO_LATE_MS: i64 = 300;
+// Stop waiting for video if more audio than this is buffered.
+const MAX_BUFFERED_MS: i64 = 1000;
+// Without a rendered video frame for this long, audio is played as it arrives.
+const VIDEO_CLOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State shared by the video and audio decoder threads of one session.
+#[derive(Default)]
+pub struct MediaSync {
+    video: Mutex<Option<(i64, Instant)>>,
+    audio_format: Mutex<Option<AudioFormat>>,
+    pub recorder: Arc<Mutex<Option<Recorder>>>,
+}
+
+impl MediaSync {
+    /// Called by the video thread after a frame with `pts` is rendered.
+    #[inline]
+    pub fn on_video_rendered(&self, pts: i64) {
+        *self.video.lock().unwrap() = Some((pts, Instant::now()));
+    }
+
+    #[inline]
+    pub fn reset_video(&self) {
+        *self.video.lock().unwrap() = None;
+    }
+
+    /// The pts currently shown on the screen, extrapolated from the last rendered frame,
+    /// since no frames are sent while the screen is still.
+    pub fn video_pts(&self) -> Option<i64> {
+        match *self.video.lock().unwrap() {
+            Some((pts, tm)) if tm.elapsed() < VIDEO_CLOCK_TIMEOUT => {
+                Some(pts + tm.elapsed().as_millis() as i64)
+            }
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn set_audio_format(&self, format: AudioFormat) {
+        *self.audio_format.lock().unwrap() = Some(format);
+    }
+
+    #[inline]
+    pub fn audio_format(&self) -> Option<AudioFormat> {
+        self.audio_format.lock().unwrap().clone()
+    }
+}
+
+/// Holds audio frames until the video with the same pts is rendered.
+#[derive(Default)]
+pub struct AudioJitterBuffer {
+    frames: VecDeque<AudioFrame>,
+}
+
+impl AudioJitterBuffer {
+    pub fn push(&mut self, frame: AudioFrame) {
+        // Frames almost always arrive in order, search from the back.
+        let pos = self
+            .frames
+            .iter()
+            .rposition(|f| f.pts <= frame.pts)
+            .map_or(0, |i| i + 1);
+        self.frames.insert(pos, frame);
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn buffered_ms(&self) -> i64 {
+        let pts = |f: Option<&AudioFrame>| f.and_then(|f| f.pts);
+        match (pts(self.frames.front()), pts(self.frames.back())) {
+            (Some(front), Some(back)) => back - front,
+            _ => 0,
+        }
+    }
+
+    /// Pop the frames due for playback when `video_pts` is on the screen.
+    ///
+    /// Frames without pts, or all frames if there is no video clock, are due immediately.
+    pub fn pop(&mut self, video_pts: Option<i64>) -> Vec<AudioFrame> {
+        let mut due = vec![];
+        while let Some(frame) = self.frames.front() {
+            let ready = match (video_pts, frame.pts) {
+                (Some(v), Some(pts)) => {
+                    if pts < v - MAX_AUDIO_LATE_MS {
+                        self.frames.pop_front();
+                        continue;
+                    }
+                    pts <= v + MAX_AUDIO_LEAD_MS || self.buffered_ms() > MAX_BUFFERED_MS
+                }
+                _ => true,
+            };
+            if !ready {
+                break;
+            }
+            if let Some(frame) = self.frames.pop_front() {
+                due.push(frame);
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pts: i64) -> AudioFrame {
+        AudioFrame {
+            pts: Some(pts),
+            ..Default::default()
+        }
+    }
+
+    fn pts(frames: Vec<AudioFrame>) -> Vec<i64> {
+        frames.iter().filter_map(|f| f.pts).collect()
+    }
+
+    #[test]
+    fn test_jitter_buffer_follows_video() {
+        let mut buf = AudioJitterBuffer::default();
+        for p in [1010, 1000, 1030, 1020] {
+            buf.push(frame(p));
+        }
+        assert_eq!(pts(buf.pop(Some(990))), vec![1000, 1010]);
+        assert_eq!(pts(buf.pop(Some(1010))), vec![1020, 1030]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_jitter_buffer_drops_late_audio() {
+        let mut buf = AudioJitterBuffer::default();
+        buf.push(frame(100));
+        buf.push(frame(900));
+        assert_eq!(pts(buf.pop(Some(1000))), vec![900]);
+    }
+
+    #[test]
+    fn test_jitter_buffer_without_clock() {
+        let mut buf = AudioJitterBuffer::default();
+        buf.push(frame(5000));
+        buf.push(frame(0));
+        assert_eq!(pts(buf.pop(None)), vec![0, 5000]);
+        // 0 is a valid capture time, only frames without pts are due immediately.
+        buf.push(frame(0));
+        assert!(buf.pop(Some(5000)).is_empty());
+        buf.push(AudioFrame::default());
+        assert_eq!(buf.pop(Some(10)).len(), 1);
+    }
+
+    #[test]
+    fn test_jitter_buffer_limit() {
+        let mut buf = AudioJitterBuffer::default();
+        for p in (0..=MAX_BUFFERED_MS + 10).step_by(10) {
+            buf.push(frame(10_000 + p));
+        }
+        assert!(!buf.pop(Some(0)).is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+//! Forward the local gamepads to the peer.
+//!
+//! Gamepads are read from the evdev nodes, so the user must be allowed to read `/dev/input/event*`,
+//! usually by being in the `input` group.
+
+use std::{
+    collections::HashMap,
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc as std_mpsc, Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use evdev_ff::{
+    AbsoluteAxisType, Device, FFEffect, FFEffectData, FFEffectKind, FFReplay, FFTrigger,
+    InputEventKind, Key,
+};
+use hbb_common::{libc, log, message_proto::*, tokio::sync::mpsc};
+
+use super::Data;
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_TIMEOUT_MS: i32 = 100;
+// Do not forward the virtual gamepads created by our own uinput service.
+const VIRTUAL_GAMEPAD_PREFIX: &str = "RustDesk UInput";
+
+type RumbleSenders = Arc<Mutex<HashMap<u32, std_mpsc::Sender<GamepadRumble>>>>;
+
+pub struct GamepadForwarder {
+    stop: Arc<AtomicBool>,
+    rumble_senders: RumbleSenders,
+}
+
+impl GamepadForwarder {
+    pub fn start(sender: mpsc::UnboundedSender<Data>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let rumble_senders: RumbleSenders = Default::default();
+        let stop_cloned = stop.clone();
+        let rumble_senders_cloned = rumble_senders.clone();
+        std::thread::spawn(move || {
+            log::info!("Start forwarding gamepads");
+            let next_index = Arc::new(AtomicU32::new(0));
+            let opened: Arc<Mutex<Vec<PathBuf>>> = Default::default();
+            while !stop_cloned.load(Ordering::SeqCst) {
+                for (path, device) in evdev_ff::enumerate() {
+                    if !is_gamepad(&device) || opened.lock().unwrap().contains(&path) {
+                        continue;
+                    }
+                    let index = next_index.fetch_add(1, Ordering::SeqCst);
+                    log::info!(
+                        "Forward gamepad {} {:?} as {}",
+                        device.name().unwrap_or_default(),
+                        path,
+                        index
+                    );
+                    let (tx, rx) = std_mpsc::channel();
+                    rumble_senders_cloned.lock().unwrap().insert(index, tx);
+                    opened.lock().unwrap().push(path.clone());
+                    let gamepad = Gamepad::new(index, device, sender.clone());
+                    let stop = stop_cloned.clone();
+                    let opened = opened.clone();
+                    let rumble_senders = rumble_senders_cloned.clone();
+                    std::thread::spawn(move || {
+                        gamepad.run(stop, rx);
+                        opened.lock().unwrap().retain(|p| p != &path);
+                        rumble_senders.lock().unwrap().remove(&index);
+                    });
+                }
+                std::thread::sleep(SCAN_INTERVAL);
+            }
+            log::info!("Stop forwarding gamepads");
+        });
+        Self {
+            stop,
+            rumble_senders,
+        }
+    }
+
+    pub fn rumble(&self, rumble: GamepadRumble) {
+        if let Some(tx) = self.rumble_senders.lock().unwrap().get(&rumble.index) {
+            tx.send(rumble).ok();
+        }
+    }
+}
+
+impl Drop for GamepadForwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn is_gamepad(device: &Device) -> bool {
+    if device
+        .name()
+        .map_or(false, |n| n.starts_with(VIRTUAL_GAMEPAD_PREFIX))
+    {
+        return false;
+    }
+    device
+        .supported_keys()
+        .map_or(false, |keys| keys.contains(Key::BTN_SOUTH))
+}
+
+fn map_button(key: Key) -> Option<GamepadButton> {
+    Some(match key {
+        Key::BTN_SOUTH => GamepadButton::South,
+        Key::BTN_EAST => GamepadButton::East,
+        Key::BTN_NORTH => GamepadButton::North,
+        Key::BTN_WEST => GamepadButton::West,
+        Key::BTN_TL => GamepadButton::LeftBumper,
+        Key::BTN_TR => GamepadButton::RightBumper,
+        Key::BTN_SELECT => GamepadButton::Back,
+        Key::BTN_START => GamepadButton::Start,
+        Key::BTN_MODE => GamepadButton::Guide,
+        Key::BTN_THUMBL => GamepadButton::LeftThumb,
+        Key::BTN_THUMBR => GamepadButton::RightThumb,
+        _ => return None,
+    })
+}
+
+fn map_axis(axis: AbsoluteAxisType) -> Option<GamepadAxis> {
+    Some(match axis {
+        AbsoluteAxisType::ABS_X => GamepadAxis::LeftX,
+        AbsoluteAxisType::ABS_Y => GamepadAxis::LeftY,
+        AbsoluteAxisType::ABS_RX => GamepadAxis::RightX,
+        AbsoluteAxisType::ABS_RY => GamepadAxis::RightY,
+        AbsoluteAxisType::ABS_Z => GamepadAxis::LeftTrigger,
+        AbsoluteAxisType::ABS_RZ => GamepadAxis::RightTrigger,
+        _ => return None,
+    })
+}
+
+/// Scale `value` in [min, max] to [-32768, 32767] for sticks, or [0, 32767] for triggers.
+fn normalize_axis(axis: GamepadAxis, value: i32, min: i32, max: i32) -> i32 {
+    if max <= min {
+        return 0;
+    }
+    let (min, max, value) = (min as i64, max as i64, value.clamp(min, max) as i64);
+    match axis {
+        GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => {
+            ((value - min) * i16::MAX as i64 / (max - min)) as i32
+        }
+        _ => ((value - min) * u16::MAX as i64 / (max - min) + i16::MIN as i64) as i32,
+    }
+}
+
+struct Gamepad {
+    index: u32,
+    device: Device,
+    sender: mpsc::UnboundedSender<Data>,
+    // (min, max) of the axes, indexed by the axis code.
+    abs_ranges: HashMap<u16, (i32, i32)>,
+    hat: (i32, i32),
+    effect: Option<FFEffect>,
+}
+
+impl Gamepad {
+    fn new(index: u32, device: Device, sender: mpsc::UnboundedSender<Data>) -> Self {
+        let mut abs_ranges = HashMap::new();
+        if let (Some(axes), Ok(state)) = (device.supported_absolute_axes(), device.get_abs_state())
+        {
+            for axis in axes.iter() {
+                let info = &state[axis.0 as usize];
+                abs_ranges.insert(axis.0, (info.minimum, info.maximum));
+            }
+        }
+        Self {
+            index,
+            device,
+            sender,
+            abs_ranges,
+            hat: (0, 0),
+            effect: None,
+        }
+    }
+
+    fn send(&self, union: gamepad_event::Union) {
+        let mut evt = GamepadEvent::new();
+        evt.index = self.index;
+        evt.union = Some(union);
+        let mut msg = Message::new();
+        msg.set_gamepad_event(evt);
+        self.sender.send(Data::Message(msg)).ok();
+    }
+
+    fn run(mut self, stop: Arc<AtomicBool>, rumble_rx: std_mpsc::Receiver<GamepadRumble>) {
+        let fd = self.device.as_raw_fd();
+        self.send(gamepad_event::Union::Connected(true));
+        while !stop.load(Ordering::SeqCst) {
+            while let Ok(rumble) = rumble_rx.try_recv() {
+                self.rumble(rumble);
+            }
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let n = unsafe { libc::poll(&mut pfd, 1, POLL_TIMEOUT_MS) };
+            if n <= 0 {
+                continue;
+            }
+            if pfd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                break;
+            }
+            let events: Vec<_> = match self.device.fetch_events() {
+                Ok(events) => events.collect(),
+                Err(e) => {
+                    log::info!("Gamepad {} is gone: {}", self.index, e);
+                    break;
+                }
+            };
+            for evt in events {
+                match evt.kind() {
+                    InputEventKind::Key(key) => {
+                        let hat = match key {
+                            Key::BTN_DPAD_LEFT => Some((-evt.value(), self.hat.1)),
+                            Key::BTN_DPAD_RIGHT => Some((evt.value(), self.hat.1)),
+                            Key::BTN_DPAD_UP => Some((self.hat.0, -evt.value())),
+                            Key::BTN_DPAD_DOWN => Some((self.hat.0, evt.value())),
+                            _ => None,
+                        };
+                        if let Some(hat) = hat {
+                            self.send_hat(hat);
+                        } else if let Some(button) = map_button(key) {
+                            let mut e = GamepadButtonEvent::new();
+                            e.button = button.into();
+                            // 2 is auto repeat.
+                            e.down = evt.value() != 0;
+                            self.send(gamepad_event::Union::Button(e));
+                        }
+                    }
+                    InputEventKind::AbsAxis(axis) => match axis {
+                        AbsoluteAxisType::ABS_HAT0X => self.send_hat((evt.value(), self.hat.1)),
+                        AbsoluteAxisType::ABS_HAT0Y => self.send_hat((self.hat.0, evt.value())),
+                        _ => {
+                            if let (Some(a), Some((min, max))) =
+                                (map_axis(axis), self.abs_ranges.get(&axis.0))
+                            {
+                                let mut e = GamepadAxisEvent::new();
+                                e.axis = a.into();
+                                e.value = normalize_axis(a, evt.value(), *min, *max);
+                                self.send(gamepad_event::Union::Axis(e));
+                            }
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+        self.send(gamepad_event::Union::Connected(false));
+    }
+
+    fn send_hat(&mut self, hat: (i32, i32)) {
+        let hat = (hat.0.signum(), hat.1.signum());
+        if hat == self.hat {
+            return;
+        }
+        self.hat = hat;
+        let mut e = GamepadHatEvent::new();
+        e.x = hat.0;
+        e.y = hat.1;
+        self.send(gamepad_event::Union::Hat(e));
+    }
+
+    fn rumble(&mut self, rumble: GamepadRumble) {
+        if rumble.strong_magnitude == 0 && rumble.weak_magnitude == 0 {
+            if let Some(effect) = self.effect.as_mut() {
+                effect.stop().ok();
+            }
+            return;
+        }
+        let data = FFEffectData {
+            direction: 0,
+            trigger: FFTrigger::default(),
+            replay: FFReplay {
+                length: rumble.duration_ms.min(u16::MAX as _) as _,
+                delay: 0,
+            },
+            kind: FFEffectKind::Rumble {
+                strong_magnitude: rumble.strong_magnitude.min(u16::MAX as _) as _,
+                weak_magnitude: rumble.weak_magnitude.min(u16::MAX as _) as _,
+            },
+        };
+        let res = match self.effect.as_mut() {
+            Some(effect) => effect.update(data),
+            None => self.device.upload_ff_effect(data).map(|effect| {
+                self.effect = Some(effect);
+            }),
+        };
+        match res {
+            Ok(()) => {
+                if let Some(effect) = self.effect.as_mut() {
+                    effect.play(1).ok();
+                }
+            }
+            Err(e) => log::debug!("Gamepad {} does not support rumble: {}", self.index, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_axis() {
+        assert_eq!(normalize_axis(GamepadAxis::LeftX, 0, 0, 255), -32768);
+        assert_eq!(normalize_axis(GamepadAxis::LeftX, 255, 0, 255), 32767);
+        assert_eq!(
+            normalize_axis(GamepadAxis::LeftX, -32768, -32768, 32767),
+            -32768
+        );
+        assert_eq!(
+            normalize_axis(GamepadAxis::RightTrigger, 1023, 0, 1023),
+            32767
+        );
+        assert_eq!(normalize_axis(GamepadAxis::RightTrigger, 0, 0, 1023), 0);
+        assert_eq!(normalize_axis(GamepadAxis::LeftY, 5, 0, 0), 0);
+    }
+}
@@ -66,6 +66,8 @@ pub struct Remote<T: InvokeUiSession> {
     elevation_requested: bool,
     fps_control: FpsControl,
     decode_fps: Arc<AtomicUsize>,
+    #[cfg(target_os = "linux")]
+    gamepad: Option<super::gamepad::GamepadForwarder>,
 }
 
 impl<T: InvokeUiSession> Remote<T> {
@@ -103,6 +105,8 @@ impl<T: InvokeUiSession> Remote<T> {
             elevation_requested: false,
             fps_control: Default::default(),
             decode_fps,
+            #[cfg(target_os = "linux")]
+            gamepad: None,
         }
     }
 
@@ -338,6 +342,19 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    fn toggle_gamepad_forwarding(&mut self, on: bool) {
+        if on {
+            if self.gamepad.is_none() {
+                self.gamepad = Some(super::gamepad::GamepadForwarder::start(
+                    self.sender.clone(),
+                ));
+            }
+        } else {
+            self.gamepad.take();
+        }
+    }
+
     fn stop_voice_call(&mut self) {
         let voice_call_sender = std::mem::replace(&mut self.stop_voice_call_sender, None);
         if let Some(stopper) = voice_call_sender {
@@ -806,6 +823,10 @@ impl<T: InvokeUiSession> Remote<T> {
                     .on_voice_call_closed("Closed manually by the peer");
                 allow_err!(peer.send(&msg).await);
             }
+            #[cfg(target_os = "linux")]
+            Data::ForwardGamepad(on) => {
+                self.toggle_gamepad_forwarding(on);
+            }
             _ => {}
         }
         true
@@ -1018,7 +1039,40 @@ impl<T: InvokeUiSession> Remote<T> {
                         self.video_sender.send(MediaData::VideoQueue).ok();
                     }
                 }
+                Some(message::Union::SignedId(si)) => {
+                    // Hosts connected by address sign with their own key, see `secure_direct_connection`.
+                    if !si.pk.is_empty() && !peer.is_secured() && Client::is_address(&self.handler.id)
+                    {
+                        match Client::secure_direct_connection(&self.handler.id, si, peer).await {
+                            Ok(pk) => {
+                                self.handler.set_connection_type(peer.is_secured(), true);
+                                self.handler
+                                    .set_fingerprint(crate::common::pk_to_fingerprint(pk));
+                            }
+                            Err(err) => {
+                                self.handler
+                                    .msgbox("error", "Connection Error", &err.to_string(), "");
+                                return false;
+                            }
+                        }
+                    }
+                }
                 Some(message::Union::Hash(hash)) => {
+                    if let Err(err) = Client::check_pinned_key(&self.handler.id, peer.is_secured())
+                    {
+                        self.handler
+                            .msgbox("error", "Connection Error", &err.to_string(), "");
+                        return false;
+                    }
+                    if crate::common::is_strict_encryption() && !peer.is_secured() {
+                        self.handler.msgbox(
+                            "error",
+                            "Connection Error",
+                            crate::client::LOGIN_MSG_STRICT_ENCRYPTION,
+                            "",
+                        );
+                        return false;
+                    }
                     self.handler
                         .handle_hash(&self.handler.password.clone(), hash, peer)
                         .await;
@@ -1030,10 +1084,20 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                     Some(login_response::Union::PeerInfo(pi)) => {
+                        if !lr.trusted_device_token.is_empty() {
+                            self.handler.lc.write().unwrap().set_option(
+                                "trusted-device-token".to_owned(),
+                                lr.trusted_device_token,
+                            );
+                        }
                         self.handler.handle_peer_info(pi);
                         #[cfg(not(feature = "flutter"))]
                         self.check_clipboard_file_context();
                         if !(self.handler.is_file_transfer() || self.handler.is_port_forward()) {
+                            #[cfg(target_os = "linux")]
+                            if self.handler.get_toggle_option("forward-gamepad".to_owned()) {
+                                self.toggle_gamepad_forwarding(true);
+                            }
                             #[cfg(feature = "flutter")]
                             #[cfg(not(any(target_os = "android", target_os = "ios")))]
                             Client::try_start_clipboard(None);
@@ -1403,6 +1467,9 @@ impl<T: InvokeUiSession> Remote<T> {
                             );
                         }
                     }
+                    Some(misc::Union::AudioInputs(inputs)) => {
+                        self.handler.set_audio_inputs(&inputs);
+                    }
                     Some(misc::Union::SwitchBack(_)) => {
                         #[cfg(feature = "flutter")]
                         self.handler.switch_back(&self.handler.id);
@@ -1492,6 +1559,12 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::PeerInfo(pi)) => {
                     self.handler.set_displays(&pi.displays);
                 }
+                #[cfg(target_os = "linux")]
+                Some(message::Union::GamepadRumble(rumble)) => {
+                    if let Some(gamepad) = self.gamepad.as_ref() {
+                        gamepad.rumble(rumble);
+                    }
+                }
                 _ => {}
             }
         }
@@ -562,8 +562,10 @@ async fn test_nat_type_() -> ResultType<bool> {
     let is_direct = crate::ipc::get_socks_async(1_000).await.is_none(); // sync socks BTW
     #[cfg(any(target_os = "android", target_os = "ios"))]
     let is_direct = Config::get_socks().is_none(); // sync socks BTW
-    if !is_direct {
+    if !is_direct || hbb_common::websocket::is_enabled() {
+        // Neither a proxy nor WebSocket can punch holes.
         Config::set_nat_type(NatType::SYMMETRIC as _);
+        Config::set_nat_info(nat_info::Class::SYMMETRIC as _, 0);
         return Ok(true);
     }
     let start = std::time::Instant::now();
@@ -606,6 +608,7 @@ async fn test_nat_type_() -> ResultType<bool> {
                         cu.rendezvous_servers.join(","),
                     );
                     Config::set_serial(cu.serial);
+                    crate::trusted_keys::update(&cu.trusted_keys);
                 }
             }
         } else {
@@ -614,13 +617,16 @@ async fn test_nat_type_() -> ResultType<bool> {
     }
     let ok = port1 > 0 && port2 > 0;
     if ok {
-        let t = if port1 == port2 {
-            NatType::ASYMMETRIC
-        } else {
-            NatType::SYMMETRIC
-        };
+        let (t, info) = crate::nat::classify(port1, port2).await;
         Config::set_nat_type(t as _);
-        log::info!("Tested nat type: {:?} in {:?}", t, start.elapsed());
+        Config::set_nat_info(info.class.value(), info.port_delta);
+        log::info!(
+            "Tested nat type: {:?}, {:?}, port delta: {} in {:?}",
+            t,
+            info.class,
+            info.port_delta,
+            start.elapsed()
+        );
     }
     Ok(ok)
 }
@@ -677,6 +683,18 @@ pub async fn get_nat_type(ms_timeout: u64) -> i32 {
     crate::ipc::get_nat_type(ms_timeout).await
 }
 
+#[inline]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub async fn get_nat_info(_ms_timeout: u64) -> NatInfo {
+    crate::nat::from_config(Config::get_nat_info())
+}
+
+#[inline]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub async fn get_nat_info(ms_timeout: u64) -> NatInfo {
+    crate::nat::from_config(crate::ipc::get_nat_info(ms_timeout).await)
+}
+
 // used for client to test which server is faster in case stop-servic=Y
 #[tokio::main(flavor = "current_thread")]
 async fn test_rendezvous_server_() {
@@ -878,8 +896,9 @@ pub fn get_custom_rendezvous_server(custom: String) -> String {
             return lic.host.clone();
         }
     }
-    if !custom.is_empty() {
-        return custom;
+    // The first of a list of servers.
+    if let Some((host, _)) = config::parse_rendezvous_servers(&custom).into_iter().next() {
+        return host;
     }
     if !config::PROD_RENDEZVOUS_SERVER.read().unwrap().is_empty() {
         return config::PROD_RENDEZVOUS_SERVER.read().unwrap().clone();
@@ -921,8 +940,57 @@ pub fn get_audit_server(api: String, custom: String, typ: String) -> String {
     format!("{}/api/audit/{}", url, typ)
 }
 
+// The proxy configured for the host of `url`.
+async fn http_proxy(url: &str) -> ResultType<Option<reqwest::Proxy>> {
+    let parsed = url::Url::parse(url)?;
+    let (Some(host), Some(port)) = (parsed.host_str(), parsed.port_or_known_default()) else {
+        return Ok(None);
+    };
+    match hbb_common::proxy::for_target(&format!("{host}:{port}")).await {
+        Some(hbb_common::proxy::Proxy::Http(conf)) => {
+            let mut proxy = reqwest::Proxy::all(&conf.proxy)?;
+            if !conf.username.is_empty() {
+                proxy = proxy.basic_auth(&conf.username, &conf.password);
+            }
+            Ok(Some(proxy))
+        }
+        Some(hbb_common::proxy::Proxy::Socks5(conf)) => {
+            let mut proxy = url::Url::parse(&format!("socks5h://{}", conf.proxy))?;
+            if !conf.username.is_empty() {
+                proxy.set_username(&conf.username).ok();
+                proxy.set_password(Some(&conf.password)).ok();
+            }
+            Ok(Some(reqwest::Proxy::all(proxy)?))
+        }
+        None => Ok(None),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn http_proxy_sync(url: &str) -> ResultType<Option<reqwest::Proxy>> {
+    http_proxy(url).await
+}
+
+// The client for `url`, with the proxy configured for its host.
+async fn create_http_client(url: &str) -> ResultType<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = http_proxy(url).await? {
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+/// Like `create_http_client`, for a blocking client. Not to be called within a runtime.
+pub fn create_http_client_sync(url: &str) -> ResultType<reqwest::blocking::Client> {
+    let mut builder = reqwest::blocking::Client::builder();
+    if let Some(proxy) = http_proxy_sync(url)? {
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
 pub async fn post_request(url: String, body: String, header: &str) -> ResultType<String> {
-    let mut req = reqwest::Client::new().post(url);
+    let mut req = create_http_client(&url).await?.post(url);
     if !header.is_empty() {
         let tmp: Vec<&str> = header.split(": ").collect();
         if tmp.len() == 2 {
@@ -1028,6 +1096,12 @@ pub fn decode64<T: AsRef<[u8]>>(input: T) -> Result<Vec<u8>, base64::DecodeError
     base64::decode(input)
 }
 
+/// The message a client signs to log in with its key, bound to both ids against replaying.
+#[inline]
+pub fn key_auth_message(challenge: &str, client_id: &str, host_id: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}", challenge, client_id, host_id).into_bytes()
+}
+
 pub async fn get_key(sync: bool) -> String {
     #[cfg(windows)]
     if let Ok(lic) = crate::platform::windows::get_license_from_exe_name() {
@@ -1050,6 +1124,12 @@ pub async fn get_key(sync: bool) -> String {
     key
 }
 
+/// Refuse sessions that are not end-to-end encrypted, both as the client and as the host.
+#[inline]
+pub fn is_strict_encryption() -> bool {
+    Config::get_option("strict-encryption") == "Y"
+}
+
 pub fn pk_to_fingerprint(pk: Vec<u8>) -> String {
     let s: String = pk.iter().map(|u| format!("{:02x}", u)).collect();
     s.chars()
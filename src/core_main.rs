@@ -290,6 +290,13 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--diagnose" {
+            if args.len() == 2 {
+                println!("{}", crate::ui_interface::diagnose(args[1].to_owned()));
+            } else {
+                println!("Usage: --diagnose ID");
+            }
+            return None;
         } else if args[0] == "--set-id" {
             if args.len() == 2 {
                 if crate::platform::is_installed() && is_root() {
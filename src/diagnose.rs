@@ -0,0 +1,394 @@
+//! Diagnostics of the connection path to a peer, for `--diagnose ID`.
+//!
+//! The steps of `Client::start` are taken one by one and timed, and the report is written as
+//! JSON. The peer sees a connection which is closed after the handshake, before login.
+
+#[cfg(feature = "quic")]
+use crate::client::UDP_PUNCH_TIMEOUT;
+use crate::client::{secure_punch_connection, Client};
+#[cfg(feature = "quic")]
+use hbb_common::quic;
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    config::{Config, LanPeers, LocalConfig, CONNECT_TIMEOUT, RELAY_PORT, RENDEZVOUS_PORT},
+    futures::future::select_ok,
+    log,
+    protobuf::Enum,
+    rendezvous_proto::*,
+    socket_client,
+    tcp::FramedStream,
+    tokio::net::UdpSocket,
+    AddrMangle, ResultType,
+};
+use serde_derive::Serialize;
+use std::{future::Future, net::SocketAddr, time::Instant};
+
+const PUNCH_TIMEOUT: u64 = 6_000;
+const DIRECT_PORT_TIMEOUT: u64 = 1_000;
+// Any global address, nothing is sent to it.
+const IPV6_PROBE: &str = "[2001:4860:4860::8888]:53";
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub id: String,
+    pub version: String,
+    pub network: String,
+    /// "direct", "relay", or "failed".
+    pub path: String,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Step {
+    pub name: &'static str,
+    pub ok: bool,
+    pub ms: u64,
+    /// The result, or the error.
+    pub detail: String,
+}
+
+impl Report {
+    async fn step<T>(
+        &mut self,
+        name: &'static str,
+        f: impl Future<Output = ResultType<(T, String)>>,
+    ) -> Option<T> {
+        let start = Instant::now();
+        let res = f.await;
+        let ms = start.elapsed().as_millis() as _;
+        let (value, step) = match res {
+            Ok((value, detail)) => (
+                Some(value),
+                Step {
+                    name,
+                    ok: true,
+                    ms,
+                    detail,
+                },
+            ),
+            Err(err) => (
+                None,
+                Step {
+                    name,
+                    ok: false,
+                    ms,
+                    detail: err.to_string(),
+                },
+            ),
+        };
+        log::info!("diagnose {}: {:?}", self.id, step);
+        self.steps.push(step);
+        value
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+// What the rendezvous server answered the punch hole request with.
+enum Punched {
+    Direct {
+        peer_addr: SocketAddr,
+        local_addr: SocketAddr,
+        nat_info: NatInfo,
+        peer_udp_addr: Option<SocketAddr>,
+        relay_server: String,
+        pk: Vec<u8>,
+    },
+    // The peer can not be punched to and has connected to the relay server already.
+    Relay {
+        uuid: String,
+        relay_server: String,
+        pk: Vec<u8>,
+        ipv4: bool,
+    },
+}
+
+pub async fn diagnose(id: &str) -> Report {
+    let mut report = Report {
+        id: id.to_owned(),
+        version: crate::VERSION.to_owned(),
+        network: format!("{:?}", Config::get_network_type()),
+        path: "failed".to_owned(),
+        steps: Vec::new(),
+    };
+    for host in Config::get_rendezvous_servers() {
+        let latency = report
+            .step("rendezvous", async {
+                let start = Instant::now();
+                socket_client::connect_tcp(
+                    crate::check_port(&host, RENDEZVOUS_PORT),
+                    CONNECT_TIMEOUT,
+                )
+                .await?;
+                let latency = start.elapsed().as_micros() as i64;
+                Ok((latency, host.clone()))
+            })
+            .await;
+        Config::update_latency(&host, latency.unwrap_or(-1));
+    }
+    report
+        .step("nat", async {
+            let nat_type = crate::get_nat_type(1_000).await;
+            let info = crate::get_nat_info(1_000).await;
+            if nat_type == NatType::UNKNOWN_NAT as i32 {
+                bail!("Not tested yet");
+            }
+            let nat_type = NatType::from_i32(nat_type).unwrap_or(NatType::UNKNOWN_NAT);
+            Ok((
+                (),
+                format!(
+                    "{:?}, {:?}, port delta {}",
+                    nat_type,
+                    info.class.enum_value_or_default(),
+                    info.port_delta
+                ),
+            ))
+        })
+        .await;
+    report
+        .step("ipv6", async {
+            let socket = UdpSocket::bind("[::]:0").await?;
+            socket.connect(IPV6_PROBE).await?;
+            Ok(((), socket.local_addr()?.ip().to_string()))
+        })
+        .await;
+    let key = crate::get_key(true).await;
+    let token = LocalConfig::get_option("access_token");
+    let (rendezvous_server, _, _) = crate::get_rendezvous_server(1_000).await;
+    let udp = if crate::nat::can_punch_udp() {
+        report
+            .step("udp_endpoint", async {
+                let (socket, addr) = crate::nat::udp_endpoint().await?;
+                Ok(((socket, addr), addr.to_string()))
+            })
+            .await
+    } else {
+        None
+    };
+    let udp_addr = udp.as_ref().map(|(_, addr)| *addr);
+    let Some(punched) = report
+        .step(
+            "punch_hole",
+            punch_hole(id, &rendezvous_server, &key, &token, udp_addr),
+        )
+        .await
+    else {
+        return report;
+    };
+    let (conn, pk) = match punched {
+        Punched::Direct {
+            peer_addr,
+            local_addr,
+            nat_info,
+            peer_udp_addr,
+            relay_server,
+            pk,
+        } => {
+            report
+                .step("direct_port", async {
+                    // The port the peer advertises in the LAN, the default one otherwise.
+                    let port = LanPeers::load()
+                        .peers
+                        .iter()
+                        .find(|p| p.id == id && p.direct_port > 0)
+                        .map_or(RELAY_PORT + 1, |p| p.direct_port);
+                    let target = SocketAddr::new(peer_addr.ip(), port as _);
+                    socket_client::connect_tcp(target, DIRECT_PORT_TIMEOUT).await?;
+                    Ok(((), target.to_string()))
+                })
+                .await;
+            // The same order as `Client::connect`, the predicted ports, then QUIC.
+            let mut conn = report
+                .step("direct", async {
+                    let (conn, _) = select_ok(
+                        crate::nat::candidate_addrs(peer_addr, &nat_info)
+                            .into_iter()
+                            .map(|addr| {
+                                Box::pin(async move {
+                                    let conn = socket_client::connect_tcp_local(
+                                        addr,
+                                        Some(local_addr),
+                                        CONNECT_TIMEOUT,
+                                    )
+                                    .await?;
+                                    ResultType::Ok((conn, addr.to_string()))
+                                })
+                            }),
+                    )
+                    .await?;
+                    Ok(conn)
+                })
+                .await;
+            #[cfg(feature = "quic")]
+            if let (true, Some((socket, _)), Some(peer_udp_addr)) =
+                (conn.is_none(), udp, peer_udp_addr)
+            {
+                conn = report
+                    .step("quic", async {
+                        let conn = quic::connect(socket, peer_udp_addr, UDP_PUNCH_TIMEOUT).await?;
+                        Ok((conn, peer_udp_addr.to_string()))
+                    })
+                    .await;
+            }
+            #[cfg(not(feature = "quic"))]
+            let _ = (udp, peer_udp_addr);
+            if conn.is_some() {
+                report.path = "direct".to_owned();
+            } else if !relay_server.is_empty() {
+                conn = report
+                    .step("relay", async {
+                        let conn = Client::request_relay(
+                            id,
+                            relay_server.clone(),
+                            &rendezvous_server,
+                            !pk.is_empty(),
+                            &key,
+                            &token,
+                            ConnType::DEFAULT_CONN,
+                        )
+                        .await?;
+                        Ok((conn, relay_server.clone()))
+                    })
+                    .await;
+                if conn.is_some() {
+                    report.path = "relay".to_owned();
+                }
+            }
+            (conn, pk)
+        }
+        Punched::Relay {
+            uuid,
+            relay_server,
+            pk,
+            ipv4,
+        } => {
+            let conn = report
+                .step("relay", async {
+                    let conn = Client::create_relay(
+                        id,
+                        uuid,
+                        relay_server.clone(),
+                        &key,
+                        ConnType::DEFAULT_CONN,
+                        ipv4,
+                    )
+                    .await?;
+                    Ok((conn, format!("{}, requested by the peer", relay_server)))
+                })
+                .await;
+            if conn.is_some() {
+                report.path = "relay".to_owned();
+            }
+            (conn, pk)
+        }
+    };
+    let Some(mut conn) = conn else {
+        report.path = "failed".to_owned();
+        return report;
+    };
+    let secured = report
+        .step("secure", async {
+            let verified = !pk.is_empty();
+            let pk = Client::secure_connection(id, pk, &key, &mut conn).await?;
+            let detail = match (verified, pk.is_some()) {
+                (true, true) => "encrypted, peer key signed by the rendezvous server",
+                (_, false) => "not encrypted",
+                (false, true) => "encrypted",
+            };
+            Ok(((), detail.to_owned()))
+        })
+        .await;
+    if secured.is_none() {
+        report.path = "failed".to_owned();
+    }
+    report
+}
+
+async fn punch_hole(
+    id: &str,
+    rendezvous_server: &str,
+    key: &str,
+    token: &str,
+    udp_addr: Option<SocketAddr>,
+) -> ResultType<(Punched, String)> {
+    let mut socket = socket_client::connect_tcp(rendezvous_server, CONNECT_TIMEOUT).await?;
+    if !key.is_empty() && !token.is_empty() {
+        secure_punch_connection(&mut socket, key).await?;
+    }
+    let local_addr = socket.local_addr();
+    let mut msg_out = RendezvousMessage::new();
+    msg_out.set_punch_hole_request(PunchHoleRequest {
+        id: id.to_owned(),
+        token: token.to_owned(),
+        nat_type: NatType::from_i32(crate::get_nat_type(1_000).await)
+            .unwrap_or(NatType::UNKNOWN_NAT)
+            .into(),
+        licence_key: key.to_owned(),
+        conn_type: ConnType::DEFAULT_CONN.into(),
+        nat_info: hbb_common::protobuf::MessageField::some(crate::get_nat_info(1_000).await),
+        udp_socket_addr: udp_addr
+            .map(|addr| AddrMangle::encode(addr).into())
+            .unwrap_or_default(),
+        ..Default::default()
+    });
+    socket.send(&msg_out).await?;
+    let res = next_punch_response(&mut socket).await;
+    // The local port is reused for the direct connection.
+    drop(socket);
+    match res? {
+        rendezvous_message::Union::PunchHoleResponse(ph) => {
+            if ph.socket_addr.is_empty() {
+                if !ph.other_failure.is_empty() {
+                    bail!(ph.other_failure);
+                }
+                bail!("{:?}", ph.failure.enum_value_or_default());
+            }
+            let peer_addr = AddrMangle::decode(&ph.socket_addr);
+            let detail = format!(
+                "peer {}, {}, relay server {:?}",
+                peer_addr,
+                if ph.is_local() {
+                    "local".to_owned()
+                } else {
+                    format!("{:?}", ph.nat_type())
+                },
+                ph.relay_server
+            );
+            Ok((
+                Punched::Direct {
+                    peer_addr,
+                    local_addr,
+                    nat_info: ph.nat_info.clone().unwrap_or_default(),
+                    peer_udp_addr: (!ph.udp_socket_addr.is_empty())
+                        .then(|| AddrMangle::decode(&ph.udp_socket_addr)),
+                    relay_server: ph.relay_server,
+                    pk: ph.pk.into(),
+                },
+                detail,
+            ))
+        }
+        rendezvous_message::Union::RelayResponse(rr) => {
+            let detail = format!("relay requested by the peer, {}", rr.relay_server);
+            Ok((
+                Punched::Relay {
+                    pk: rr.pk().into(),
+                    uuid: rr.uuid,
+                    relay_server: rr.relay_server,
+                    ipv4: local_addr.is_ipv4(),
+                },
+                detail,
+            ))
+        }
+        _ => bail!("Unexpected response"),
+    }
+}
+
+async fn next_punch_response(socket: &mut FramedStream) -> ResultType<rendezvous_message::Union> {
+    match crate::get_next_nonkeyexchange_msg(socket, Some(PUNCH_TIMEOUT)).await {
+        Some(msg_in) => msg_in.union.ok_or_else(|| anyhow!("Empty response")),
+        None => bail!("No response from the rendezvous server"),
+    }
+}
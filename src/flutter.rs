@@ -684,6 +684,16 @@ impl InvokeUiSession for FlutterHandler {
         );
     }
 
+    fn set_audio_inputs(&self, inputs: &AudioInputs) {
+        self.push_event(
+            "audio_inputs",
+            vec![
+                ("inputs", &serde_json::to_string(&inputs.inputs).unwrap_or_default()),
+                ("current", &inputs.current),
+            ],
+        );
+    }
+
     fn on_voice_call_started(&self) {
         self.push_event("on_voice_call_started", [].into());
     }
@@ -1158,6 +1168,37 @@ fn session_send_touch_pan(
     }
 }
 
+fn session_send_stylus_event(
+    session_id: SessionID,
+    v: &serde_json::Value,
+    alt: bool,
+    ctrl: bool,
+    shift: bool,
+    command: bool,
+) {
+    let (Some(t), Some(v)) = (v.get("t").and_then(|t| t.as_str()), v.get("v")) else {
+        return;
+    };
+    let get_i32 = |k: &str| v.get(k).and_then(|x| x.as_i64()).unwrap_or(0) as i32;
+    let get_bool = |k: &str| v.get(k).and_then(|x| x.as_bool()).unwrap_or(false);
+    let evt = StylusEvent {
+        x: get_i32("x"),
+        y: get_i32("y"),
+        pressure: v.get("pressure").and_then(|x| x.as_f64()).unwrap_or(0.) as f32,
+        tilt_x: get_i32("tilt_x"),
+        tilt_y: get_i32("tilt_y"),
+        tip_down: t == "down" || t == "move",
+        in_range: t != "leave",
+        eraser: get_bool("eraser"),
+        barrel_button: get_bool("button"),
+        barrel_button2: get_bool("button2"),
+        ..Default::default()
+    };
+    if let Some(session) = sessions::get_session(&session_id) {
+        session.send_stylus_event(evt, alt, ctrl, shift, command);
+    }
+}
+
 fn session_send_touch_event(
     session_id: SessionID,
     v: &serde_json::Value,
@@ -1184,6 +1225,9 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
         match (m.get("k"), m.get("v")) {
             (Some(k), Some(v)) => match k.as_str() {
                 Some("touch") => session_send_touch_event(session_id, v, alt, ctrl, shift, command),
+                Some("stylus") => {
+                    session_send_stylus_event(session_id, v, alt, ctrl, shift, command)
+                }
                 _ => {}
             },
             _ => {}
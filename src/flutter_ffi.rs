@@ -152,6 +152,12 @@ pub fn session_login(
     }
 }
 
+pub fn session_send_two_factor_code(session_id: SessionID, code: String, trust_this_device: bool) {
+    if let Some(session) = sessions::get_session(&session_id) {
+        session.send_two_factor_code(code, trust_this_device);
+    }
+}
+
 pub fn session_close(session_id: SessionID) {
     if let Some(session) = sessions::remove_session(&session_id) {
         session.close_event_stream();
@@ -165,6 +171,18 @@ pub fn session_refresh(session_id: SessionID) {
     }
 }
 
+pub fn session_get_remote_audio_inputs(session_id: SessionID) {
+    if let Some(session) = sessions::get_session(&session_id) {
+        session.get_remote_audio_inputs();
+    }
+}
+
+pub fn session_select_remote_audio_input(session_id: SessionID, input: String) {
+    if let Some(session) = sessions::get_session(&session_id) {
+        session.select_remote_audio_input(input);
+    }
+}
+
 pub fn session_record_screen(session_id: SessionID, start: bool, width: usize, height: usize) {
     if let Some(session) = sessions::get_session(&session_id) {
         session.record_screen(start, width as _, height as _);
@@ -672,6 +690,10 @@ pub fn main_set_option(key: String, value: String) {
         crate::rendezvous_mediator::RendezvousMediator::restart();
         #[cfg(any(target_os = "android", target_os = "ios", feature = "cli"))]
         crate::common::test_rendezvous_server();
+    } else if key.eq("use-websocket") || key.eq("websocket-tls") {
+        set_option(key, value);
+        #[cfg(target_os = "android")]
+        crate::rendezvous_mediator::RendezvousMediator::restart();
     } else {
         set_option(key, value);
     }
@@ -696,6 +718,10 @@ pub fn main_test_if_valid_server(server: String) -> String {
     test_if_valid_server(server)
 }
 
+pub fn main_diagnose(id: String) -> String {
+    diagnose(id)
+}
+
 pub fn main_set_socks(proxy: String, username: String, password: String) {
     set_socks(proxy, username, password)
 }
@@ -1329,6 +1355,46 @@ pub fn main_set_permanent_password(password: String) {
     set_permanent_password(password);
 }
 
+pub fn main_get_authorized_keys() -> String {
+    get_authorized_keys()
+}
+
+pub fn main_add_authorized_key(name: String, id: String, pk: String) -> String {
+    add_authorized_key(name, id, pk)
+}
+
+pub fn main_remove_authorized_key(pk: String) {
+    remove_authorized_key(pk)
+}
+
+pub fn main_get_client_public_key() -> String {
+    get_client_public_key()
+}
+
+pub fn main_check_access_list(list: String) -> String {
+    check_access_list(list)
+}
+
+pub fn main_has_two_factor_auth() -> bool {
+    has_two_factor_auth()
+}
+
+pub fn main_generate_two_factor_auth() -> String {
+    generate_two_factor_auth()
+}
+
+pub fn main_enable_two_factor_auth(secret: String, code: String) -> bool {
+    enable_two_factor_auth(secret, code)
+}
+
+pub fn main_disable_two_factor_auth() {
+    disable_two_factor_auth()
+}
+
+pub fn main_clear_trusted_devices() {
+    clear_trusted_devices()
+}
+
 pub fn main_check_super_user_permission() -> bool {
     check_super_user_permission()
 }
@@ -1354,6 +1420,23 @@ pub fn main_wol(id: String) {
     crate::lan::send_wol(id)
 }
 
+/// Wake the peer through another online peer on its LAN, and wait until it is online.
+/// Returns the error, empty on success.
+pub fn main_wol_through_peer(id: String) -> String {
+    #[cfg(not(any(target_os = "ios")))]
+    {
+        match crate::lan::wake_through_peer(id) {
+            Ok(_) => "".to_owned(),
+            Err(err) => err.to_string(),
+        }
+    }
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = id;
+        "".to_owned()
+    }
+}
+
 pub fn main_create_shortcut(_id: String) {
     #[cfg(windows)]
     create_shortcut(_id);
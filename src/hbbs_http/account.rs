@@ -99,7 +99,6 @@ pub struct AuthBody {
 }
 
 pub struct OidcSession {
-    client: Client,
     state_msg: &'static str,
     failed_msg: String,
     code_url: Option<OidcAuthUrl>,
@@ -126,7 +125,6 @@ impl Default for UserStatus {
 impl OidcSession {
     fn new() -> Self {
         Self {
-            client: Client::new(),
             state_msg: REQUESTING_ACCOUNT_AUTH,
             failed_msg: "".to_owned(),
             code_url: None,
@@ -138,15 +136,13 @@ impl OidcSession {
     }
 
     fn auth(
+        client: &Client,
         api_server: &str,
         op: &str,
         id: &str,
         uuid: &str,
     ) -> ResultType<HbbHttpResponse<OidcAuthUrl>> {
-        Ok(OIDC_SESSION
-            .read()
-            .unwrap()
-            .client
+        Ok(client
             .post(format!("{}/api/oidc/auth", api_server))
             .json(&serde_json::json!({
                 "op": op,
@@ -159,6 +155,7 @@ impl OidcSession {
     }
 
     fn query(
+        client: &Client,
         api_server: &str,
         code: &str,
         id: &str,
@@ -168,13 +165,7 @@ impl OidcSession {
             &format!("{}/api/oidc/auth-query", api_server),
             &[("code", code), ("id", id), ("uuid", uuid)],
         )?;
-        Ok(OIDC_SESSION
-            .read()
-            .unwrap()
-            .client
-            .get(url)
-            .send()?
-            .try_into()?)
+        Ok(client.get(url).send()?.try_into()?)
     }
 
     fn reset(&mut self) {
@@ -200,7 +191,17 @@ impl OidcSession {
     }
 
     fn auth_task(api_server: String, op: String, id: String, uuid: String, remember_me: bool) {
-        let auth_request_res = Self::auth(&api_server, &op, &id, &uuid);
+        let client = match crate::common::create_http_client_sync(&api_server) {
+            Ok(client) => client,
+            Err(err) => {
+                OIDC_SESSION
+                    .write()
+                    .unwrap()
+                    .set_state(REQUESTING_ACCOUNT_AUTH, err.to_string());
+                return;
+            }
+        };
+        let auth_request_res = Self::auth(&client, &api_server, &op, &id, &uuid);
         log::info!("Request oidc auth result: {:?}", &auth_request_res);
         let code_url = match auth_request_res {
             Ok(HbbHttpResponse::<_>::Data(code_url)) => code_url,
@@ -236,7 +237,7 @@ impl OidcSession {
         let begin = Instant::now();
         let query_timeout = OIDC_SESSION.read().unwrap().query_timeout;
         while OIDC_SESSION.read().unwrap().keep_querying && begin.elapsed() < query_timeout {
-            match Self::query(&api_server, &code_url.code, &id, &uuid) {
+            match Self::query(&client, &api_server, &code_url.code, &id, &uuid) {
                 Ok(HbbHttpResponse::<_>::Data(auth_body)) => {
                     if remember_me {
                         LocalConfig::set_option(
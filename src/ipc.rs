@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::atomic::{AtomicBool, Ordering},
 };
 #[cfg(not(windows))]
@@ -19,7 +20,7 @@ pub use clipboard::ClipboardFile;
 use hbb_common::{
     allow_err, bail, bytes,
     bytes_codec::BytesCodec,
-    config::{self, Config, Config2},
+    config::{self, AuthorizedKey, Config, Config2},
     futures::StreamExt as _,
     futures_util::sink::SinkExt,
     log, password_security as password, timeout, tokio,
@@ -40,6 +41,8 @@ pub enum PrivacyModeState {
 }
 // IPC actions here.
 pub const IPC_ACTION_CLOSE: &str = "close";
+// All the steps of a diagnosis may time out.
+const DIAGNOSE_TIMEOUT: u64 = 120_000;
 pub static EXIT_RECV_CLOSE: AtomicBool = AtomicBool::new(true);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -71,6 +74,8 @@ pub enum FS {
         overwrite_detection: bool,
         total_size: u64,
         conn_id: i32,
+        // directories the written files must be in, any if `None`
+        roots: Option<Vec<PathBuf>>,
     },
     CancelWrite {
         id: i32,
@@ -135,6 +140,31 @@ pub enum DataMouse {
     Refresh,
 }
 
+#[cfg(target_os = "linux")]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "t", content = "c")]
+pub enum DataGamepad {
+    // GamepadEvent in protobuf
+    Event(Vec<u8>),
+    // GamepadRumble in protobuf
+    Rumble(Vec<u8>),
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "t", content = "c")]
+pub enum DataStylus {
+    // Bounds of the desktop.
+    Resolution {
+        minx: i32,
+        maxx: i32,
+        miny: i32,
+        maxy: i32,
+    },
+    // StylusEvent in protobuf
+    Event(Vec<u8>),
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
 pub enum DataControl {
@@ -208,12 +238,18 @@ pub enum Data {
     ClipboardFileEnabled(bool),
     PrivacyModeState((i32, PrivacyModeState)),
     TestRendezvousServer,
+    // (peer id, JSON report)
+    Diagnose((String, Option<String>)),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Keyboard(DataKeyboard),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     KeyboardResponse(DataKeyboardResponse),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Mouse(DataMouse),
+    #[cfg(target_os = "linux")]
+    Gamepad(DataGamepad),
+    #[cfg(target_os = "linux")]
+    Stylus(DataStylus),
     Control(DataControl),
     Theme(String),
     Language(String),
@@ -301,7 +337,7 @@ pub async fn new_listener(postfix: &str) -> ResultType<Incoming> {
     }
 }
 
-pub struct CheckIfRestart(String, Vec<String>, String);
+pub struct CheckIfRestart(String, Vec<String>, String, String, String);
 
 impl CheckIfRestart {
     pub fn new() -> CheckIfRestart {
@@ -309,6 +345,8 @@ impl CheckIfRestart {
             Config::get_option("stop-service"),
             Config::get_rendezvous_servers(),
             Config::get_option("audio-input"),
+            Config::get_option("use-websocket"),
+            Config::get_option("websocket-tls"),
         )
     }
 }
@@ -316,6 +354,8 @@ impl Drop for CheckIfRestart {
     fn drop(&mut self) {
         if self.0 != Config::get_option("stop-service")
             || self.1 != Config::get_rendezvous_servers()
+            || self.3 != Config::get_option("use-websocket")
+            || self.4 != Config::get_option("websocket-tls")
         {
             RendezvousMediator::restart();
         }
@@ -390,6 +430,10 @@ async fn handle(data: Data, stream: &mut Connection) {
                     value = Some(password::temporary_password());
                 } else if name == "permanent-password" {
                     value = Some(Config::get_permanent_password());
+                } else if name == "authorized-keys" {
+                    value = serde_json::to_string(&Config::get_authorized_keys()).ok();
+                } else if name == "totp-enabled" {
+                    value = Some(crate::two_factor_auth::is_enabled().to_string());
                 } else if name == "salt" {
                     value = Some(Config::get_salt());
                 } else if name == "rendezvous_server" {
@@ -400,6 +444,9 @@ async fn handle(data: Data, stream: &mut Connection) {
                     ));
                 } else if name == "rendezvous_servers" {
                     value = Some(Config::get_rendezvous_servers().join(","));
+                } else if name == "nat-info" {
+                    let (class, port_delta) = Config::get_nat_info();
+                    value = Some(format!("{},{}", class, port_delta));
                 } else if name == "fingerprint" {
                     value = if Config::get_key_confirmed() {
                         Some(crate::common::pk_to_fingerprint(Config::get_key_pair().1))
@@ -419,6 +466,15 @@ async fn handle(data: Data, stream: &mut Connection) {
                     password::update_temporary_password();
                 } else if name == "permanent-password" {
                     Config::set_permanent_password(&value);
+                } else if name == "authorized-keys" {
+                    if let Ok(keys) = serde_json::from_str(&value) {
+                        Config::set_authorized_keys(keys);
+                    }
+                } else if name == "totp-secret" {
+                    Config::set_totp_secret(&value);
+                } else if name == "trusted-devices" {
+                    // Only clearing is supported, the devices are added by the server.
+                    Config::set_trusted_devices(vec![]);
                 } else if name == "salt" {
                     Config::set_salt(&value);
                 } else {
@@ -471,6 +527,10 @@ async fn handle(data: Data, stream: &mut Connection) {
         Data::TestRendezvousServer => {
             crate::test_rendezvous_server();
         }
+        Data::Diagnose((id, None)) => {
+            let report = crate::diagnose::diagnose(&id).await.to_json();
+            allow_err!(stream.send(&Data::Diagnose((id, Some(report)))).await);
+        }
         Data::SwitchSidesRequest(id) => {
             let uuid = uuid::Uuid::new_v4();
             crate::server::insert_switch_sides_uuid(id, uuid.clone());
@@ -521,6 +581,20 @@ pub async fn start_pa() {
                             {
                                 device = x;
                             }
+                            if let Some(app) =
+                                device.strip_prefix(crate::platform::linux::PW_APP_AUDIO_PREFIX)
+                            {
+                                match crate::platform::linux::capture_pw_app_stream(app) {
+                                    Ok(child) => {
+                                        forward_pw_app_stream(child, &mut stream).await;
+                                        continue;
+                                    }
+                                    Err(err) => {
+                                        log::error!("Failed to capture audio of {}: {}", app, err);
+                                        device = "".to_owned();
+                                    }
+                                }
+                            }
                             if !device.is_empty() {
                                 device = crate::platform::linux::get_pa_source_name(&device);
                             }
@@ -580,6 +654,44 @@ pub async fn start_pa() {
     }
 }
 
+/// Forward the raw samples of a `pw-record` child to the audio service.
+#[cfg(target_os = "linux")]
+async fn forward_pw_app_stream(mut child: std::process::Child, stream: &mut Connection) {
+    use crate::audio_service::AUDIO_DATA_SIZE_U8;
+
+    if let Some(mut stdout) = child.stdout.take() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+        // pw-record blocks while the application is silent, read on another thread.
+        std::thread::spawn(move || {
+            let mut buf: Vec<u8> = vec![0; AUDIO_DATA_SIZE_U8];
+            while stdout.read_exact(&mut buf).is_ok() {
+                if tx.send(buf.clone()).is_err() {
+                    break;
+                }
+            }
+        });
+        loop {
+            let out = match timeout(100, rx.recv()).await {
+                Ok(Some(buf)) => {
+                    if buf.iter().filter(|x| **x != 0).next().is_none() {
+                        vec![]
+                    } else {
+                        buf
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => vec![],
+            };
+            if let Err(err) = stream.send_raw(out.into()).await {
+                log::error!("Failed to send audio data:{}", err);
+                break;
+            }
+        }
+    }
+    child.kill().ok();
+    child.wait().ok();
+}
+
 #[inline]
 #[cfg(not(windows))]
 fn get_pid_file(postfix: &str) -> String {
@@ -747,6 +859,38 @@ pub fn set_permanent_password(v: String) -> ResultType<()> {
     set_config("permanent-password", v)
 }
 
+pub fn get_authorized_keys() -> Vec<AuthorizedKey> {
+    if let Ok(Some(v)) = get_config("authorized-keys") {
+        serde_json::from_str(&v).unwrap_or_default()
+    } else {
+        Config::get_authorized_keys()
+    }
+}
+
+pub fn set_authorized_keys(keys: Vec<AuthorizedKey>) -> ResultType<()> {
+    let v = serde_json::to_string(&keys)?;
+    Config::set_authorized_keys(keys);
+    set_config("authorized-keys", v)
+}
+
+pub fn set_totp_secret(v: String) -> ResultType<()> {
+    Config::set_totp_secret(&v);
+    set_config("totp-secret", v)
+}
+
+pub fn is_totp_enabled() -> bool {
+    if let Ok(Some(v)) = get_config("totp-enabled") {
+        v == "true"
+    } else {
+        crate::two_factor_auth::is_enabled()
+    }
+}
+
+pub fn clear_trusted_devices() -> ResultType<()> {
+    Config::set_trusted_devices(vec![]);
+    set_config("trusted-devices", "".to_owned())
+}
+
 pub fn get_id() -> String {
     if let Ok(Some(v)) = get_config("id") {
         // update salt also, so that next time reinstallation not causing first-time auto-login failure
@@ -844,6 +988,17 @@ pub async fn get_nat_type(ms_timeout: u64) -> i32 {
         .unwrap_or(Config::get_nat_type())
 }
 
+pub async fn get_nat_info(ms_timeout: u64) -> (i32, i32) {
+    if let Ok(Some(v)) = get_config_async("nat-info", ms_timeout).await {
+        if let Some((class, port_delta)) = v.split_once(',') {
+            let info = (class.parse().unwrap_or(0), port_delta.parse().unwrap_or(0));
+            Config::set_nat_info(info.0, info.1);
+            return info;
+        }
+    }
+    Config::get_nat_info()
+}
+
 pub async fn get_rendezvous_servers(ms_timeout: u64) -> Vec<String> {
     if let Ok(Some(v)) = get_config_async("rendezvous_servers", ms_timeout).await {
         return v.split(',').map(|x| x.to_owned()).collect();
@@ -893,6 +1048,16 @@ pub async fn test_rendezvous_server() -> ResultType<()> {
     Ok(())
 }
 
+#[tokio::main(flavor = "current_thread")]
+pub async fn diagnose(id: String) -> ResultType<String> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::Diagnose((id, None))).await?;
+    if let Some(Data::Diagnose((_, Some(report)))) = c.next_timeout(DIAGNOSE_TIMEOUT).await? {
+        return Ok(report);
+    }
+    bail!("No diagnose report");
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn send_url_scheme(url: String) -> ResultType<()> {
     connect(1_000, "_url")
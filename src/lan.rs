@@ -1,34 +1,51 @@
+use crate::client::{
+    async_trait, handle_hash, handle_login_from_ui, handle_test_delay, Client, Data, Interface,
+    LoginConfigHandler,
+};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use hbb_common::config::Config;
 use hbb_common::{
     allow_err,
     anyhow::bail,
-    config::{self, RENDEZVOUS_PORT},
+    config::{self, LocalConfig, READ_TIMEOUT, RENDEZVOUS_PORT},
     log,
+    message_proto::{self, login_response, message, Hash, PeerInfo, TestDelay, WakeOnLan},
     protobuf::Message as _,
     rendezvous_proto::*,
+    sleep,
     tokio::{
         self,
         sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
     },
-    ResultType,
+    ResultType, Stream,
 };
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
 
 use std::{
     collections::{HashMap, HashSet},
     net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
-    time::Instant,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 
 type Message = RendezvousMessage;
 
+const MDNS_SERVICE_TYPE: &str = "_rustdesk._tcp.local.";
+const MDNS_BROWSE_TIMEOUT: Duration = Duration::from_millis(3_000);
+const ONLINE_QUERY_TIMEOUT: Duration = Duration::from_millis(3_000);
+// How long a woken machine may take to boot and come online.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(180);
+const ONLINE_CHECK_INTERVAL: f32 = 5.;
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub(super) fn start_listening() -> ResultType<()> {
     let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
     let socket = std::net::UdpSocket::bind(addr)?;
     socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
     log::info!("lan discovery listener started");
+    let mut mdns = MdnsAdvertiser::default();
     loop {
+        mdns.update();
         let mut buf = [0; 2048];
         if let Ok((len, addr)) = socket.recv_from(&mut buf) {
             if let Ok(msg_in) = Message::parse_from_bytes(&buf[0..len]) {
@@ -59,10 +76,100 @@ pub(super) fn start_listening() -> ResultType<()> {
     }
 }
 
+// Advertises this host as `_rustdesk._tcp` with mDNS while LAN discovery is enabled, for the
+// networks the broadcast ping does not reach, e.g. IPv6-only ones.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[derive(Default)]
+struct MdnsAdvertiser {
+    daemon: Option<ServiceDaemon>,
+    // The id and the direct port advertised, with the full name of the service.
+    registered: Option<(String, i32, String)>,
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+impl MdnsAdvertiser {
+    fn update(&mut self) {
+        let wanted = if Config::get_option("enable-lan-discovery").is_empty() {
+            let port = if Config::get_option("direct-server").is_empty() {
+                0
+            } else {
+                crate::rendezvous_mediator::get_direct_port()
+            };
+            Some((Config::get_id(), port))
+        } else {
+            None
+        };
+        let registered = self
+            .registered
+            .as_ref()
+            .map(|(id, port, _)| (id.clone(), *port));
+        if wanted == registered {
+            return;
+        }
+        if let (Some(daemon), Some((_, _, fullname))) = (&self.daemon, self.registered.take()) {
+            if !fullname.is_empty() {
+                allow_err!(daemon.unregister(&fullname));
+            }
+        }
+        if let Some((id, port)) = wanted {
+            // Not retried before the id or the port changes.
+            let fullname = self.register(&id, port).unwrap_or_else(|err| {
+                log::error!("Failed to advertise with mDNS: {}", err);
+                "".to_owned()
+            });
+            self.registered = Some((id, port, fullname));
+        }
+    }
+
+    fn register(&mut self, id: &str, port: i32) -> ResultType<String> {
+        let daemon = match self.daemon.take() {
+            Some(daemon) => daemon,
+            None => ServiceDaemon::new()?,
+        };
+        let daemon = self.daemon.insert(daemon);
+        let username = crate::platform::get_active_username();
+        let properties = HashMap::from([
+            ("id".to_owned(), id.to_owned()),
+            ("hostname".to_owned(), whoami::hostname()),
+            ("username".to_owned(), username),
+            ("platform".to_owned(), whoami::platform().to_string()),
+            ("mac".to_owned(), get_default_mac()),
+            ("port".to_owned(), port.to_string()),
+        ]);
+        let info = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            id,
+            &format!("rustdesk-{}.local.", id),
+            "",
+            // The same as the TXT record, 0 if direct access is off.
+            port as _,
+            Some(properties),
+        )?
+        .enable_addr_auto();
+        let fullname = info.get_fullname().to_owned();
+        daemon.register(info)?;
+        log::info!("Advertised with mDNS as {}", fullname);
+        Ok(fullname)
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn get_default_mac() -> String {
+    default_net::get_default_interface()
+        .ok()
+        .and_then(|interface| interface.mac_addr)
+        .map(|mac| mac.to_string())
+        .unwrap_or_default()
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn discover() -> ResultType<()> {
-    let sockets = send_query()?;
-    let rx = spawn_wait_responses(sockets);
+    let (tx, rx) = unbounded_channel::<_>();
+    match send_query() {
+        Ok(sockets) => spawn_wait_responses(sockets, tx.clone()),
+        Err(err) => log::warn!("Failed to send discover ping: {}", err),
+    }
+    spawn_mdns_browse(tx);
     handle_received_peers(rx).await?;
 
     log::info!("discover ping done");
@@ -70,26 +177,253 @@ pub async fn discover() -> ResultType<()> {
 }
 
 pub fn send_wol(id: String) {
-    let interfaces = default_net::get_interfaces();
     for peer in &config::LanPeers::load().peers {
         if peer.id == id {
-            for (_, mac) in peer.ip_mac.iter() {
-                if let Ok(mac_addr) = mac.parse() {
-                    for interface in &interfaces {
-                        for ipv4 in &interface.ipv4 {
-                            // remove below mask check to avoid unexpected bug
-                            // if (u32::from(ipv4.addr) & u32::from(ipv4.netmask)) == (u32::from(peer_ip) & u32::from(ipv4.netmask))
-                            log::info!("Send wol to {mac_addr} of {}", ipv4.addr);
-                            allow_err!(wol::send_wol(mac_addr, None, Some(IpAddr::V4(ipv4.addr))));
+            send_magic_packets(peer.ip_mac.values());
+            break;
+        }
+    }
+}
+
+// Returns the number of packets sent.
+fn send_magic_packets<'a>(macs: impl IntoIterator<Item = &'a String>) -> usize {
+    let interfaces = default_net::get_interfaces();
+    let mut sent = 0;
+    for mac in macs {
+        if let Ok(mac_addr) = mac.parse() {
+            for interface in &interfaces {
+                for ipv4 in &interface.ipv4 {
+                    // remove below mask check to avoid unexpected bug
+                    // if (u32::from(ipv4.addr) & u32::from(ipv4.netmask)) == (u32::from(peer_ip) & u32::from(ipv4.netmask))
+                    log::info!("Send wol to {mac_addr} of {}", ipv4.addr);
+                    match wol::send_wol(mac_addr, None, Some(IpAddr::V4(ipv4.addr))) {
+                        Ok(_) => sent += 1,
+                        Err(err) => log::error!("Failed to send wol: {}", err),
+                    }
+                }
+            }
+        }
+    }
+    sent
+}
+
+/// Send the magic packets asked for by a peer which is not on this LAN, only to the MAC
+/// addresses discovered here, not to any the peer may claim.
+pub fn wake_on_lan(req: &WakeOnLan) -> ResultType<()> {
+    let macs = get_macs(&req.id);
+    if macs.is_empty() {
+        bail!("The MAC address of {} is unknown", req.id);
+    }
+    if send_magic_packets(&macs) == 0 {
+        bail!("Failed to send the magic packet");
+    }
+    Ok(())
+}
+
+fn get_macs(id: &str) -> Vec<String> {
+    config::LanPeers::load()
+        .peers
+        .into_iter()
+        .find(|p| p.id == id)
+        .map(|p| p.ip_mac.into_values().collect())
+        .unwrap_or_default()
+}
+
+/// Wake `id` through an online peer on its LAN, then wait until it is online. Returns the peer
+/// which sent the magic packets.
+#[tokio::main(flavor = "current_thread")]
+pub async fn wake_through_peer(id: String) -> ResultType<String> {
+    let candidates = relay_candidates(&id);
+    if candidates.is_empty() {
+        bail!("No peer is known on the LAN of {}", id);
+    }
+    let (onlines, _) =
+        crate::rendezvous_mediator::query_online_states_(&candidates, ONLINE_QUERY_TIMEOUT).await?;
+    let req = WakeOnLan {
+        id: id.clone(),
+        ..Default::default()
+    };
+    let mut relay = None;
+    for peer in candidates.iter().filter(|x| onlines.contains(x)) {
+        match request_wake(peer, req.clone()).await {
+            Ok(()) => {
+                relay = Some(peer.clone());
+                break;
+            }
+            Err(err) => log::warn!("Failed to wake {} through {}: {}", id, peer, err),
+        }
+    }
+    let Some(relay) = relay else {
+        bail!("No online peer on the LAN of {} could wake it", id);
+    };
+    log::info!("Woke {} through {}, waiting for it to be online", id, relay);
+    let begin = Instant::now();
+    let ids = vec![id.clone()];
+    while begin.elapsed() < WAKE_TIMEOUT {
+        sleep(ONLINE_CHECK_INTERVAL).await;
+        if let Ok((onlines, _)) =
+            crate::rendezvous_mediator::query_online_states_(&ids, ONLINE_QUERY_TIMEOUT).await
+        {
+            if !onlines.is_empty() {
+                return Ok(relay);
+            }
+        }
+    }
+    bail!("{} is not online yet after being woken", id)
+}
+
+// The peers which may be on the LAN of `id`: the discovered ones on its subnet first, then the
+// ones sharing a tag with it in the address book.
+fn relay_candidates(id: &str) -> Vec<String> {
+    let lan_peers = config::LanPeers::load().peers;
+    let subnets: HashSet<_> = lan_peers
+        .iter()
+        .filter(|p| p.id == id)
+        .flat_map(|p| p.ip_mac.keys())
+        .filter_map(|ip| get_subnet(ip))
+        .collect();
+    let mut ids: Vec<String> = Vec::new();
+    for peer in &lan_peers {
+        if peer.id != id
+            && !ids.contains(&peer.id)
+            && peer
+                .ip_mac
+                .keys()
+                .any(|ip| get_subnet(ip).map_or(false, |x| subnets.contains(&x)))
+        {
+            ids.push(peer.id.clone());
+        }
+    }
+    let ab = config::Ab::load();
+    if let Some(target) = ab.peers.iter().find(|p| p.id == id) {
+        for peer in &ab.peers {
+            if peer.id != id
+                && !ids.contains(&peer.id)
+                && peer.tags.iter().any(|tag| target.tags.contains(tag))
+            {
+                ids.push(peer.id.clone());
+            }
+        }
+    }
+    ids
+}
+
+// The /24 network of an IPv4 address.
+fn get_subnet(ip: &str) -> Option<[u8; 3]> {
+    match ip.parse() {
+        Ok(IpAddr::V4(ip)) => {
+            let octets = ip.octets();
+            Some([octets[0], octets[1], octets[2]])
+        }
+        _ => None,
+    }
+}
+
+// Log in to `relay` only to have it send the magic packets.
+async fn request_wake(relay: &str, req: WakeOnLan) -> ResultType<()> {
+    let (tx, mut rx) = unbounded_channel::<String>();
+    let session = WakeSession {
+        lc: Default::default(),
+        tx,
+    };
+    {
+        let mut lc = session.lc.write().unwrap();
+        lc.initialize(relay.to_owned(), ConnType::PORT_FORWARD, None, false);
+        lc.wake_on_lan = Some(req);
+    }
+    let key = crate::get_key(true).await;
+    let token = LocalConfig::get_option("access_token");
+    let (mut stream, _, _) =
+        Client::start(relay, &key, &token, ConnType::PORT_FORWARD, session.clone()).await?;
+    loop {
+        tokio::select! {
+            Some(err) = rx.recv() => bail!(err),
+            res = hbb_common::timeout(READ_TIMEOUT, stream.next()) => {
+                let Some(Ok(bytes)) = res? else {
+                    bail!("Reset by the peer");
+                };
+                let Ok(msg_in) = message_proto::Message::parse_from_bytes(&bytes) else {
+                    continue;
+                };
+                match msg_in.union {
+                    Some(message::Union::Hash(hash)) => {
+                        session.handle_hash("", hash, &mut stream).await;
+                    }
+                    Some(message::Union::LoginResponse(lr)) => {
+                        if let Some(login_response::Union::Error(err)) = lr.union {
+                            bail!(err);
+                        }
+                    }
+                    Some(message::Union::WakeOnLanResponse(res)) => {
+                        if !res.error.is_empty() {
+                            bail!(res.error);
                         }
+                        return Ok(());
                     }
+                    _ => {}
                 }
             }
-            break;
         }
     }
 }
 
+// Nobody is there to answer, e.g. for the password, so the saved one is used and any prompt is
+// an error.
+#[derive(Clone)]
+struct WakeSession {
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    tx: UnboundedSender<String>,
+}
+
+#[async_trait]
+impl Interface for WakeSession {
+    fn send(&self, _data: Data) {}
+
+    fn msgbox(&self, _msgtype: &str, title: &str, text: &str, _link: &str) {
+        self.tx
+            .send(if text.is_empty() { title } else { text }.to_owned())
+            .ok();
+    }
+
+    fn handle_login_error(&self, err: &str) -> bool {
+        self.tx.send(err.to_owned()).ok();
+        false
+    }
+
+    fn handle_peer_info(&self, _pi: PeerInfo) {}
+
+    async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
+        handle_hash(self.lc.clone(), pass, hash, self, peer).await;
+    }
+
+    async fn handle_login_from_ui(
+        &self,
+        os_username: String,
+        os_password: String,
+        password: String,
+        remember: bool,
+        peer: &mut Stream,
+    ) {
+        handle_login_from_ui(
+            self.lc.clone(),
+            os_username,
+            os_password,
+            password,
+            remember,
+            peer,
+        )
+        .await;
+    }
+
+    async fn handle_test_delay(&self, t: TestDelay, peer: &mut Stream) {
+        handle_test_delay(t, peer).await;
+    }
+
+    fn get_login_config_handler(&self) -> Arc<RwLock<LoginConfigHandler>> {
+        self.lc.clone()
+    }
+}
+
 #[inline]
 fn get_broadcast_port() -> u16 {
     (RENDEZVOUS_PORT + 3) as _
@@ -242,6 +576,7 @@ fn wait_response(
                                     hostname: p.hostname.clone(),
                                     platform: p.platform.clone(),
                                     online: true,
+                                    ..Default::default()
                                 }));
                             }
                         }
@@ -257,8 +592,7 @@ fn wait_response(
     Ok(())
 }
 
-fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::DiscoveryPeer> {
-    let (tx, rx) = unbounded_channel::<_>();
+fn spawn_wait_responses(sockets: Vec<UdpSocket>, tx: UnboundedSender<config::DiscoveryPeer>) {
     for socket in sockets {
         let tx_clone = tx.clone();
         std::thread::spawn(move || {
@@ -269,7 +603,62 @@ fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::Di
             ));
         });
     }
-    rx
+}
+
+fn spawn_mdns_browse(tx: UnboundedSender<config::DiscoveryPeer>) {
+    std::thread::spawn(move || {
+        if let Err(err) = mdns_browse(tx) {
+            log::warn!("Failed to discover with mDNS: {}", err);
+        }
+    });
+}
+
+fn mdns_browse(tx: UnboundedSender<config::DiscoveryPeer>) -> ResultType<()> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.browse(MDNS_SERVICE_TYPE)?;
+    let my_id = config::Config::get_id();
+    let begin = Instant::now();
+    while let Some(left) = MDNS_BROWSE_TIMEOUT.checked_sub(begin.elapsed()) {
+        match receiver.recv_timeout(left) {
+            Ok(ServiceEvent::ServiceResolved(info)) => {
+                if let Some(peer) = get_mdns_peer(&info) {
+                    if peer.id != my_id {
+                        allow_err!(tx.send(peer));
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    allow_err!(daemon.shutdown());
+    Ok(())
+}
+
+fn get_mdns_peer(info: &ServiceInfo) -> Option<config::DiscoveryPeer> {
+    let get = |key: &str| {
+        info.get_property_val_str(key)
+            .unwrap_or_default()
+            .to_owned()
+    };
+    let id = get("id");
+    if id.is_empty() {
+        return None;
+    }
+    let mac = get("mac");
+    Some(config::DiscoveryPeer {
+        id,
+        username: get("username"),
+        hostname: get("hostname"),
+        platform: get("platform"),
+        online: true,
+        ip_mac: info
+            .get_addresses()
+            .iter()
+            .map(|ip| (ip.to_string(), mac.clone()))
+            .collect(),
+        direct_port: get("port").parse().unwrap_or(0),
+    })
 }
 
 async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>) -> ResultType<()> {
@@ -287,6 +676,10 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
                     let in_response_set = !response_set.insert(peer.id.clone());
                     if let Some(pos) = peers.iter().position(|x| x.is_same_peer(&peer) ) {
                         let peer1 = peers.remove(pos);
+                        // Only advertised with mDNS.
+                        if peer.direct_port == 0 {
+                            peer.direct_port = peer1.direct_port;
+                        }
                         if in_response_set {
                             peer.ip_mac.extend(peer1.ip_mac);
                             peer.online = true;
@@ -312,3 +705,45 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     crate::flutter_ffi::main_load_lan_peers();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mdns_peer() {
+        let properties = HashMap::from([
+            ("id".to_owned(), "123456789".to_owned()),
+            ("hostname".to_owned(), "host".to_owned()),
+            ("mac".to_owned(), "00:11:22:33:44:55".to_owned()),
+            ("port".to_owned(), "21118".to_owned()),
+        ]);
+        let info = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            "123456789",
+            "rustdesk-123456789.local.",
+            "192.168.1.2",
+            21118,
+            Some(properties),
+        )
+        .unwrap();
+        let peer = get_mdns_peer(&info).unwrap();
+        assert_eq!(peer.id, "123456789");
+        assert_eq!(peer.hostname, "host");
+        assert_eq!(peer.direct_port, 21118);
+        assert_eq!(
+            peer.ip_mac.get("192.168.1.2").map(|x| x.as_str()),
+            Some("00:11:22:33:44:55")
+        );
+        let info = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            "other",
+            "other.local.",
+            "192.168.1.3",
+            80,
+            None,
+        )
+        .unwrap();
+        assert!(get_mdns_peer(&info).is_none());
+    }
+}
@@ -220,5 +220,22 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("upgrade_rustdesk_server_pro_to_{}_tip", "Please upgrade RustDesk Server Pro to version {} or newer!"),
         ("pull_group_failed_tip", "Failed to refresh group"),
         ("doc_fix_wayland", "https://rustdesk.com/docs/en/manual/linux/#x11-required"),
+        ("voice_call_virtual_mic_tip", "Use the voice call as a microphone on this computer"),
+        ("host_key_changed_tip", "The identity key of the remote device has changed since the last connection. Someone may be intercepting the connection. If the remote device was reinstalled, remove it from the recent sessions and connect again."),
+        ("strict_encryption_tip", "Refuse unencrypted connections"),
+        ("strict_encryption_failed_tip", "The connection is not end-to-end encrypted and strict encryption is enabled, so it was refused."),
+        ("e2e_encrypted_tip", "Only you and the remote device can read this session"),
+        ("e2e_unencrypted_tip", "This session is not end-to-end encrypted"),
+        ("wrong_2fa_code_tip", "The verification code is wrong or expired"),
+        ("enter_2fa_code_tip", "Enter the 6-digit code from the authenticator app of the remote device owner"),
+        ("setup_2fa_tip", "Add this key to your authenticator app, then enter the code it shows to confirm"),
+        ("enable_2fa_tip", "Ask for a one-time code from an authenticator app after the password"),
+        ("authorized_keys_tip", "Clients signing with these keys log in without the password. A key bound to an ID only logs in as that ID, which permission profile rules by ID or tag require. Remove a key to revoke it."),
+        ("file_transfer_roots_tip", "Folders the remote side may access, one per line. Any folder if empty."),
+        ("file_transfer_denied_extensions_tip", "File types which can not be transferred, e.g. exe, bat"),
+        ("access_hours_tip", "HH:MM-HH:MM in local time, separated by commas"),
+        ("access_weekdays_tip", "1 is Monday and 7 is Sunday, separated by commas"),
+        ("session_end_in_{}_minutes_tip", "The session will be closed in {} minutes."),
+        ("access_list_tip", "One rule per line, the first matching rule decides: allow or deny, followed by an IP, a CIDR range, a hostname, country:XX, asn:NUMBER or *. Takes precedence over the IP whitelist."),
     ].iter().cloned().collect();
 }
@@ -555,6 +555,57 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Check for software update on startup", "Comprobar actualización al iniciar"),
         ("upgrade_rustdesk_server_pro_to_{}_tip", "¡Por favor, actualiza RustDesk Server Pro a la versión {} o superior"),
         ("pull_group_failed_tip", "No se ha podido refrescar el grupo"),
-        ("Filter by intersection", "")
+        ("Filter by intersection", ""),
+        ("voice_call_virtual_mic_tip", ""),
+        ("Forward gamepads", ""),
+        ("host_key_changed_tip", ""),
+        ("strict_encryption_tip", ""),
+        ("strict_encryption_failed_tip", ""),
+        ("e2e_encrypted_tip", ""),
+        ("e2e_unencrypted_tip", ""),
+        ("2FA Required", ""),
+        ("Wrong 2FA Code", ""),
+        ("wrong_2fa_code_tip", ""),
+        ("enter_2fa_code_tip", ""),
+        ("Two-factor authentication", ""),
+        ("setup_2fa_tip", ""),
+        ("enable_2fa_tip", ""),
+        ("Enable 2FA", ""),
+        ("Clear trusted devices", ""),
+        ("Trusted client keys", ""),
+        ("authorized_keys_tip", ""),
+        ("Revoke", ""),
+        ("Public Key", ""),
+        ("Public key of this device", ""),
+        ("Invalid public key", ""),
+        ("Access is not allowed at this time", ""),
+        ("Access to this path is not allowed", ""),
+        ("File transfer restrictions", ""),
+        ("file_transfer_roots_tip", ""),
+        ("file_transfer_denied_extensions_tip", ""),
+        ("File transfer is read-only", ""),
+        ("This file type is not allowed", ""),
+        ("Session limits", ""),
+        ("Maximum session duration (minutes)", ""),
+        ("Access hours", ""),
+        ("access_hours_tip", ""),
+        ("Access weekdays", ""),
+        ("access_weekdays_tip", ""),
+        ("Permanent password expires in (days)", ""),
+        ("Invalid number", ""),
+        ("session_end_in_{}_minutes_tip", ""),
+        ("The maximum session duration is reached", ""),
+        ("Access list", ""),
+        ("access_list_tip", ""),
+        ("Invalid rule", ""),
+        ("Country database (MaxMind)", ""),
+        ("ASN database (MaxMind)", ""),
+        ("PAC URL", ""),
+        ("Use WebSocket", ""),
+        ("Use TLS on port 443", ""),
+        ("WOL through a LAN peer", ""),
+        ("Waking up, please wait...", ""),
+        ("Enable WOL for other peers", ""),
+        ("No permission of waking peers on LAN", ""),
     ].iter().cloned().collect();
 }
@@ -58,6 +58,11 @@ mod ui_session_interface;
 
 mod hbbs_http;
 
+mod diagnose;
+mod nat;
+mod trusted_keys;
+mod two_factor_auth;
+
 #[cfg(windows)]
 pub mod clipboard_file;
 
@@ -0,0 +1,417 @@
+//! NAT classification and port prediction for hole punching.
+//!
+//! The mapping behavior, and the port delta of a symmetric NAT, come from the TCP probes against
+//! the rendezvous server in `common::test_nat_type`. The filtering behavior of a cone NAT comes
+//! from the classic STUN tests (RFC 3489) against `stun-server`, a server which supports
+//! CHANGE-REQUEST. No STUN server is contacted unless one is configured, without one a cone NAT
+//! is taken as port-restricted, the most strict.
+//!
+//! If only one side is behind a symmetric NAT with a known delta, the other side tries the next
+//! few ports it is likely to map, before falling back to relay.
+//!
+//! With the `quic` feature, if the TCP holes can not be punched and `stun-server` is configured,
+//! a QUIC connection is tried over a UDP port whose public address each side learns from it, see
+//! `hbb_common::quic`. The address is learned ahead in the background, punching a hole never
+//! waits for the STUN server.
+
+use hbb_common::{
+    bail,
+    config::Config,
+    lazy_static, log,
+    protobuf::Enum,
+    rendezvous_proto::{nat_info::Class, NatInfo, NatType},
+    tokio::{self, net::UdpSocket},
+    ResultType,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// Larger deltas are more likely other traffic than the allocation scheme of the NAT.
+const MAX_PORT_DELTA: i32 = 50;
+const PREDICTED_PORTS: i32 = 5;
+const STUN_TIMEOUT: u64 = 1_000;
+const STUN_RETRIES: usize = 2;
+// Shorter than NATs usually keep an idle UDP mapping.
+const UDP_ENDPOINT_TTL: Duration = Duration::from_secs(20);
+
+const MAGIC_COOKIE: u32 = 0x2112A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_CHANGE_REQUEST: u16 = 0x0003;
+const ATTR_CHANGED_ADDRESS: u16 = 0x0005;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_OTHER_ADDRESS: u16 = 0x802C;
+const CHANGE_IP: u32 = 0x04;
+const CHANGE_PORT: u32 = 0x02;
+
+lazy_static::lazy_static! {
+    // A std socket, to be used in the runtime of whoever takes it.
+    static ref UDP_ENDPOINT: Mutex<Option<(std::net::UdpSocket, SocketAddr, Instant)>> =
+        Default::default();
+    static ref UDP_ENDPOINT_REFRESHING: AtomicBool = AtomicBool::new(false);
+}
+
+/// The stored classification.
+pub fn from_config((class, port_delta): (i32, i32)) -> NatInfo {
+    NatInfo {
+        class: Class::from_i32(class).unwrap_or(Class::UNKNOWN).into(),
+        port_delta,
+        ..Default::default()
+    }
+}
+
+/// Classify from the ports the rendezvous server saw for two probes from the same local port.
+pub async fn classify(port1: i32, port2: i32) -> (NatType, NatInfo) {
+    let mut info = NatInfo::new();
+    if port1 != port2 {
+        let delta = port2 - port1;
+        info.class = Class::SYMMETRIC.into();
+        info.port_delta = if delta.abs() <= MAX_PORT_DELTA {
+            delta
+        } else {
+            0
+        };
+        return (NatType::SYMMETRIC, info);
+    }
+    let class = match test_filtering().await {
+        Ok(Some(class)) => class,
+        Ok(None) => Class::PORT_RESTRICTED_CONE,
+        Err(err) => {
+            log::info!("STUN test failed: {}", err);
+            Class::PORT_RESTRICTED_CONE
+        }
+    };
+    info.class = class.into();
+    (NatType::ASYMMETRIC, info)
+}
+
+/// Whether a hole can be punched, symmetric NATs only on one side and with a known delta.
+pub fn can_punch(
+    my_nat_type: i32,
+    my_info: &NatInfo,
+    peer_nat_type: NatType,
+    peer: &NatInfo,
+) -> bool {
+    let is_symmetric = |nat_type: bool, info: &NatInfo| {
+        nat_type || info.class.enum_value() == Ok(Class::SYMMETRIC)
+    };
+    let me = is_symmetric(my_nat_type == NatType::SYMMETRIC as i32, my_info);
+    let peer_symmetric = is_symmetric(peer_nat_type == NatType::SYMMETRIC, peer);
+    match (me, peer_symmetric) {
+        (false, false) => true,
+        (true, false) => is_predictable(my_info),
+        (false, true) => is_predictable(peer),
+        (true, true) => false,
+    }
+}
+
+#[inline]
+fn is_predictable(info: &NatInfo) -> bool {
+    info.class.enum_value() == Ok(Class::SYMMETRIC) && info.port_delta != 0
+}
+
+/// The addresses to try for a peer last seen at `addr`, `addr` first.
+pub fn candidate_addrs(addr: SocketAddr, info: &NatInfo) -> Vec<SocketAddr> {
+    let mut addrs = vec![addr];
+    if is_predictable(info) {
+        for i in 1..=PREDICTED_PORTS {
+            let port = addr.port() as i32 + info.port_delta * i;
+            if port > 0 && port <= u16::MAX as i32 {
+                addrs.push(SocketAddr::new(addr.ip(), port as _));
+            }
+        }
+    }
+    addrs
+}
+
+/// Whether a UDP hole can be punched, i.e. QUIC is built in, a STUN server is configured and
+/// neither a proxy nor WebSocket is used.
+pub fn can_punch_udp() -> bool {
+    cfg!(feature = "quic")
+        && !Config::get_option("stun-server").trim().is_empty()
+        && Config::get_socks().is_none()
+        && !hbb_common::websocket::is_enabled()
+}
+
+/// The UDP socket learned last with the public address it is mapped to, if it is recent. Another
+/// one is learned in the background for the next time.
+pub fn take_udp_endpoint() -> Option<(UdpSocket, SocketAddr)> {
+    if !can_punch_udp() {
+        return None;
+    }
+    let endpoint = UDP_ENDPOINT.lock().unwrap().take();
+    refresh_udp_endpoint();
+    let (socket, addr, time) = endpoint?;
+    if time.elapsed() >= UDP_ENDPOINT_TTL {
+        return None;
+    }
+    match UdpSocket::from_std(socket) {
+        Ok(socket) => Some((socket, addr)),
+        Err(err) => {
+            log::info!("No UDP endpoint: {}", err);
+            None
+        }
+    }
+}
+
+/// Learn a UDP endpoint in the background, unless a recent one is there. Called within a runtime.
+pub fn refresh_udp_endpoint() {
+    if !can_punch_udp() || UDP_ENDPOINT_REFRESHING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let recent = UDP_ENDPOINT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(false, |(_, _, time)| time.elapsed() < UDP_ENDPOINT_TTL);
+    if recent {
+        UDP_ENDPOINT_REFRESHING.store(false, Ordering::SeqCst);
+        return;
+    }
+    tokio::spawn(async {
+        match udp_endpoint()
+            .await
+            .and_then(|(socket, addr)| Ok((socket.into_std()?, addr)))
+        {
+            Ok((socket, addr)) => {
+                *UDP_ENDPOINT.lock().unwrap() = Some((socket, addr, Instant::now()));
+            }
+            Err(err) => log::info!("No UDP endpoint: {}", err),
+        }
+        UDP_ENDPOINT_REFRESHING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// A UDP socket with the public address it is mapped to, waiting for the STUN server.
+pub async fn udp_endpoint() -> ResultType<(UdpSocket, SocketAddr)> {
+    let Some(server) = stun_server().await? else {
+        bail!("No STUN server configured");
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
+    match binding(&socket, server, 0).await? {
+        Some(response) => Ok((socket, response.mapped)),
+        None => bail!("No response from {}", server),
+    }
+}
+
+// `None` if not configured.
+async fn stun_server() -> ResultType<Option<SocketAddr>> {
+    let server = Config::get_option("stun-server");
+    let server = server.trim();
+    if server.is_empty() {
+        return Ok(None);
+    }
+    match hbb_common::tokio::net::lookup_host(server)
+        .await?
+        .find(|a| a.is_ipv4())
+    {
+        Some(addr) => Ok(Some(addr)),
+        None => bail!("Failed to resolve {}", server),
+    }
+}
+
+// `None` if there is no server, or it can not tell, e.g. it does not support CHANGE-REQUEST.
+async fn test_filtering() -> ResultType<Option<Class>> {
+    let Some(server) = stun_server().await? else {
+        return Ok(None);
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
+    let Some(first) = binding(&socket, server, 0).await? else {
+        bail!("No response from {}", server);
+    };
+    let Some(other) = first.other else {
+        return Ok(None);
+    };
+    if binding(&socket, server, CHANGE_IP | CHANGE_PORT)
+        .await?
+        .is_some()
+    {
+        return Ok(Some(Class::FULL_CONE));
+    }
+    match binding(&socket, other, 0).await? {
+        Some(second) if second.mapped != first.mapped => return Ok(Some(Class::SYMMETRIC)),
+        Some(_) => {}
+        None => return Ok(None),
+    }
+    if binding(&socket, server, CHANGE_PORT).await?.is_some() {
+        Ok(Some(Class::RESTRICTED_CONE))
+    } else {
+        Ok(Some(Class::PORT_RESTRICTED_CONE))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct BindingResponse {
+    mapped: SocketAddr,
+    // The alternate address of the server.
+    other: Option<SocketAddr>,
+}
+
+async fn binding(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    change: u32,
+) -> ResultType<Option<BindingResponse>> {
+    let transaction: [u8; 12] = hbb_common::rand::random();
+    let request = encode_request(&transaction, change);
+    let mut buf = [0u8; 1024];
+    for _ in 0..STUN_RETRIES {
+        socket.send_to(&request, server).await?;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(STUN_TIMEOUT);
+        while let Some(left) = deadline.checked_duration_since(std::time::Instant::now()) {
+            let Ok(res) =
+                hbb_common::timeout(left.as_millis() as _, socket.recv_from(&mut buf)).await
+            else {
+                break;
+            };
+            let (n, _) = res?;
+            // The response to a change request comes from another address.
+            if let Some(response) = decode_response(&buf[..n], &transaction) {
+                return Ok(Some(response));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn encode_request(transaction: &[u8; 12], change: u32) -> Vec<u8> {
+    let mut v = Vec::with_capacity(28);
+    v.extend(BINDING_REQUEST.to_be_bytes());
+    v.extend((if change != 0 { 8u16 } else { 0 }).to_be_bytes());
+    v.extend(MAGIC_COOKIE.to_be_bytes());
+    v.extend(transaction);
+    if change != 0 {
+        v.extend(ATTR_CHANGE_REQUEST.to_be_bytes());
+        v.extend(4u16.to_be_bytes());
+        v.extend(change.to_be_bytes());
+    }
+    v
+}
+
+fn decode_response(data: &[u8], transaction: &[u8; 12]) -> Option<BindingResponse> {
+    if data.len() < 20
+        || u16::from_be_bytes([data[0], data[1]]) != BINDING_RESPONSE
+        || &data[8..20] != transaction
+    {
+        return None;
+    }
+    let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    let attrs = data.get(20..20 + len)?;
+    let mut mapped = None;
+    let mut xor_mapped = None;
+    let mut other = None;
+    let mut i = 0;
+    while i + 4 <= attrs.len() {
+        let typ = u16::from_be_bytes([attrs[i], attrs[i + 1]]);
+        let n = u16::from_be_bytes([attrs[i + 2], attrs[i + 3]]) as usize;
+        let value = attrs.get(i + 4..i + 4 + n)?;
+        match typ {
+            ATTR_MAPPED_ADDRESS => mapped = decode_address(value, None),
+            ATTR_XOR_MAPPED_ADDRESS => xor_mapped = decode_address(value, Some(&data[4..20])),
+            ATTR_OTHER_ADDRESS | ATTR_CHANGED_ADDRESS => other = decode_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to 4 bytes.
+        i += 4 + (n + 3) / 4 * 4;
+    }
+    Some(BindingResponse {
+        mapped: xor_mapped.or(mapped)?,
+        other,
+    })
+}
+
+// `xor` is the magic cookie and the transaction id for XOR-MAPPED-ADDRESS.
+fn decode_address(value: &[u8], xor: Option<&[u8]>) -> Option<SocketAddr> {
+    let family = *value.get(1)?;
+    let mut port = u16::from_be_bytes([*value.get(2)?, *value.get(3)?]);
+    let mut ip = value.get(4..)?.to_vec();
+    if let Some(xor) = xor {
+        port ^= (MAGIC_COOKIE >> 16) as u16;
+        for (b, x) in ip.iter_mut().zip(xor) {
+            *b ^= x;
+        }
+    }
+    let ip: IpAddr = match family {
+        1 => <[u8; 4]>::try_from(ip.get(..4)?)
+            .ok()
+            .map(Ipv4Addr::from)?
+            .into(),
+        2 => <[u8; 16]>::try_from(ip.get(..16)?)
+            .ok()
+            .map(Ipv6Addr::from)?
+            .into(),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_response() {
+        let transaction = [7u8; 12];
+        let mut data = vec![0x01, 0x01, 0, 24];
+        data.extend(MAGIC_COOKIE.to_be_bytes());
+        data.extend(transaction);
+        // XOR-MAPPED-ADDRESS 192.0.2.1:32853
+        data.extend([0x00, 0x20, 0, 8, 0, 1]);
+        data.extend((32853u16 ^ 0x2112).to_be_bytes());
+        data.extend([192 ^ 0x21, 0x12, 2 ^ 0xA4, 1 ^ 0x42]);
+        // OTHER-ADDRESS 198.51.100.2:3479
+        data.extend([0x80, 0x2C, 0, 8, 0, 1]);
+        data.extend(3479u16.to_be_bytes());
+        data.extend([198, 51, 100, 2]);
+        assert_eq!(
+            decode_response(&data, &transaction),
+            Some(BindingResponse {
+                mapped: "192.0.2.1:32853".parse().unwrap(),
+                other: Some("198.51.100.2:3479".parse().unwrap()),
+            })
+        );
+        assert_eq!(decode_response(&data, &[0u8; 12]), None);
+    }
+
+    #[test]
+    fn test_candidate_addrs() {
+        let addr: SocketAddr = "192.0.2.1:65533".parse().unwrap();
+        let symmetric = |port_delta| NatInfo {
+            class: Class::SYMMETRIC.into(),
+            port_delta,
+            ..Default::default()
+        };
+        assert_eq!(candidate_addrs(addr, &NatInfo::new()), vec![addr]);
+        assert_eq!(candidate_addrs(addr, &symmetric(0)), vec![addr]);
+        let ports: Vec<u16> = candidate_addrs(addr, &symmetric(1))
+            .iter()
+            .map(|a| a.port())
+            .collect();
+        assert_eq!(ports, vec![65533, 65534, 65535]);
+        assert!(can_punch(
+            NatType::ASYMMETRIC as _,
+            &NatInfo::new(),
+            NatType::SYMMETRIC,
+            &symmetric(2)
+        ));
+        assert!(!can_punch(
+            NatType::SYMMETRIC as _,
+            &symmetric(1),
+            NatType::SYMMETRIC,
+            &symmetric(2)
+        ));
+        assert!(!can_punch(
+            NatType::ASYMMETRIC as _,
+            &NatInfo::new(),
+            NatType::SYMMETRIC,
+            &NatInfo::new()
+        ));
+    }
+}
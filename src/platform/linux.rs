@@ -193,6 +193,12 @@ fn start_uinput_service() {
     std::thread::spawn(|| {
         service::start_service_mouse();
     });
+    std::thread::spawn(|| {
+        service::start_service_gamepad();
+    });
+    std::thread::spawn(|| {
+        service::start_service_stylus();
+    });
 }
 
 #[inline]
@@ -647,6 +653,147 @@ pub fn get_default_pa_source() -> Option<(String, String)> {
     None
 }
 
+/// A PulseAudio/PipeWire microphone fed by the voice call audio of the remote peer.
+///
+/// The audio is played into a null sink, whose monitor is remapped as a source, so that
+/// applications on this machine can record the remote peer like a real microphone.
+pub struct VirtualMic {
+    modules: Vec<String>,
+    prior_default_source: Option<String>,
+}
+
+impl VirtualMic {
+    pub const SINK_NAME: &'static str = "rustdesk_voice_call";
+    pub const SOURCE_NAME: &'static str = "rustdesk_mic";
+
+    pub fn new() -> ResultType<Self> {
+        let mut mic = VirtualMic {
+            modules: Vec::new(),
+            prior_default_source: get_default_pa_source().map(|x| x.0),
+        };
+        // Modules are unloaded on drop if any of the following fails.
+        mic.load_module(&[
+            "module-null-sink",
+            &format!("sink_name={}", Self::SINK_NAME),
+            "sink_properties=device.description=RustDesk-Voice-Call",
+        ])?;
+        mic.load_module(&[
+            "module-remap-source",
+            &format!("master={}.monitor", Self::SINK_NAME),
+            &format!("source_name={}", Self::SOURCE_NAME),
+            "source_properties=device.description=RustDesk-Microphone",
+        ])?;
+        // Keep the remote peer audible on the local speakers.
+        mic.load_module(&[
+            "module-loopback",
+            &format!("source={}.monitor", Self::SINK_NAME),
+            "latency_msec=20",
+        ])?;
+        pactl(&["set-default-source", Self::SOURCE_NAME])?;
+        log::info!("Virtual microphone {} created", Self::SOURCE_NAME);
+        Ok(mic)
+    }
+
+    fn load_module(&mut self, args: &[&str]) -> ResultType<()> {
+        let mut load = vec!["load-module"];
+        load.extend_from_slice(args);
+        let index = pactl(&load)?;
+        self.modules.push(index.trim().to_owned());
+        Ok(())
+    }
+}
+
+impl Drop for VirtualMic {
+    fn drop(&mut self) {
+        if let Some(source) = self.prior_default_source.take() {
+            allow_err!(pactl(&["set-default-source", &source]));
+        }
+        for index in self.modules.drain(..).rev() {
+            allow_err!(pactl(&["unload-module", &index]));
+        }
+        log::info!("Virtual microphone {} removed", Self::SOURCE_NAME);
+    }
+}
+
+fn pactl(args: &[&str]) -> ResultType<String> {
+    let output = Command::new("pactl").args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "pactl {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Prefix of the `audio-input` option which selects the audio of one application,
+/// e.g. "app:Firefox".
+pub const PW_APP_AUDIO_PREFIX: &str = "app:";
+
+/// Audio output streams of applications from PipeWire, (object.serial, application name).
+pub fn get_pw_app_streams() -> Vec<(String, String)> {
+    match Command::new("pw-dump").output() {
+        Ok(output) if output.status.success() => parse_pw_app_streams(&output.stdout),
+        Ok(output) => {
+            log::debug!("pw-dump exited with {}", output.status);
+            vec![]
+        }
+        Err(err) => {
+            log::debug!("Failed to run pw-dump: {}", err);
+            vec![]
+        }
+    }
+}
+
+fn parse_pw_app_streams(dump: &[u8]) -> Vec<(String, String)> {
+    let objects: Vec<serde_json::Value> = serde_json::from_slice(dump).unwrap_or_default();
+    let mut out: Vec<(String, String)> = Vec::new();
+    for obj in objects.iter() {
+        if obj["type"] != "PipeWire:Interface:Node" {
+            continue;
+        }
+        let props = &obj["info"]["props"];
+        if props["media.class"] != "Stream/Output/Audio" {
+            continue;
+        }
+        let name = props["application.name"]
+            .as_str()
+            .or(props["node.name"].as_str())
+            .unwrap_or_default();
+        // One entry per application, the first stream is captured.
+        if name.is_empty() || out.iter().any(|x| x.1 == name) {
+            continue;
+        }
+        let serial = if props["object.serial"].is_u64() {
+            props["object.serial"].to_string()
+        } else {
+            obj["id"].to_string()
+        };
+        out.push((serial, name.to_owned()));
+    }
+    out
+}
+
+/// Start `pw-record` capturing the audio of `app`, raw f32le stereo samples on its stdout.
+pub fn capture_pw_app_stream(app: &str) -> ResultType<Child> {
+    let target = match get_pw_app_streams().into_iter().find(|x| x.1 == app) {
+        Some((serial, _)) => serial,
+        None => bail!("No audio stream of {}", app),
+    };
+    let rate = super::PA_SAMPLE_RATE.to_string();
+    let child = Command::new("pw-record")
+        .args([
+            "--target", &target, "--rate", &rate, "--channels", "2", "--format", "f32", "--raw",
+            "-",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    log::info!("pw-record {} (target {})", app, target);
+    Ok(child)
+}
+
 pub fn lock_screen() {
     Command::new("xdg-screensaver").arg("lock").spawn().ok();
 }
@@ -136,7 +136,22 @@ async fn connect_and_login(
                     }
                     let msg_in = Message::parse_from_bytes(&bytes)?;
                     match msg_in.union {
+                        Some(message::Union::SignedId(si)) => {
+                            if !si.pk.is_empty() && !stream.is_secured() && Client::is_address(id) {
+                                Client::secure_direct_connection(id, si, &mut stream).await?;
+                            }
+                        }
                         Some(message::Union::Hash(hash)) => {
+                            Client::check_pinned_key(id, stream.is_secured())?;
+                            if crate::common::is_strict_encryption() && !stream.is_secured() {
+                                interface.msgbox(
+                                    "error",
+                                    "Connection Error",
+                                    crate::client::LOGIN_MSG_STRICT_ENCRYPTION,
+                                    "",
+                                );
+                                return Ok(None);
+                            }
                             interface.handle_hash(password, hash, &mut stream).await;
                         }
                         Some(message::Union::LoginResponse(lr)) => match lr.union {
@@ -145,6 +160,12 @@ async fn connect_and_login(
                                 return Ok(None);
                             }
                             Some(login_response::Union::PeerInfo(pi)) => {
+                                if !lr.trusted_device_token.is_empty() {
+                                    interface.get_login_config_handler().write().unwrap().set_option(
+                                        "trusted-device-token".to_owned(),
+                                        lr.trusted_device_token,
+                                    );
+                                }
                                 interface.handle_peer_info(pi);
                                 break;
                             }
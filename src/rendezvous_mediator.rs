@@ -9,14 +9,18 @@ use std::{
 
 use uuid::Uuid;
 
+#[cfg(feature = "quic")]
+use hbb_common::quic;
 use hbb_common::tcp::FramedStream;
 use hbb_common::{
     allow_err,
     anyhow::bail,
+    bytes::BytesMut,
     config::{Config, CONNECT_TIMEOUT, READ_TIMEOUT, REG_INTERVAL, RENDEZVOUS_PORT},
-    futures::future::join_all,
+    futures::future::{join_all, select_ok},
     log,
     protobuf::Message as _,
+    proxy,
     rendezvous_proto::*,
     sleep,
     socket_client::{self, is_ipv4},
@@ -25,7 +29,7 @@ use hbb_common::{
         time::{interval, Duration},
     },
     udp::FramedSocket,
-    AddrMangle, ResultType,
+    websocket, AddrMangle, IntoTargetAddr, ResultType, TargetAddr,
 };
 
 use crate::server::{check_zombie, new as new_server, ServerPtr};
@@ -36,6 +40,34 @@ lazy_static::lazy_static! {
     static ref SOLVING_PK_MISMATCH: Arc<Mutex<String>> = Default::default();
 }
 static SHOULD_EXIT: AtomicBool = AtomicBool::new(false);
+const WEBSOCKET_DETECT_TIMEOUT: u64 = 500;
+// The peer tries QUIC only after TCP has timed out.
+#[cfg(feature = "quic")]
+const UDP_PUNCH_TIMEOUT: u64 = CONNECT_TIMEOUT * 2;
+// Of the rendezvous servers when none is registered with, which tells their health otherwise.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+// The rendezvous server is reached over UDP, or over a stream where UDP is not available.
+enum Socket {
+    Udp(FramedSocket),
+    Stream(FramedStream),
+}
+
+impl Socket {
+    async fn send(&mut self, msg: &Message, addr: &TargetAddr<'static>) -> ResultType<()> {
+        match self {
+            Self::Udp(socket) => socket.send(msg, addr.to_owned()).await,
+            Self::Stream(stream) => stream.send(msg).await,
+        }
+    }
+
+    async fn next(&mut self) -> Option<ResultType<BytesMut>> {
+        match self {
+            Self::Udp(socket) => socket.next().await.map(|res| res.map(|(bytes, _)| bytes)),
+            Self::Stream(stream) => stream.next().await.map(|res| Ok(res?)),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct RendezvousMediator {
@@ -63,6 +95,7 @@ impl RendezvousMediator {
         if !Config::get_option("stop-service").is_empty() {
             crate::test_rendezvous_server();
         }
+        let mut last_health_check = Instant::now();
         let server_cloned = server.clone();
         tokio::spawn(async move {
             direct_server(server_cloned).await;
@@ -102,6 +135,10 @@ impl RendezvousMediator {
                 join_all(futs).await;
             } else {
                 server.write().unwrap().close_connections();
+                if last_health_check.elapsed() > HEALTH_CHECK_INTERVAL {
+                    crate::test_rendezvous_server();
+                    last_health_check = Instant::now();
+                }
             }
             sleep(1.).await;
         }
@@ -126,7 +163,17 @@ impl RendezvousMediator {
             })
             .unwrap_or(host.to_owned());
         let host = crate::check_port(&host, RENDEZVOUS_PORT);
-        let (mut socket, addr) = socket_client::new_udp_for(&host, CONNECT_TIMEOUT).await?;
+        // UDP can not go through an HTTP proxy.
+        let use_tcp = websocket::is_enabled()
+            || matches!(proxy::for_target(&host).await, Some(proxy::Proxy::Http(_)));
+        let (mut socket, addr) = if use_tcp {
+            let stream = socket_client::connect_tcp(&*host, CONNECT_TIMEOUT).await?;
+            let addr = host.as_str().into_target_addr()?.to_owned();
+            (Socket::Stream(stream), addr)
+        } else {
+            let (socket, addr) = socket_client::new_udp_for(&host, CONNECT_TIMEOUT).await?;
+            (Socket::Udp(socket), addr)
+        };
         let mut rz = Self {
             addr: addr,
             host: host.clone(),
@@ -176,7 +223,7 @@ impl RendezvousMediator {
             select! {
                 n = socket.next() => {
                     match n {
-                        Some(Ok((bytes, _))) => {
+                        Some(Ok(bytes)) => {
                             if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                                 match msg_in.union {
                                     Some(rendezvous_message::Union::RegisterPeerResponse(rpr)) => {
@@ -228,6 +275,7 @@ impl RendezvousMediator {
                                         let v0 = Config::get_rendezvous_servers();
                                         Config::set_option("rendezvous-servers".to_owned(), cu.rendezvous_servers.join(","));
                                         Config::set_serial(cu.serial);
+                                        crate::trusted_keys::update(&cu.trusted_keys);
                                         if v0 != Config::get_rendezvous_servers() {
                                             Self::restart();
                                         }
@@ -254,6 +302,8 @@ impl RendezvousMediator {
                         continue;
                     }
                     last_timer = now;
+                    // Have a UDP endpoint at hand for the next punch hole request.
+                    crate::nat::refresh_udp_endpoint();
                     let elapsed_resp = last_register_resp.map(|x| x.elapsed().as_millis() as i64).unwrap_or(REG_INTERVAL);
                     let timeout = (elapsed_resp - last_register_sent.map(|x| x.elapsed().as_millis() as i64).unwrap_or(REG_INTERVAL)) > REG_TIMEOUT;
                     if timeout || elapsed_resp >= REG_INTERVAL {
@@ -267,9 +317,11 @@ impl RendezvousMediator {
                                 if last_dns_check.elapsed().as_millis() as i64 > DNS_INTERVAL {
                                     // in some case of network reconnect (dial IP network),
                                     // old UDP socket not work any more after network recover
-                                    if let Some((s, addr)) = socket_client::rebind_udp_for(&rz.host).await? {
-                                        socket = s;
-                                        rz.addr = addr;
+                                    if let Socket::Udp(_) = socket {
+                                        if let Some((s, addr)) = socket_client::rebind_udp_for(&rz.host).await? {
+                                            socket = Socket::Udp(s);
+                                            rz.addr = addr;
+                                        }
                                     }
                                     last_dns_check = Instant::now();
                                 }
@@ -381,9 +433,14 @@ impl RendezvousMediator {
 
     async fn handle_punch_hole(&self, ph: PunchHole, server: ServerPtr) -> ResultType<()> {
         let relay_server = self.get_relay_server(ph.relay_server);
-        if ph.nat_type.enum_value() == Ok(NatType::SYMMETRIC)
-            || Config::get_nat_type() == NatType::SYMMETRIC as i32
-        {
+        let peer_nat_info = ph.nat_info.clone().unwrap_or_default();
+        let my_nat_info = crate::nat::from_config(Config::get_nat_info());
+        if !crate::nat::can_punch(
+            Config::get_nat_type(),
+            &my_nat_info,
+            ph.nat_type.enum_value().unwrap_or(NatType::UNKNOWN_NAT),
+            &peer_nat_info,
+        ) {
             let uuid = Uuid::new_v4().to_string();
             return self
                 .create_relay(
@@ -403,9 +460,20 @@ impl RendezvousMediator {
             let local_addr = socket.local_addr();
             // key important here for punch hole to tell my gateway incoming peer is safe.
             // it can not be async here, because local_addr can not be reused, we must close the connection before use it again.
-            allow_err!(socket_client::connect_tcp_local(peer_addr, Some(local_addr), 30).await);
+            // A symmetric NAT of the peer maps the next ports, open the hole to them as well.
+            join_all(
+                crate::nat::candidate_addrs(peer_addr, &peer_nat_info)
+                    .into_iter()
+                    .map(|addr| socket_client::connect_tcp_local(addr, Some(local_addr), 30)),
+            )
+            .await;
             socket
         };
+        let udp = if ph.udp_socket_addr.is_empty() {
+            None
+        } else {
+            crate::nat::take_udp_endpoint()
+        };
         let mut msg_out = Message::new();
         use hbb_common::protobuf::Enum;
         let nat_type = NatType::from_i32(Config::get_nat_type()).unwrap_or(NatType::UNKNOWN_NAT);
@@ -415,15 +483,40 @@ impl RendezvousMediator {
             relay_server,
             nat_type: nat_type.into(),
             version: crate::VERSION.to_owned(),
+            nat_info: hbb_common::protobuf::MessageField::some(my_nat_info),
+            udp_socket_addr: udp
+                .as_ref()
+                .map(|(_, addr)| AddrMangle::encode(*addr).into())
+                .unwrap_or_default(),
             ..Default::default()
         });
         let bytes = msg_out.write_to_bytes()?;
         socket.send_raw(bytes).await?;
+        #[cfg(feature = "quic")]
+        if let Some((udp, _)) = udp {
+            let peer_udp_addr = AddrMangle::decode(&ph.udp_socket_addr);
+            let server = server.clone();
+            tokio::spawn(async move {
+                match quic::accept(udp, peer_udp_addr, UDP_PUNCH_TIMEOUT).await {
+                    Ok(stream) => allow_err!(
+                        crate::server::create_tcp_connection(
+                            server,
+                            stream,
+                            peer_udp_addr,
+                            true,
+                            false
+                        )
+                        .await
+                    ),
+                    Err(err) => log::debug!("No QUIC connection from {}: {}", peer_udp_addr, err),
+                }
+            });
+        }
         crate::accept_connection(server.clone(), socket, peer_addr, true).await;
         Ok(())
     }
 
-    async fn register_pk(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn register_pk(&mut self, socket: &mut Socket) -> ResultType<()> {
         let mut msg_out = Message::new();
         let pk = Config::get_key_pair().1;
         let uuid = hbb_common::get_uuid();
@@ -435,11 +528,11 @@ impl RendezvousMediator {
             pk: pk.into(),
             ..Default::default()
         });
-        socket.send(&msg_out, self.addr.to_owned()).await?;
+        socket.send(&msg_out, &self.addr).await?;
         Ok(())
     }
 
-    async fn handle_uuid_mismatch(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn handle_uuid_mismatch(&mut self, socket: &mut Socket) -> ResultType<()> {
         if self.last_id_pk_registry != Config::get_id() {
             return Ok(());
         }
@@ -457,7 +550,7 @@ impl RendezvousMediator {
         self.register_pk(socket).await
     }
 
-    async fn register_peer(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn register_peer(&mut self, socket: &mut Socket) -> ResultType<()> {
         if !SOLVING_PK_MISMATCH.lock().unwrap().is_empty() {
             return Ok(());
         }
@@ -481,7 +574,7 @@ impl RendezvousMediator {
             serial,
             ..Default::default()
         });
-        socket.send(&msg_out, self.addr.to_owned()).await?;
+        socket.send(&msg_out, &self.addr).await?;
         Ok(())
     }
 
@@ -497,7 +590,7 @@ impl RendezvousMediator {
     }
 }
 
-fn get_direct_port() -> i32 {
+pub(crate) fn get_direct_port() -> i32 {
     let mut port = Config::get_option("direct-access-port")
         .parse::<i32>()
         .unwrap_or(0);
@@ -553,14 +646,20 @@ async fn direct_server(server: ServerPtr) {
                     .unwrap_or(Config::get_any_listen_addr(true));
                 let server = server.clone();
                 tokio::spawn(async move {
+                    let stream = if websocket::is_enabled() && is_websocket_upgrade(&stream).await {
+                        match websocket::accept(stream, local_addr, CONNECT_TIMEOUT).await {
+                            Ok(stream) => stream,
+                            Err(err) => {
+                                log::error!("WebSocket handshake with {} failed: {}", addr, err);
+                                return;
+                            }
+                        }
+                    } else {
+                        hbb_common::Stream::from(stream, local_addr)
+                    };
                     allow_err!(
-                        crate::server::create_tcp_connection(
-                            server,
-                            hbb_common::Stream::from(stream, local_addr),
-                            addr,
-                            false,
-                        )
-                        .await
+                        crate::server::create_tcp_connection(server, stream, addr, true, true)
+                            .await
                     );
                 });
             } else {
@@ -572,6 +671,31 @@ async fn direct_server(server: ServerPtr) {
     }
 }
 
+// A native client waits for the server to speak first, a WebSocket client sends its request.
+async fn is_websocket_upgrade(stream: &tokio::net::TcpStream) -> bool {
+    const PREFIX: &[u8; 4] = b"GET ";
+    let mut buf = [0u8; 4];
+    // The request may arrive in pieces.
+    let peek = async {
+        loop {
+            let n = match stream.peek(&mut buf).await {
+                Ok(n) if n > 0 => n,
+                _ => return false,
+            };
+            if buf[..n] != PREFIX[..n] {
+                return false;
+            }
+            if n == PREFIX.len() {
+                return true;
+            }
+            sleep(0.01).await;
+        }
+    };
+    hbb_common::timeout(WEBSOCKET_DETECT_TIMEOUT, peek)
+        .await
+        .unwrap_or(false)
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn query_online_states<F: FnOnce(Vec<String>, Vec<String>)>(ids: Vec<String>, f: F) {
     let test = false;
@@ -607,6 +731,42 @@ pub async fn query_online_states<F: FnOnce(Vec<String>, Vec<String>)>(ids: Vec<S
     }
 }
 
+/// The first of `servers` the peer `id` is online with, all asked at once.
+pub async fn find_peer_server(id: &str, servers: &[String]) -> Option<String> {
+    if servers.is_empty() {
+        return None;
+    }
+    let mut msg_out = RendezvousMessage::new();
+    msg_out.set_online_request(OnlineRequest {
+        id: Config::get_id(),
+        peers: vec![id.to_owned()],
+        ..Default::default()
+    });
+    let msg_out = &msg_out;
+    select_ok(servers.iter().map(|server| {
+        Box::pin(async move {
+            let online_server = crate::increase_port(server, -1);
+            let mut socket = socket_client::connect_tcp(online_server, CONNECT_TIMEOUT).await?;
+            socket.send(msg_out).await?;
+            match crate::common::get_next_nonkeyexchange_msg(&mut socket, None)
+                .await
+                .and_then(|msg_in| msg_in.union)
+            {
+                // The first bit is the first peer.
+                Some(rendezvous_message::Union::OnlineResponse(res))
+                    if res.states.first().map(|x| x & 0x80 != 0) == Some(true) =>
+                {
+                    ResultType::Ok(server.clone())
+                }
+                _ => bail!("Not online with {}", server),
+            }
+        })
+    }))
+    .await
+    .ok()
+    .map(|(server, _)| server)
+}
+
 async fn create_online_stream() -> ResultType<FramedStream> {
     let (rendezvous_server, _servers, _contained) =
         crate::get_rendezvous_server(READ_TIMEOUT).await;
@@ -622,7 +782,7 @@ async fn create_online_stream() -> ResultType<FramedStream> {
     socket_client::connect_tcp(online_server, CONNECT_TIMEOUT).await
 }
 
-async fn query_online_states_(
+pub(crate) async fn query_online_states_(
     ids: &Vec<String>,
     timeout: std::time::Duration,
 ) -> ResultType<(Vec<String>, Vec<String>)> {
@@ -52,10 +52,15 @@ pub const NAME_POS: &'static str = "";
 }
 }
 
+pub mod access_list;
 mod connection;
+mod file_sandbox;
+mod permission_profile;
 #[cfg(windows)]
 pub mod portable_service;
+mod send_queue;
 mod service;
+mod session_limit;
 mod video_qos;
 pub mod video_service;
 
@@ -64,6 +69,8 @@ type ConnMap = HashMap<i32, ConnInner>;
 
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 const CONFIG_SYNC_INTERVAL_SECS: f32 = 0.3;
+// Old clients never answer the signed id of a direct connection, do not keep them waiting long.
+const DIRECT_HANDSHAKE_TIMEOUT: u64 = 3_000;
 
 lazy_static::lazy_static! {
     pub static ref CHILD_PROCESS: Childs = Default::default();
@@ -117,7 +124,8 @@ async fn accept_connection_(server: ServerPtr, socket: Stream, secure: bool) ->
     if let Ok((stream, addr)) = timeout(CONNECT_TIMEOUT, listener.accept()).await? {
         stream.set_nodelay(true).ok();
         let stream_addr = stream.local_addr()?;
-        create_tcp_connection(server, Stream::from(stream, stream_addr), addr, secure).await?;
+        create_tcp_connection(server, Stream::from(stream, stream_addr), addr, secure, false)
+            .await?;
     }
     Ok(())
 }
@@ -127,8 +135,10 @@ pub async fn create_tcp_connection(
     stream: Stream,
     addr: SocketAddr,
     secure: bool,
+    direct: bool,
 ) -> ResultType<()> {
     let mut stream = stream;
+    stream.set_shaper(hbb_common::bandwidth::Shaper::for_connection());
     let id = server.write().unwrap().get_new_id();
     let (sk, pk) = Config::get_key_pair();
     if secure && pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES {
@@ -149,11 +159,22 @@ pub async fn create_tcp_connection(
                 &sk,
             )
             .into(),
+            // Without the rendezvous server, the client pins this key on first use.
+            pk: if direct {
+                Bytes::from(pk.clone())
+            } else {
+                Bytes::new()
+            },
             ..Default::default()
         });
         timeout(CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
-        match timeout(CONNECT_TIMEOUT, stream.next()).await? {
-            Some(res) => {
+        let wait = if direct {
+            DIRECT_HANDSHAKE_TIMEOUT
+        } else {
+            CONNECT_TIMEOUT
+        };
+        match timeout(wait, stream.next()).await {
+            Ok(Some(res)) => {
                 let bytes = res?;
                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                     if let Some(message::Union::PublicKey(pk)) = msg_in.union {
@@ -164,8 +185,10 @@ pub async fn create_tcp_connection(
                                 &our_sk_b,
                             )?);
                         } else if pk.asymmetric_value.is_empty() {
-                            Config::set_key_confirmed(false);
-                            log::info!("Force to update pk");
+                            if !direct {
+                                Config::set_key_confirmed(false);
+                                log::info!("Force to update pk");
+                            }
                         } else {
                             bail!("Handshake failed: invalid public sign key length from peer");
                         }
@@ -176,12 +199,28 @@ pub async fn create_tcp_connection(
                     bail!("Handshake failed: invalid message format");
                 }
             }
-            None => {
+            Ok(None) => {
                 bail!("Failed to receive public key");
             }
+            Err(_) if direct => {
+                // Clients without encrypted direct connections ignore the signed id.
+                log::info!("No public key from {}, fall back to non-secure", addr);
+            }
+            Err(e) => {
+                bail!("Failed to receive public key: {}", e);
+            }
         }
     }
 
+    if crate::common::is_strict_encryption() && !stream.is_secured() {
+        let mut msg_out = Message::new();
+        let mut res = LoginResponse::new();
+        res.set_error(crate::client::LOGIN_MSG_STRICT_ENCRYPTION.to_owned());
+        msg_out.set_login_response(res);
+        timeout(CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
+        bail!("Refuse non-secure connection from {} in strict encryption mode", addr);
+    }
+
     #[cfg(target_os = "macos")]
     {
         use std::process::Command;
@@ -248,7 +287,7 @@ async fn create_relay_connection_(
         ..Default::default()
     });
     stream.send(&msg_out).await?;
-    create_tcp_connection(server, stream, peer_addr, secure).await?;
+    create_tcp_connection(server, stream, peer_addr, secure, false).await?;
     Ok(())
 }
 
@@ -0,0 +1,279 @@
+//! Ordered allow and deny rules for incoming connections, one per line in the `access-list`
+//! option:
+//!
+//! ```text
+//! deny 203.0.113.7
+//! allow 192.168.0.0/16
+//! allow 2001:db8::/32
+//! allow office.example.com
+//! deny country:RU
+//! allow asn:3320
+//! deny *
+//! ```
+//!
+//! The first matching rule decides. If no rule matches, the connection is denied if there is
+//! any allow rule, otherwise it is allowed.
+//!
+//! Hostnames are resolved again after `RESOLVE_INTERVAL`. `country:` and `asn:` rules need the
+//! offline MaxMind databases set in `geoip-country-database` and `geoip-asn-database`, they never
+//! match without them.
+//!
+//! If `access-list` is empty, the comma separated `whitelist` is used as allow rules.
+
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{config::Config, log, tokio::net::lookup_host};
+use maxminddb::{geoip2, Reader};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(300);
+
+lazy_static::lazy_static! {
+    static ref RESOLVED: Mutex<HashMap<String, (Instant, Vec<IpAddr>)>> = Default::default();
+    static ref DATABASES: Mutex<HashMap<String, Arc<Reader<Vec<u8>>>>> = Default::default();
+}
+
+#[derive(Debug, PartialEq)]
+enum Target {
+    Any,
+    Cidr(IpCidr),
+    Host(String),
+    Country(String),
+    Asn(u32),
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    allow: bool,
+    target: Target,
+    text: String,
+}
+
+pub struct Decision {
+    pub allowed: bool,
+    // The matched rule, empty if none matched.
+    pub rule: String,
+}
+
+/// Parse one rule, e.g. `allow 10.0.0.0/8` or `deny country:CN`.
+pub fn parse_rule(line: &str) -> Option<Rule> {
+    let (action, target) = line.trim().split_once(char::is_whitespace)?;
+    let allow = match action.to_lowercase().as_str() {
+        "allow" => true,
+        "deny" => false,
+        _ => return None,
+    };
+    let target = target.trim();
+    let target = if target == "*" {
+        Target::Any
+    } else if let Some(country) = target.strip_prefix("country:") {
+        if country.len() != 2 {
+            return None;
+        }
+        Target::Country(country.to_uppercase())
+    } else if let Some(asn) = target.strip_prefix("asn:") {
+        Target::Asn(asn.to_uppercase().trim_start_matches("AS").parse().ok()?)
+    } else if let Ok(cidr) = IpCidr::from_str(target) {
+        Target::Cidr(cidr)
+    } else if !target.is_empty()
+        && target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        Target::Host(target.to_lowercase())
+    } else {
+        return None;
+    };
+    Some(Rule {
+        allow,
+        target,
+        text: line.trim().to_owned(),
+    })
+}
+
+fn rules() -> Vec<Rule> {
+    let list = Config::get_option("access-list");
+    if list.trim().is_empty() {
+        return legacy_whitelist();
+    }
+    list.lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .filter_map(|x| {
+            let rule = parse_rule(x);
+            if rule.is_none() {
+                log::error!("Invalid access rule: {}", x);
+            }
+            rule
+        })
+        .collect()
+}
+
+fn legacy_whitelist() -> Vec<Rule> {
+    parse_whitelist(&Config::get_option("whitelist"))
+}
+
+fn parse_whitelist(whitelist: &str) -> Vec<Rule> {
+    let entries: Vec<&str> = whitelist.split(',').filter(|x| !x.is_empty()).collect();
+    let rules: Vec<Rule> = entries
+        .iter()
+        .filter_map(|x| {
+            let target = if *x == "0.0.0.0" {
+                Target::Any
+            } else {
+                Target::Cidr(IpCidr::from_str(x).ok()?)
+            };
+            Some(Rule {
+                allow: true,
+                target,
+                text: x.to_string(),
+            })
+        })
+        .collect();
+    if rules.is_empty() && !entries.is_empty() {
+        // A whitelist without a valid entry allows nobody.
+        log::error!("No valid entry in the whitelist: {}", whitelist);
+        return vec![Rule {
+            allow: false,
+            target: Target::Any,
+            text: whitelist.to_owned(),
+        }];
+    }
+    rules
+}
+
+pub async fn check(ip: IpAddr) -> Decision {
+    // A dual stack listener reports IPv4 clients as mapped IPv6 addresses.
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    };
+    let rules = rules();
+    for rule in rules.iter() {
+        if rule.matches(ip).await {
+            return Decision {
+                allowed: rule.allow,
+                rule: rule.text.clone(),
+            };
+        }
+    }
+    Decision {
+        allowed: !rules.iter().any(|r| r.allow),
+        rule: "".to_owned(),
+    }
+}
+
+impl Rule {
+    async fn matches(&self, ip: IpAddr) -> bool {
+        match &self.target {
+            Target::Any => true,
+            Target::Cidr(cidr) => cidr.contains(ip),
+            Target::Host(host) => resolve(host).await.contains(&ip),
+            Target::Country(country) => lookup_country(ip).as_ref() == Some(country),
+            Target::Asn(asn) => lookup_asn(ip) == Some(*asn),
+        }
+    }
+}
+
+async fn resolve(host: &str) -> Vec<IpAddr> {
+    let cached = RESOLVED.lock().unwrap().get(host).cloned();
+    if let Some((time, ips)) = cached.as_ref() {
+        if time.elapsed() < RESOLVE_INTERVAL {
+            return ips.clone();
+        }
+    }
+    match lookup_host((host, 0)).await {
+        Ok(addrs) => {
+            let ips: Vec<IpAddr> = addrs.map(|a| a.ip()).collect();
+            RESOLVED
+                .lock()
+                .unwrap()
+                .insert(host.to_owned(), (Instant::now(), ips.clone()));
+            ips
+        }
+        Err(err) => {
+            log::warn!("Failed to resolve {}: {}", host, err);
+            // Keep the last known addresses rather than locking the allowed host out.
+            cached.map(|x| x.1).unwrap_or_default()
+        }
+    }
+}
+
+fn database(option: &str) -> Option<Arc<Reader<Vec<u8>>>> {
+    let path = Config::get_option(option);
+    if path.is_empty() {
+        return None;
+    }
+    let mut databases = DATABASES.lock().unwrap();
+    if let Some(db) = databases.get(&path) {
+        return Some(db.clone());
+    }
+    match Reader::open_readfile(&path) {
+        Ok(db) => {
+            let db = Arc::new(db);
+            databases.insert(path, db.clone());
+            Some(db)
+        }
+        Err(err) => {
+            log::error!("Failed to open {}: {}", path, err);
+            None
+        }
+    }
+}
+
+fn lookup_country(ip: IpAddr) -> Option<String> {
+    let db = database("geoip-country-database")?;
+    let country: geoip2::Country = db.lookup(ip).ok()?;
+    Some(country.country?.iso_code?.to_uppercase())
+}
+
+fn lookup_asn(ip: IpAddr) -> Option<u32> {
+    let db = database("geoip-asn-database")?;
+    let asn: geoip2::Asn = db.lookup(ip).ok()?;
+    asn.autonomous_system_number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rule() {
+        let target = |s: &str| parse_rule(s).map(|r| (r.allow, r.target));
+        assert_eq!(target("deny *"), Some((false, Target::Any)));
+        assert_eq!(
+            target("allow country:de"),
+            Some((true, Target::Country("DE".to_owned())))
+        );
+        assert_eq!(target("allow asn:AS3320"), Some((true, Target::Asn(3320))));
+        assert_eq!(
+            target("Allow Office.Example.com"),
+            Some((true, Target::Host("office.example.com".to_owned())))
+        );
+        assert!(matches!(
+            target("deny 2001:db8::/32"),
+            Some((false, Target::Cidr(_)))
+        ));
+        assert!(parse_rule("permit 10.0.0.1").is_none());
+        assert!(parse_rule("allow country:DEU").is_none());
+        assert!(parse_rule("deny").is_none());
+    }
+
+    #[test]
+    fn test_parse_whitelist() {
+        let targets = |s: &str| -> Vec<(bool, Target)> {
+            parse_whitelist(s)
+                .into_iter()
+                .map(|r| (r.allow, r.target))
+                .collect()
+        };
+        assert!(targets("").is_empty());
+        assert_eq!(targets("0.0.0.0,bad"), vec![(true, Target::Any)]);
+        assert_eq!(targets("bad,worse"), vec![(false, Target::Any)]);
+    }
+}
@@ -336,6 +336,7 @@ const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
 
 fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
+    let pts = video_service::media_pts(std::time::Instant::now());
     if data.iter().filter(|x| **x != 0.).next().is_some() {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
@@ -369,6 +370,8 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                         let mut msg_out = Message::new();
                         msg_out.set_audio_frame(AudioFrame {
                             data: data.into(),
+                            // 960 interleaved stereo samples at 48000hz are 10ms
+                            pts: Some(pts + i as i64 * 10),
                             ..Default::default()
                         });
                         sp.send(msg_out);
@@ -388,6 +391,7 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
             let mut msg_out = Message::new();
             msg_out.set_audio_frame(AudioFrame {
                 data: data.into(),
+                pts: Some(pts),
                 ..Default::default()
             });
             sp.send(msg_out);
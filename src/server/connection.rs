@@ -1,4 +1,10 @@
-use super::{input_service::*, *};
+use super::{
+    file_sandbox::FileSandbox,
+    input_service::*,
+    send_queue::{Priority, SendQueue},
+    session_limit::{self, SessionLimit},
+    *,
+};
 #[cfg(windows)]
 use crate::clipboard_file::*;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -18,19 +24,21 @@ use crate::{
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use crate::{common::DEVICE_NAME, flutter::connection_manager::start_channel};
 use crate::{ipc, VERSION};
-use cidr_utils::cidr::IpCidr;
 #[cfg(all(target_os = "linux", feature = "linux_headless"))]
 #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
 use hbb_common::platform::linux::run_cmds;
 use hbb_common::{
-    config::Config,
+    chrono::Local,
+    config::{AuthorizedKey, Config},
     fs,
     fs::can_enable_overwrite_detection,
     futures::{SinkExt, StreamExt},
     get_time, get_version_number,
     message_proto::{option_message::BoolOption, permission_info::Permission},
     password_security::{self as password, ApproveMode},
-    sleep, timeout,
+    sleep,
+    sodiumoxide::crypto::sign,
+    timeout,
     tokio::{
         net::TcpStream,
         sync::mpsc,
@@ -149,11 +157,13 @@ pub struct Connection {
     server: super::ServerPtrWeak,
     hash: Hash,
     read_jobs: Vec<fs::TransferJob>,
+    send_queue: SendQueue,
     timer: Interval,
     file_timer: Interval,
     file_transfer: Option<(String, bool)>,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
     port_forward_address: String,
+    wake_on_lan: Option<WakeOnLan>,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
     keyboard: bool,
@@ -194,6 +204,10 @@ pub struct Connection {
     from_switch: bool,
     voice_call_request_timestamp: Option<NonZeroI64>,
     audio_input_device_before_voice_call: Option<String>,
+    #[cfg(target_os = "linux")]
+    virtual_mic: Option<crate::platform::linux::VirtualMic>,
+    #[cfg(target_os = "linux")]
+    gamepad: Option<super::uinput::client::UInputGamepad>,
     options_in_login: Option<OptionMessage>,
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     pressed_modifiers: HashSet<rdev::Key>,
@@ -205,6 +219,17 @@ pub struct Connection {
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     start_cm_ipc_para: Option<StartCmIpcPara>,
     auto_disconnect_timer: Option<(Instant, u64)>,
+    // The password is verified, waiting for the two-factor authentication code,
+    // with the error of the login to send after it.
+    require_2fa: Option<String>,
+    passed_2fa: bool,
+    // The temporary password of this login, remembered as a session after the second factor.
+    session_password_2fa: Option<String>,
+    trusted_device_token: String,
+    // The trusted key the client signed the login with.
+    client_key: Option<AuthorizedKey>,
+    profile: Option<super::permission_profile::Profile>,
+    session_limit: Option<SessionLimit>,
 }
 
 impl ConnInner {
@@ -260,6 +285,7 @@ impl Connection {
         let hash = Hash {
             salt: Config::get_salt(),
             challenge: Config::get_auto_password(6),
+            key_auth: !Config::get_authorized_keys().is_empty(),
             ..Default::default()
         };
         let (tx_from_cm_holder, mut rx_from_cm) = mpsc::unbounded_channel::<ipc::Data>();
@@ -291,11 +317,13 @@ impl Connection {
             server,
             hash,
             read_jobs: Vec::new(),
+            send_queue: SendQueue::default(),
             timer: time::interval(SEC30),
             file_timer: time::interval(SEC30),
             file_transfer: None,
             port_forward_socket: None,
             port_forward_address: "".to_owned(),
+            wake_on_lan: None,
             tx_to_cm,
             authorized: false,
             keyboard: Connection::permission("enable-keyboard"),
@@ -329,6 +357,10 @@ impl Connection {
             audio_sender: None,
             voice_call_request_timestamp: None,
             audio_input_device_before_voice_call: None,
+            #[cfg(target_os = "linux")]
+            virtual_mic: None,
+            #[cfg(target_os = "linux")]
+            gamepad: None,
             options_in_login: None,
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             pressed_modifiers: Default::default(),
@@ -345,6 +377,13 @@ impl Connection {
                 tx_cm_stream_ready,
             }),
             auto_disconnect_timer: None,
+            require_2fa: None,
+            passed_2fa: false,
+            session_password_2fa: None,
+            trusted_device_token: "".to_owned(),
+            client_key: None,
+            profile: None,
+            session_limit: None,
         };
         let addr = hbb_common::try_into_v4(addr);
         if !conn.on_open(addr).await {
@@ -421,6 +460,7 @@ impl Connection {
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
+                            let enabled = enabled && conn.profile_allows(&name);
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -536,6 +576,10 @@ impl Connection {
                 },
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
+                        if !conn.send_queue.bulk_ready(conn.network_delay) {
+                            continue;
+                        }
+                        conn.send_queue.bulk_sent();
                         conn.send_to_cm(ipc::Data::FileTransferLog(fs::serialize_transfer_jobs(&conn.read_jobs)));
                         match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
                             Ok(log) => {
@@ -560,27 +604,31 @@ impl Connection {
                     }
                 }
                 Some((instant, value)) = rx_video.recv() => {
-                    if !conn.video_ack_required {
+                    conn.send_queue.push(instant, value);
+                },
+                Some((instant, value)) = rx.recv() => {
+                    conn.send_queue.push(instant, value);
+                },
+                _ = std::future::ready(()), if !conn.send_queue.is_empty() => {
+                    // Everything pending is queued first, so that it goes out by priority.
+                    while let Ok((instant, value)) = rx.try_recv() {
+                        conn.send_queue.push(instant, value);
+                    }
+                    while let Ok((instant, value)) = rx_video.try_recv() {
+                        conn.send_queue.push(instant, value);
+                    }
+                    let Some((priority, instant, value)) = conn.send_queue.pop(|instant| {
                         video_service::notify_video_frame_fetched(id, Some(instant.into()));
+                    }) else {
+                        continue;
+                    };
+                    if conn.send_queue.take_key_frame_request() {
+                        video_service::request_key_frame(id);
                     }
-                    if let Err(err) = conn.stream.send(&value as &Message).await {
-                        conn.on_close(&err.to_string(), false).await;
-                        break;
+                    if priority == Priority::Video && !conn.video_ack_required {
+                        video_service::notify_video_frame_fetched(id, Some(instant.into()));
                     }
-                },
-                Some((instant, value)) = rx.recv() => {
-                    let latency = instant.elapsed().as_millis() as i64;
                     let msg: &Message = &value;
-
-                    if latency > 1000 {
-                        match &msg.union {
-                            Some(message::Union::AudioFrame(_)) => {
-                                // log::info!("audio frame latency {}", instant.elapsed().as_secs_f32());
-                                continue;
-                            }
-                            _ => {}
-                        }
-                    }
                     match &msg.union {
                         Some(message::Union::Misc(m)) => {
                             match &m.union {
@@ -609,6 +657,24 @@ impl Connection {
                             break;
                         }
                     }
+                    if let Some(limit) = conn.session_limit.as_mut() {
+                        let profile = &conn.profile;
+                        let check = limit.check(|at| {
+                            session_limit::in_access_window(at)
+                                && profile.as_ref().map_or(true, |p| p.in_time_window(at))
+                        });
+                        match check {
+                            session_limit::Check::Ok => {}
+                            session_limit::Check::Warn(minutes) => {
+                                conn.send_session_end_warning(minutes).await;
+                            }
+                            session_limit::Check::Close(reason) => {
+                                conn.send_close_reason_no_retry(reason).await;
+                                conn.on_close(reason, true).await;
+                                break;
+                            }
+                        }
+                    }
                 }
                 _ = test_delay_timer.tick() => {
                     if last_recv_time.elapsed() >= SEC30 {
@@ -647,6 +713,8 @@ impl Connection {
             conn.lr.my_id.clone(),
         );
         video_service::notify_video_frame_fetched(id, None);
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        remove_stylus_mouse_button(id);
         scrap::codec::Encoder::update(id, scrap::codec::EncodingUpdate::Remove);
         if conn.authorized {
             password::update_temporary_password();
@@ -840,28 +908,13 @@ impl Connection {
     }
 
     async fn check_whitelist(&mut self, addr: &SocketAddr) -> bool {
-        let whitelist: Vec<String> = Config::get_option("whitelist")
-            .split(",")
-            .filter(|x| !x.is_empty())
-            .map(|x| x.to_owned())
-            .collect();
-        if !whitelist.is_empty()
-            && whitelist
-                .iter()
-                .filter(|x| x == &"0.0.0.0")
-                .next()
-                .is_none()
-            && whitelist
-                .iter()
-                .filter(|x| IpCidr::from_str(x).map_or(false, |y| y.contains(addr.ip())))
-                .next()
-                .is_none()
-        {
+        let decision = super::access_list::check(addr.ip()).await;
+        if !decision.allowed {
             self.send_login_error("Your ip is blocked by the peer")
                 .await;
             Self::post_alarm_audit(
                 AlarmAuditType::IpWhitelist, //"ip whitelist",
-                json!({ "ip":addr.ip() }),
+                json!({ "ip":addr.ip(), "rule": decision.rule }),
             );
             return false;
         }
@@ -976,6 +1029,23 @@ impl Connection {
         if self.authorized {
             return;
         }
+        // Nothing else is done on such a connection, the client closes it on the response.
+        if let Some(wol) = self.wake_on_lan.take() {
+            let mut res = WakeOnLanResponse::new();
+            if let Err(err) = crate::lan::wake_on_lan(&wol) {
+                res.error = err.to_string();
+            }
+            log::info!(
+                "Wake {} on LAN for {}: {:?}",
+                wol.id,
+                self.lr.my_id,
+                res.error
+            );
+            let mut msg_out = Message::new();
+            msg_out.set_wake_on_lan_response(res);
+            self.send(msg_out).await;
+            return;
+        }
         let conn_type = if self.file_transfer.is_some() {
             1
         } else if self.port_forward_socket.is_some() {
@@ -989,6 +1059,7 @@ impl Connection {
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
+        res.trusted_device_token = std::mem::take(&mut self.trusted_device_token);
         let mut pi = PeerInfo {
             username: username.clone(),
             version: VERSION.to_owned(),
@@ -1067,6 +1138,7 @@ impl Connection {
             }
         }
         self.authorized = true;
+        self.session_limit = Some(SessionLimit::new());
         #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         PLUGIN_BLOCK_INPUT_TXS
@@ -1130,7 +1202,8 @@ impl Connection {
             } else {
                 ""
             };
-            self.read_dir(dir, show_hidden);
+            let dir = self.file_sandbox().default_dir(dir);
+            self.read_dir(&dir, show_hidden);
         } else if sub_service {
             if let Some(s) = self.server.upgrade() {
                 let mut noperms = Vec::new();
@@ -1155,6 +1228,42 @@ impl Connection {
         }
     }
 
+    #[inline]
+    fn profile_allows(&self, name: &str) -> bool {
+        self.profile.as_ref().map_or(true, |p| p.allows(name))
+    }
+
+    // The file transfer restrictions, narrowed by the directories of the profile.
+    fn file_sandbox(&self) -> FileSandbox {
+        let mut sandbox = FileSandbox::load();
+        if let Some(profile) = self.profile.as_ref() {
+            sandbox.restrict_to(&profile.paths);
+        }
+        sandbox
+    }
+
+    // Narrow the global permissions with the profile assigned to the client.
+    async fn apply_permission_profile(&mut self) -> bool {
+        self.profile = super::permission_profile::find(self.client_key.as_ref());
+        let Some(profile) = self.profile.clone() else {
+            return true;
+        };
+        log::info!("Permission profile {} for {}", profile.name, self.lr.my_id);
+        if !profile.in_time_window(&Local::now()) {
+            self.send_login_error(session_limit::OUTSIDE_ACCESS_HOURS)
+                .await;
+            sleep(1.).await;
+            return false;
+        }
+        self.keyboard &= profile.keyboard;
+        self.clipboard &= profile.clipboard;
+        self.audio &= profile.audio;
+        self.file &= profile.file;
+        self.restart &= profile.restart;
+        self.recording &= profile.recording;
+        true
+    }
+
     fn peer_keyboard_enabled(&self) -> bool {
         self.keyboard && !self.disable_keyboard
     }
@@ -1264,19 +1373,16 @@ impl Connection {
         if password::temporary_enabled() {
             let password = password::temporary_password();
             if self.validate_one_password(password.clone()) {
-                SESSIONS.lock().unwrap().insert(
-                    self.lr.my_id.clone(),
-                    Session {
-                        name: self.lr.my_name.clone(),
-                        session_id: self.lr.session_id,
-                        last_recv_time: self.last_recv_time.clone(),
-                        random_password: password,
-                    },
-                );
+                if crate::two_factor_auth::is_enabled() {
+                    // Otherwise reconnecting with the recent session skips the code.
+                    self.session_password_2fa = Some(password);
+                } else {
+                    self.insert_session(password);
+                }
                 return true;
             }
         }
-        if password::permanent_enabled() {
+        if password::permanent_enabled() && !password::permanent_expired() {
             if self.validate_one_password(Config::get_permanent_password()) {
                 return true;
             }
@@ -1284,6 +1390,118 @@ impl Connection {
         false
     }
 
+    // The password or a trusted client key is verified, the second factor may still be required.
+    async fn on_password_verified(&mut self, err_msg: String) -> bool {
+        if !self.passed_2fa
+            && crate::two_factor_auth::is_enabled()
+            && !crate::two_factor_auth::is_trusted_device(
+                &self.lr.my_id,
+                &self.lr.trusted_device_token,
+            )
+        {
+            self.require_2fa = Some(err_msg);
+            self.send_login_error(crate::client::LOGIN_MSG_2FA_REQUIRED)
+                .await;
+            return true;
+        }
+        if let Some(password) = self.session_password_2fa.take() {
+            self.insert_session(password);
+        }
+        if err_msg.is_empty() {
+            #[cfg(all(target_os = "linux", feature = "linux_headless"))]
+            #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
+            self.linux_headless_handle.wait_desktop_cm_ready().await;
+            self.send_logon_response().await;
+            self.try_start_cm(self.lr.my_id.clone(), self.lr.my_name.clone(), true);
+            if self.port_forward_socket.is_some() {
+                return false;
+            }
+        } else {
+            self.send_login_error(err_msg).await;
+        }
+        true
+    }
+
+    fn verify_client_key(&self) -> Option<AuthorizedKey> {
+        if self.lr.public_key.is_empty() || self.lr.key_signature.is_empty() {
+            return None;
+        }
+        let pk = crate::common::encode64(&self.lr.public_key);
+        let key = Config::get_authorized_keys()
+            .into_iter()
+            .find(|k| k.pk == pk)?;
+        if !key.id.is_empty() && key.id != self.lr.my_id {
+            log::warn!("Key {} is not bound to {}", key.name, self.lr.my_id);
+            return None;
+        }
+        let pk = sign::PublicKey::from_slice(&self.lr.public_key)?;
+        let expected = crate::common::key_auth_message(
+            &self.hash.challenge,
+            &self.lr.my_id,
+            &self.lr.username,
+        );
+        match sign::verify(&self.lr.key_signature, &pk) {
+            Ok(msg) if msg == expected => Some(key),
+            _ => {
+                log::warn!("Invalid key signature from {}", self.lr.my_id);
+                None
+            }
+        }
+    }
+
+    async fn handle_2fa(&mut self, tfa: TwoFactorAuth) -> bool {
+        let mut failure = LOGIN_FAILURES
+            .lock()
+            .unwrap()
+            .get(&self.ip)
+            .map(|x| x.clone())
+            .unwrap_or((0, 0, 0));
+        let time = (get_time() / 60_000) as i32;
+        if failure.2 > 30 || time == failure.0 && failure.1 > 6 {
+            self.send_login_error("Too many wrong password attempts")
+                .await;
+            return false;
+        }
+        if !crate::two_factor_auth::verify(&self.lr.my_id, &tfa.code) {
+            if failure.0 == time {
+                failure.1 += 1;
+                failure.2 += 1;
+            } else {
+                failure.0 = time;
+                failure.1 = 1;
+                failure.2 += 1;
+            }
+            LOGIN_FAILURES
+                .lock()
+                .unwrap()
+                .insert(self.ip.clone(), failure);
+            self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
+                .await;
+            return true;
+        }
+        if failure.0 != 0 {
+            LOGIN_FAILURES.lock().unwrap().remove(&self.ip);
+        }
+        let err_msg = self.require_2fa.take().unwrap_or_default();
+        self.passed_2fa = true;
+        if tfa.trust_this_device {
+            self.trusted_device_token = crate::two_factor_auth::trust_device(&self.lr.my_id);
+        }
+        self.on_password_verified(err_msg).await
+    }
+
+    fn insert_session(&self, random_password: String) {
+        SESSIONS.lock().unwrap().insert(
+            self.lr.my_id.clone(),
+            Session {
+                name: self.lr.my_name.clone(),
+                session_id: self.lr.session_id,
+                last_recv_time: self.last_recv_time.clone(),
+                random_password,
+            },
+        );
+    }
+
     fn is_recent_session(&mut self) -> bool {
         let session = SESSIONS
             .lock()
@@ -1392,9 +1610,21 @@ impl Connection {
             if self.authorized {
                 return true;
             }
+            if !session_limit::in_access_window(&Local::now()) {
+                self.send_login_error(session_limit::OUTSIDE_ACCESS_HOURS)
+                    .await;
+                sleep(1.).await;
+                return false;
+            }
+            self.client_key = self.verify_client_key();
+            if !self.apply_permission_profile().await {
+                return false;
+            }
             match lr.union {
                 Some(login_request::Union::FileTransfer(ft)) => {
-                    if !Connection::permission("enable-file-transfer") {
+                    if !Connection::permission("enable-file-transfer")
+                        || !self.profile_allows("file")
+                    {
                         self.send_login_error("No permission of file transfer")
                             .await;
                         sleep(1.).await;
@@ -1403,7 +1633,7 @@ impl Connection {
                     self.file_transfer = Some((ft.dir, ft.show_hidden));
                 }
                 Some(login_request::Union::PortForward(mut pf)) => {
-                    if !Connection::permission("enable-tunnel") {
+                    if !Connection::permission("enable-tunnel") || !self.profile_allows("tunnel") {
                         self.send_login_error("No permission of IP tunneling").await;
                         sleep(1.).await;
                         return false;
@@ -1436,6 +1666,17 @@ impl Connection {
                         }
                     }
                 }
+                Some(login_request::Union::WakeOnLan(wol)) => {
+                    if !Connection::permission("enable-wake-on-lan")
+                        || !self.profile_allows("wake-on-lan")
+                    {
+                        self.send_login_error("No permission of waking peers on LAN")
+                            .await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    self.wake_on_lan = Some(wol);
+                }
                 _ => {
                     if !self.check_privacy_mode_on().await {
                         return false;
@@ -1472,6 +1713,9 @@ impl Connection {
                 self.send_login_error(crate::client::LOGIN_MSG_OFFLINE)
                     .await;
                 return false;
+            } else if password::approve_mode() != ApproveMode::Click && self.client_key.is_some() {
+                log::info!("{} logged in with its key", self.lr.my_id);
+                return self.on_password_verified(err_msg).await;
             } else if password::approve_mode() == ApproveMode::Click
                 || password::approve_mode() == ApproveMode::Both && !password::has_valid_password()
             {
@@ -1504,6 +1748,11 @@ impl Connection {
             } else if lr.password.is_empty() {
                 if err_msg.is_empty() {
                     self.try_start_cm(lr.my_id, lr.my_name, false);
+                    // The client waits for the result of its key instead of asking for the password.
+                    if !lr.key_signature.is_empty() {
+                        self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_EMPTY)
+                            .await;
+                    }
                 } else {
                     self.send_login_error(
                         crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY_PASSWORD_EMPTY,
@@ -1566,20 +1815,13 @@ impl Connection {
                     if failure.0 != 0 {
                         LOGIN_FAILURES.lock().unwrap().remove(&self.ip);
                     }
-                    if err_msg.is_empty() {
-                        #[cfg(all(target_os = "linux", feature = "linux_headless"))]
-                        #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
-                        self.linux_headless_handle.wait_desktop_cm_ready().await;
-                        self.send_logon_response().await;
-                        self.try_start_cm(lr.my_id, lr.my_name, true);
-                        if self.port_forward_socket.is_some() {
-                            return false;
-                        }
-                    } else {
-                        self.send_login_error(err_msg).await;
-                    }
+                    return self.on_password_verified(err_msg).await;
                 }
             }
+        } else if let Some(message::Union::TwoFactorAuth(tfa)) = msg.union {
+            if self.require_2fa.is_some() && !self.authorized {
+                return self.handle_2fa(tfa).await;
+            }
         } else if let Some(message::Union::TestDelay(t)) = msg.union {
             if t.from_client {
                 let mut msg_out = Message::new();
@@ -1670,6 +1912,20 @@ impl Connection {
                     }
                     self.update_auto_disconnect_timer();
                 }
+                #[cfg(target_os = "linux")]
+                Some(message::Union::GamepadEvent(evt)) => {
+                    if self.peer_keyboard_enabled() {
+                        if self.gamepad.is_none() {
+                            if let Some(tx) = self.inner.tx.clone() {
+                                self.gamepad = Some(super::uinput::client::UInputGamepad::new(tx));
+                            }
+                        }
+                        if let Some(gamepad) = self.gamepad.as_ref() {
+                            gamepad.send(evt);
+                        }
+                    }
+                    self.update_auto_disconnect_timer();
+                }
                 #[cfg(any(target_os = "android", target_os = "ios"))]
                 Some(message::Union::KeyEvent(..)) => {}
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -1742,6 +1998,19 @@ impl Connection {
                 }
                 Some(message::Union::FileAction(fa)) => {
                     if self.file_transfer.is_some() {
+                        let sandbox = self.file_sandbox();
+                        if let Some((id, path, write)) = file_action_path(&fa) {
+                            if let Err(err) = sandbox.check(path, write) {
+                                let r#type = if write {
+                                    FileAuditType::RemoteReceive
+                                } else {
+                                    FileAuditType::RemoteSend
+                                };
+                                self.deny_file_action(r#type, id, path.to_owned(), err)
+                                    .await;
+                                return true;
+                            }
+                        }
                         match fa.union {
                             Some(file_action::Union::ReadDir(rd)) => {
                                 self.read_dir(&rd.path, rd.include_hidden);
@@ -1752,6 +2021,16 @@ impl Connection {
                                         self.send(fs::new_error(f.id, err, -1)).await;
                                     }
                                     Ok(files) => {
+                                        if let Err(err) = sandbox.check_files(&f.path, &files) {
+                                            self.deny_file_action(
+                                                FileAuditType::RemoteSend,
+                                                f.id,
+                                                f.path,
+                                                err,
+                                            )
+                                            .await;
+                                            return true;
+                                        }
                                         self.send(fs::new_dir(f.id, f.path, files)).await;
                                     }
                                 }
@@ -1776,11 +2055,23 @@ impl Connection {
                                         self.send(fs::new_error(id, err, 0)).await;
                                     }
                                     Ok(mut job) => {
+                                        // Symbolic links inside the directory may lead out of it.
+                                        if let Err(err) = sandbox.check_files(&path, job.files()) {
+                                            self.deny_file_action(
+                                                FileAuditType::RemoteSend,
+                                                id,
+                                                path,
+                                                err,
+                                            )
+                                            .await;
+                                            return true;
+                                        }
                                         self.send(fs::new_dir(id, path, job.files().to_vec()))
                                             .await;
                                         let mut files = job.files().to_owned();
                                         job.is_remote = true;
                                         job.conn_id = self.inner.id();
+                                        job.set_roots(sandbox.roots());
                                         self.read_jobs.push(job);
                                         self.file_timer = time::interval(MILLI1);
                                         self.post_file_audit(
@@ -1800,6 +2091,16 @@ impl Connection {
                                 // client to server
                                 // note: 1.1.10 introduced identical file detection, which breaks original logic of send/recv files
                                 // whenever got send/recv request, check peer version to ensure old version of rustdesk
+                                if let Err(err) = sandbox.check_files(&r.path, &r.files) {
+                                    self.deny_file_action(
+                                        FileAuditType::RemoteReceive,
+                                        r.id,
+                                        r.path,
+                                        err,
+                                    )
+                                    .await;
+                                    return true;
+                                }
                                 let od = can_enable_overwrite_detection(get_version_number(
                                     &self.lr.version,
                                 ));
@@ -1816,6 +2117,7 @@ impl Connection {
                                     overwrite_detection: od,
                                     total_size: r.total_size,
                                     conn_id: self.inner.id(),
+                                    roots: sandbox.roots(),
                                 });
                                 self.post_file_audit(
                                     FileAuditType::RemoteReceive,
@@ -1911,6 +2213,9 @@ impl Connection {
                         }
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        if !self.profile_allows("chat") {
+                            return true;
+                        }
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
@@ -1964,7 +2269,13 @@ impl Connection {
                         if !self.disable_audio {
                             // Drop the audio sender previously.
                             drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
+                            #[cfg(target_os = "linux")]
+                            let output_device = self.virtual_mic.as_ref().map(|_| {
+                                crate::platform::linux::VirtualMic::SINK_NAME.to_owned()
+                            });
+                            #[cfg(not(target_os = "linux"))]
+                            let output_device = None;
+                            self.audio_sender = Some(start_audio_thread(None, output_device));
                             self.audio_sender
                                 .as_ref()
                                 .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
@@ -1986,6 +2297,13 @@ impl Connection {
                     }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     Some(misc::Union::ChangeResolution(r)) => self.change_resolution(&r),
+                    Some(misc::Union::GetAudioInputs(_)) => {
+                        let current = Config::get_option("audio-input");
+                        self.send_audio_inputs(current).await;
+                    }
+                    Some(misc::Union::SelectAudioInput(input)) => {
+                        self.select_audio_input(input).await;
+                    }
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     Some(misc::Union::PluginRequest(p)) => {
@@ -2109,6 +2427,14 @@ impl Connection {
                 if let Some(device) = default_sound_device {
                     set_sound_input(device);
                 }
+                // Expose the peer's voice as a microphone for local applications, e.g. softphones.
+                #[cfg(target_os = "linux")]
+                if Config::get_option("allow-voice-call-virtual-mic") == "Y" {
+                    match crate::platform::linux::VirtualMic::new() {
+                        Ok(mic) => self.virtual_mic = Some(mic),
+                        Err(e) => log::error!("Failed to create virtual microphone: {}", e),
+                    }
+                }
                 self.send_to_cm(Data::StartVoiceCall);
             } else {
                 self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
@@ -2126,10 +2452,56 @@ impl Connection {
         {
             set_sound_input(sound_input);
         }
+        #[cfg(target_os = "linux")]
+        if self.virtual_mic.is_some() {
+            // The playback stream is bound to the sink of the virtual microphone.
+            self.audio_sender.take();
+            self.virtual_mic.take();
+        }
         // Notify the connection manager that the voice call has been closed.
         self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
     }
 
+    fn audio_inputs() -> Vec<String> {
+        #[cfg(target_os = "linux")]
+        {
+            use crate::platform::linux::{get_pa_sources, get_pw_app_streams, PW_APP_AUDIO_PREFIX};
+            let mut inputs: Vec<String> = get_pa_sources().drain(..).map(|x| x.1).collect();
+            inputs.extend(
+                get_pw_app_streams()
+                    .drain(..)
+                    .map(|x| format!("{}{}", PW_APP_AUDIO_PREFIX, x.1)),
+            );
+            inputs
+        }
+        #[cfg(not(target_os = "linux"))]
+        Vec::new()
+    }
+
+    async fn send_audio_inputs(&mut self, current: String) {
+        let mut misc = Misc::new();
+        misc.set_audio_inputs(AudioInputs {
+            inputs: Self::audio_inputs(),
+            current,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    async fn select_audio_input(&mut self, input: String) {
+        if !self.audio_enabled() || self.audio_input_device_before_voice_call.is_some() {
+            return;
+        }
+        if !input.is_empty() && !Self::audio_inputs().contains(&input) {
+            log::warn!("Unknown audio input: {}", input);
+            return;
+        }
+        set_sound_input(input.clone());
+        self.send_audio_inputs(input).await;
+    }
+
     async fn update_options(&mut self, o: &OptionMessage) {
         log::info!("Option update: {:?}", o);
         if let Ok(q) = o.image_quality.enum_value() {
@@ -2347,6 +2719,29 @@ impl Connection {
         SESSIONS.lock().unwrap().remove(&self.lr.my_id);
     }
 
+    async fn send_session_end_warning(&mut self, minutes: u64) {
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "info".to_owned(),
+            title: "Session limits".to_owned(),
+            text: format!("session_end_in_{{{}}}_minutes_tip", minutes),
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        self.send(msg_out).await;
+    }
+
+    async fn deny_file_action(&mut self, r#type: FileAuditType, id: i32, path: String, err: &str) {
+        log::warn!(
+            "File action on {} from {} denied: {}",
+            path,
+            self.lr.my_id,
+            err
+        );
+        self.send(fs::new_error(id, err, -1)).await;
+        self.post_file_audit(r#type, &path, vec![], json!({ "denied": err }));
+    }
+
     fn read_dir(&mut self, dir: &str, include_hidden: bool) {
         let dir = dir.to_string();
         self.send_fs(ipc::FS::ReadDir {
@@ -2452,6 +2847,20 @@ pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
         .insert(id, (tokio::time::Instant::now(), uuid));
 }
 
+// The id to report errors with, the path the file action works on, and whether it modifies it.
+fn file_action_path(fa: &FileAction) -> Option<(i32, &str, bool)> {
+    match fa.union.as_ref()? {
+        file_action::Union::ReadDir(rd) => Some((0, &rd.path, false)),
+        file_action::Union::AllFiles(f) => Some((f.id, &f.path, false)),
+        file_action::Union::Send(s) => Some((s.id, &s.path, false)),
+        file_action::Union::Receive(r) => Some((r.id, &r.path, true)),
+        file_action::Union::RemoveDir(d) => Some((d.id, &d.path, true)),
+        file_action::Union::RemoveFile(f) => Some((f.id, &f.path, true)),
+        file_action::Union::Create(c) => Some((c.id, &c.path, true)),
+        _ => None,
+    }
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 async fn start_ipc(
     mut rx_to_cm: mpsc::UnboundedReceiver<ipc::Data>,
@@ -0,0 +1,200 @@
+//! Host side restrictions of the file transfer.
+//!
+//! - `file-transfer-roots`: directories, one per line, the remote side may access. Any directory
+//!   if empty.
+//! - `file-transfer-read-only`: `Y` to refuse uploads, deletions and new directories.
+//! - `file-transfer-denied-extensions`: extensions, separated by `,`, which can not be
+//!   transferred in either direction, e.g. `exe,bat,ps1`.
+//!
+//! Paths are canonicalized before they are compared with the roots, so neither `..` nor a
+//! symbolic link can leave them.
+
+use hbb_common::{config::Config, fs, message_proto::FileEntry};
+use std::path::{Path, PathBuf};
+
+pub const PATH_NOT_ALLOWED: &str = "Access to this path is not allowed";
+pub const READ_ONLY: &str = "File transfer is read-only";
+pub const EXTENSION_NOT_ALLOWED: &str = "This file type is not allowed";
+
+#[derive(Debug, Default)]
+pub struct FileSandbox {
+    // Set if roots are configured, even if none of them exists.
+    restricted: bool,
+    roots: Vec<PathBuf>,
+    read_only: bool,
+    denied_extensions: Vec<String>,
+}
+
+impl FileSandbox {
+    pub fn load() -> Self {
+        let roots = Config::get_option("file-transfer-roots");
+        Self {
+            restricted: !roots.trim().is_empty(),
+            roots: roots
+                .lines()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                // A root which does not exist can not contain anything.
+                .filter_map(|x| std::fs::canonicalize(x).ok())
+                .collect(),
+            read_only: Config::get_option("file-transfer-read-only") == "Y",
+            denied_extensions: Config::get_option("file-transfer-denied-extensions")
+                .split(',')
+                .map(|x| x.trim().trim_start_matches('.').to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Narrow the roots to `paths`, the directories of the client's permission profile.
+    pub fn restrict_to(&mut self, paths: &[String]) {
+        if paths.is_empty() {
+            return;
+        }
+        let paths = paths
+            .iter()
+            .filter_map(|x| std::fs::canonicalize(x.trim()).ok());
+        self.roots = if self.restricted {
+            // Only the parts inside both a root and a profile path are left.
+            paths
+                .flat_map(|p| {
+                    self.roots.iter().filter_map(move |r| {
+                        if p.starts_with(r) {
+                            Some(p.clone())
+                        } else if r.starts_with(&p) {
+                            Some(r.clone())
+                        } else {
+                            None
+                        }
+                    })
+                })
+                .collect()
+        } else {
+            paths.collect()
+        };
+        self.restricted = true;
+    }
+
+    /// Check a directory or file the remote side works on, `write` if it is going to be
+    /// modified.
+    pub fn check(&self, path: &str, write: bool) -> Result<(), &'static str> {
+        if write && self.read_only {
+            return Err(READ_ONLY);
+        }
+        self.check_path(Path::new(path))
+    }
+
+    /// Check every file of a transfer from or to `dir`.
+    pub fn check_files(&self, dir: &str, files: &[FileEntry]) -> Result<(), &'static str> {
+        for f in files {
+            // The name is empty if `dir` is a single file.
+            let path = if f.name.is_empty() {
+                PathBuf::from(dir)
+            } else {
+                Path::new(dir).join(&f.name)
+            };
+            if !self.allows_extension(&path) {
+                return Err(EXTENSION_NOT_ALLOWED);
+            }
+            self.check_path(&path)?;
+        }
+        Ok(())
+    }
+
+    /// The roots an opened file must be in, `None` if not restricted.
+    pub fn roots(&self) -> Option<Vec<PathBuf>> {
+        self.restricted.then(|| self.roots.clone())
+    }
+
+    /// The directory shown first in the file transfer.
+    pub fn default_dir(&self, dir: &str) -> String {
+        match self.roots.first() {
+            Some(root) if self.check_path(Path::new(dir)).is_err() => {
+                root.to_string_lossy().to_string()
+            }
+            _ => dir.to_owned(),
+        }
+    }
+
+    fn check_path(&self, path: &Path) -> Result<(), &'static str> {
+        if !self.restricted {
+            return Ok(());
+        }
+        match fs::resolve_path(path) {
+            Some(path) if self.roots.iter().any(|r| path.starts_with(r)) => Ok(()),
+            _ => Err(PATH_NOT_ALLOWED),
+        }
+    }
+
+    fn allows_extension(&self, path: &Path) -> bool {
+        if self.denied_extensions.is_empty() {
+            return true;
+        }
+        let Some(ext) = path.extension() else {
+            return true;
+        };
+        let ext = ext.to_string_lossy().to_lowercase();
+        !self.denied_extensions.contains(&ext)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_path() {
+        let dir = std::env::temp_dir().join(format!("file_sandbox_{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink(&dir, root.join("link")).unwrap();
+        let sandbox = FileSandbox {
+            restricted: true,
+            roots: vec![std::fs::canonicalize(&root).unwrap()],
+            read_only: true,
+            denied_extensions: vec!["exe".to_owned()],
+        };
+        let path = |p: &str| root.join(p).to_string_lossy().to_string();
+        assert!(sandbox.check(&path("a/b.txt"), false).is_ok());
+        assert_eq!(sandbox.check(&path("a"), true), Err(READ_ONLY));
+        assert_eq!(sandbox.check(&path("../x"), false), Err(PATH_NOT_ALLOWED));
+        assert_eq!(sandbox.check(&path("link/x"), false), Err(PATH_NOT_ALLOWED));
+        let file = FileEntry {
+            name: "a/SETUP.EXE".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            sandbox.check_files(&path(""), &[file]),
+            Err(EXTENSION_NOT_ALLOWED)
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_restrict_to() {
+        let dir = std::env::temp_dir().join(format!("file_sandbox_r_{}", std::process::id()));
+        let share = dir.join("share");
+        std::fs::create_dir_all(share.join("a")).unwrap();
+        std::os::unix::fs::symlink(&dir, share.join("link")).unwrap();
+        let path = |p: &Path| p.to_string_lossy().to_string();
+        let mut sandbox = FileSandbox::default();
+        sandbox.restrict_to(&[path(&share)]);
+        assert!(sandbox.check(&path(&share.join("a/b.txt")), false).is_ok());
+        assert_eq!(sandbox.check(&path(&dir), false), Err(PATH_NOT_ALLOWED));
+        assert_eq!(
+            sandbox.check(&path(&share.join("link/x")), false),
+            Err(PATH_NOT_ALLOWED)
+        );
+        // Within the configured roots only.
+        sandbox.restrict_to(&[path(&dir)]);
+        assert_eq!(sandbox.check(&path(&dir), false), Err(PATH_NOT_ALLOWED));
+        sandbox.restrict_to(&[path(&share.join("a"))]);
+        assert!(sandbox.check(&path(&share.join("a/b.txt")), false).is_ok());
+        assert_eq!(
+            sandbox.check(&path(&share.join("c")), false),
+            Err(PATH_NOT_ALLOWED)
+        );
+        assert_eq!(sandbox.roots().unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
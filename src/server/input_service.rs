@@ -338,9 +338,21 @@ lazy_static::lazy_static! {
     static ref KEYS_DOWN: Arc<Mutex<HashMap<KeysDown, Instant>>> = Default::default();
     static ref LATEST_PEER_INPUT_CURSOR: Arc<Mutex<Input>> = Default::default();
     static ref LATEST_SYS_CURSOR_POS: Arc<Mutex<(Instant, (i32, i32))>> = Arc::new(Mutex::new((Instant::now().sub(MOUSE_MOVE_PROTECTION_TIMEOUT), (INVALID_CURSOR_POS, INVALID_CURSOR_POS))));
+    // The mouse button pressed by the stylus tip of each connection.
+    static ref STYLUS_MOUSE_BUTTONS: Mutex<HashMap<i32, i32>> = Default::default();
 }
 static EXITING: AtomicBool = AtomicBool::new(false);
 
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    // Pen input is simulated with the mouse if the uinput stylus is unavailable.
+    static ref STYLUS: Mutex<UInputStylusState> = Default::default();
+}
+
+// How often the desktop area is checked for the uinput stylus, also the delay to retry creating it.
+#[cfg(target_os = "linux")]
+const STYLUS_CHECK_INTERVAL: Duration = Duration::from_secs(3);
+
 const MOUSE_MOVE_PROTECTION_TIMEOUT: Duration = Duration::from_millis(1_000);
 // Actual diff of (x,y) is (1,1) here. But 5 may be tolerant.
 const MOUSE_ACTIVE_DISTANCE: i32 = 5;
@@ -783,10 +795,129 @@ pub fn handle_pointer_(evt: &PointerDeviceEvent, conn: i32) {
             }
             _ => {}
         },
+        Some(pointer_device_event::Union::StylusEvent(evt)) => {
+            handle_stylus(evt, conn);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct UInputStylusState {
+    stylus: Option<super::uinput::client::UInputStylus>,
+    // (minx, maxx, miny, maxy) of the desktop the stylus is created for.
+    area: Option<(i32, i32, i32, i32)>,
+    last_check: Option<Instant>,
+}
+
+#[cfg(target_os = "linux")]
+impl UInputStylusState {
+    fn desktop_area() -> Option<(i32, i32, i32, i32)> {
+        let displays = scrap::Display::all().ok()?;
+        let (mut minx, mut maxx, mut miny, mut maxy) = (i32::MAX, i32::MIN, i32::MAX, i32::MIN);
+        for d in displays.iter() {
+            let (x, y) = d.origin();
+            minx = minx.min(x);
+            maxx = maxx.max(x + d.width() as i32);
+            miny = miny.min(y);
+            maxy = maxy.max(y + d.height() as i32);
+        }
+        if displays.is_empty() {
+            None
+        } else {
+            Some((minx, maxx, miny, maxy))
+        }
+    }
+
+    // Re-create the stylus if the displays have changed, or if it could not be created before.
+    fn check(&mut self) {
+        // The desktop size is not known here on Wayland, the uinput mouse is used instead.
+        if !*IS_X11 {
+            return;
+        }
+        if self
+            .last_check
+            .map_or(false, |t| t.elapsed() < STYLUS_CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+        let area = Self::desktop_area();
+        if area == self.area && self.stylus.is_some() {
+            return;
+        }
+        self.stylus = None;
+        self.area = area;
+        let Some((minx, maxx, miny, maxy)) = area else {
+            return;
+        };
+        match super::uinput::client::UInputStylus::new(minx, maxx, miny, maxy) {
+            Ok(stylus) => {
+                log::info!("UInput stylus created for {:?}", area);
+                self.stylus = Some(stylus);
+            }
+            Err(e) => {
+                log::info!("Simulate stylus with mouse, {}", e);
+            }
+        }
+    }
+}
+
+fn handle_stylus(evt: &StylusEvent, conn: i32) {
+    #[cfg(target_os = "linux")]
+    {
+        let mut state = STYLUS.lock().unwrap();
+        state.check();
+        if let Some(stylus) = state.stylus.as_mut() {
+            match stylus.send(evt) {
+                Ok(()) => return,
+                Err(e) => {
+                    log::error!("Failed to send stylus event, {}", e);
+                    // Created again at the next check.
+                    state.stylus = None;
+                }
+            }
+        }
+    }
+    handle_stylus_as_mouse(evt, conn);
+}
+
+/// Simulate the pen with the left button, or the right button if the barrel button is pressed.
+fn handle_stylus_as_mouse(evt: &StylusEvent, conn: i32) {
+    let mut me = MouseEvent {
+        mask: MOUSE_TYPE_MOVE,
+        x: evt.x,
+        y: evt.y,
+        ..Default::default()
+    };
+    handle_mouse_(&me, conn);
+    let pressed = STYLUS_MOUSE_BUTTONS.lock().unwrap().get(&conn).cloned();
+    match pressed {
+        None if evt.tip_down => {
+            let button = if evt.barrel_button {
+                MOUSE_BUTTON_RIGHT
+            } else {
+                MOUSE_BUTTON_LEFT
+            };
+            STYLUS_MOUSE_BUTTONS.lock().unwrap().insert(conn, button);
+            me.mask = (button << 3) | MOUSE_TYPE_DOWN;
+            handle_mouse_(&me, conn);
+        }
+        Some(button) if !evt.tip_down => {
+            STYLUS_MOUSE_BUTTONS.lock().unwrap().remove(&conn);
+            me.mask = (button << 3) | MOUSE_TYPE_UP;
+            handle_mouse_(&me, conn);
+        }
         _ => {}
     }
 }
 
+/// Forget the button pressed by the stylus of a closed connection.
+pub fn remove_stylus_mouse_button(conn: i32) {
+    STYLUS_MOUSE_BUTTONS.lock().unwrap().remove(&conn);
+}
+
 pub fn handle_mouse_(evt: &MouseEvent, conn: i32) {
     if !active_mouse_(conn) {
         return;
@@ -0,0 +1,225 @@
+//! Named permission profiles, assigned to clients by trusted key, ID, ID pattern or address book tag.
+//!
+//! They are stored as json in the `permission-profiles` option, e.g.
+//!
+//! ```json
+//! {
+//!   "profiles": [
+//!     { "name": "helpdesk", "chat": true, "hours": ["09:00-18:00"], "weekdays": [1, 2, 3, 4, 5] },
+//!     { "name": "admin", "keyboard": true, "clipboard": true, "audio": true, "file": true,
+//!       "restart": true, "recording": true, "tunnel": true, "chat": true, "wake_on_lan": true,
+//!       "paths": ["/srv/share"] }
+//!   ],
+//!   "rules": [
+//!     { "key": "alice-laptop", "profile": "admin" },
+//!     { "id": "123456789", "profile": "admin" },
+//!     { "id": "88*", "profile": "helpdesk" },
+//!     { "tag": "support", "profile": "helpdesk" }
+//!   ]
+//! }
+//! ```
+//!
+//! The first matching rule wins. A profile only narrows the global permissions, a permission
+//! missing in the profile is denied.
+//!
+//! The ID is claimed by the client, so ID and tag rules only match the ID bound to the trusted key
+//! the client signed the login with. They are skipped for other clients.
+
+use hbb_common::{
+    chrono::{DateTime, Local},
+    config::{Ab, AuthorizedKey, Config},
+    log,
+};
+use serde_derive::{Deserialize, Serialize};
+
+const OPTION: &str = "permission-profiles";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    pub keyboard: bool,
+    pub clipboard: bool,
+    pub audio: bool,
+    pub file: bool,
+    pub restart: bool,
+    pub recording: bool,
+    pub tunnel: bool,
+    pub chat: bool,
+    pub wake_on_lan: bool,
+    // Directories the file transfer is restricted to, within `file-transfer-roots`. Any directory
+    // if empty.
+    pub paths: Vec<String>,
+    // "HH:MM-HH:MM" in local time, may wrap around midnight. Any time if empty.
+    pub hours: Vec<String>,
+    // 1 is Monday and 7 is Sunday. Every day if empty.
+    pub weekdays: Vec<u32>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Rule {
+    // Name or base64 public key of a trusted client key.
+    key: String,
+    // Exact ID, or a pattern with `*` and `?`.
+    id: String,
+    // Tag of the client in the local address book.
+    tag: String,
+    profile: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Profiles {
+    profiles: Vec<Profile>,
+    rules: Vec<Rule>,
+}
+
+/// The profile assigned to the client, `None` if no rule matches.
+///
+/// `key` is the trusted key the client signed the login with.
+pub fn find(key: Option<&AuthorizedKey>) -> Option<Profile> {
+    let option = Config::get_option(OPTION);
+    if option.is_empty() {
+        return None;
+    }
+    let profiles: Profiles = match serde_json::from_str(&option) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Invalid {}: {}", OPTION, e);
+            return None;
+        }
+    };
+    find_in(&profiles, key, peer_tags)
+}
+
+fn find_in(
+    profiles: &Profiles,
+    key: Option<&AuthorizedKey>,
+    get_tags: impl Fn(&str) -> Vec<String>,
+) -> Option<Profile> {
+    // The only ID proven by the client.
+    let peer_id = key.map(|k| k.id.as_str()).filter(|id| !id.is_empty());
+    let mut tags = None;
+    for rule in profiles.rules.iter() {
+        let matched = if !rule.key.is_empty() {
+            key.map_or(false, |k| k.name == rule.key || k.pk == rule.key)
+        } else if !rule.id.is_empty() {
+            peer_id.map_or(false, |id| {
+                wildcard_match(rule.id.as_bytes(), id.as_bytes())
+            })
+        } else if !rule.tag.is_empty() {
+            peer_id.map_or(false, |id| {
+                tags.get_or_insert_with(|| get_tags(id)).contains(&rule.tag)
+            })
+        } else {
+            false
+        };
+        if matched {
+            let profile = profiles
+                .profiles
+                .iter()
+                .find(|p| p.name == rule.profile)
+                .cloned();
+            if profile.is_none() {
+                // Deny everything instead of falling back to the global permissions.
+                log::error!("Permission profile {} not found", rule.profile);
+            }
+            return Some(profile.unwrap_or_default());
+        }
+    }
+    None
+}
+
+fn peer_tags(peer_id: &str) -> Vec<String> {
+    Ab::load()
+        .peers
+        .into_iter()
+        .find(|p| p.id == peer_id)
+        .map(|p| p.tags)
+        .unwrap_or_default()
+}
+
+fn wildcard_match(pattern: &[u8], s: &[u8]) -> bool {
+    match (pattern.first(), s.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], s) || (!s.is_empty() && wildcard_match(pattern, &s[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &s[1..]),
+        (Some(p), Some(c)) if p == c => wildcard_match(&pattern[1..], &s[1..]),
+        _ => false,
+    }
+}
+
+impl Profile {
+    /// `name` is the permission name used by the connection manager.
+    pub fn allows(&self, name: &str) -> bool {
+        match name {
+            "keyboard" => self.keyboard,
+            "clipboard" => self.clipboard,
+            "audio" => self.audio,
+            "file" => self.file,
+            "restart" => self.restart,
+            "recording" => self.recording,
+            "tunnel" => self.tunnel,
+            "chat" => self.chat,
+            "wake-on-lan" => self.wake_on_lan,
+            _ => false,
+        }
+    }
+
+    pub fn in_time_window(&self, at: &DateTime<Local>) -> bool {
+        super::session_limit::in_time_window(&self.hours, &self.weekdays, at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let profiles: Profiles = serde_json::from_str(
+            r#"{
+                "profiles": [{ "name": "admin", "file": true }, { "name": "helpdesk" }],
+                "rules": [
+                    { "key": "laptop", "profile": "admin" },
+                    { "id": "12*", "profile": "helpdesk" },
+                    { "tag": "support", "profile": "admin" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let tags = |id: &str| {
+            if id == "789" {
+                vec!["support".to_owned()]
+            } else {
+                vec![]
+            }
+        };
+        let key = |name: &str, id: &str| AuthorizedKey {
+            name: name.to_owned(),
+            id: id.to_owned(),
+            pk: "pk".to_owned(),
+        };
+        let profile = find_in(&profiles, Some(&key("laptop", "")), tags);
+        assert_eq!(profile.unwrap().name, "admin");
+        let profile = find_in(&profiles, Some(&key("phone", "123")), tags);
+        assert_eq!(profile.unwrap().name, "helpdesk");
+        let profile = find_in(&profiles, Some(&key("phone", "789")), tags);
+        assert_eq!(profile.unwrap().name, "admin");
+        // Without an ID bound to the key, ID and tag rules are skipped.
+        assert!(find_in(&profiles, Some(&key("phone", "")), tags).is_none());
+        assert!(find_in(&profiles, None, tags).is_none());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match(b"88*", b"881234567"));
+        assert!(wildcard_match(b"1?3", b"123"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(!wildcard_match(b"88*", b"781234567"));
+        assert!(!wildcard_match(b"123", b"1234"));
+    }
+}
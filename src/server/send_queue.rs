@@ -0,0 +1,191 @@
+//! The messages waiting to be sent on a connection, in queues by priority.
+//!
+//! Input and control messages go first, then audio, then video. File blocks are the bulk queue,
+//! they are read only when the other queues are empty, so a large transfer does not hold back
+//! the cursor or the keyboard. While file blocks are being sent, a video frame which has waited
+//! too long is dropped with the frames after it up to the next key frame, and a key frame is asked
+//! for. File blocks are spaced out while the network delay measured with `TestDelay` is high.
+
+use hbb_common::{
+    message_proto::{message, misc, video_frame, Message, VideoFrame},
+    tokio::time::{Duration, Instant},
+};
+use std::{collections::VecDeque, sync::Arc};
+
+const STALE_AUDIO: Duration = Duration::from_secs(1);
+const STALE_VIDEO: Duration = Duration::from_millis(300);
+// How long after the last file block the transfer is taken as going on.
+const BULK_ACTIVE: Duration = Duration::from_secs(1);
+// Network delay in ms above which file blocks are spaced out, and the space then.
+const HIGH_DELAY: u32 = 500;
+const BULK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Control,
+    Audio,
+    Video,
+}
+
+impl Priority {
+    pub fn of(msg: &Message) -> Self {
+        match &msg.union {
+            Some(message::Union::AudioFrame(_)) => Self::Audio,
+            Some(message::Union::VideoFrame(_)) => Self::Video,
+            // In order with the video frames.
+            Some(message::Union::Misc(misc)) => match &misc.union {
+                Some(misc::Union::SwitchDisplay(_)) => Self::Video,
+                _ => Self::Control,
+            },
+            _ => Self::Control,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SendQueue {
+    queues: [VecDeque<(Instant, Arc<Message>)>; 3],
+    last_bulk: Option<Instant>,
+    // Video frames are dropped until the next key frame.
+    skip_to_key_frame: bool,
+    key_frame_requested: bool,
+}
+
+impl SendQueue {
+    pub fn push(&mut self, instant: Instant, msg: Arc<Message>) {
+        self.queues[Priority::of(&msg) as usize].push_back((instant, msg));
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|q| q.is_empty())
+    }
+
+    /// The next message to send. `on_video_dropped` is called with each video frame dropped.
+    pub fn pop(
+        &mut self,
+        mut on_video_dropped: impl FnMut(Instant),
+    ) -> Option<(Priority, Instant, Arc<Message>)> {
+        if let Some((instant, msg)) = self.queues[Priority::Control as usize].pop_front() {
+            return Some((Priority::Control, instant, msg));
+        }
+        while let Some((instant, msg)) = self.queues[Priority::Audio as usize].pop_front() {
+            if instant.elapsed() <= STALE_AUDIO {
+                return Some((Priority::Audio, instant, msg));
+            }
+        }
+        let bulk_active = self.bulk_active();
+        while let Some((instant, msg)) = self.queues[Priority::Video as usize].pop_front() {
+            if let Some(message::Union::VideoFrame(vf)) = &msg.union {
+                if is_key_frame(vf) {
+                    self.skip_to_key_frame = false;
+                } else if bulk_active && !self.skip_to_key_frame && instant.elapsed() > STALE_VIDEO
+                {
+                    self.skip_to_key_frame = true;
+                    self.key_frame_requested = true;
+                }
+                if self.skip_to_key_frame {
+                    on_video_dropped(instant);
+                    continue;
+                }
+            }
+            return Some((Priority::Video, instant, msg));
+        }
+        None
+    }
+
+    /// Whether video frames have been dropped since the last call, and a key frame is needed.
+    pub fn take_key_frame_request(&mut self) -> bool {
+        std::mem::take(&mut self.key_frame_requested)
+    }
+
+    /// Whether the next file block may be read and sent now.
+    pub fn bulk_ready(&self, network_delay: Option<u32>) -> bool {
+        if !self.is_empty() {
+            return false;
+        }
+        match (network_delay, self.last_bulk) {
+            (Some(delay), Some(last)) if delay > HIGH_DELAY => last.elapsed() >= BULK_INTERVAL,
+            _ => true,
+        }
+    }
+
+    pub fn bulk_sent(&mut self) {
+        self.last_bulk = Some(Instant::now());
+    }
+
+    fn bulk_active(&self) -> bool {
+        self.last_bulk
+            .map_or(false, |last| last.elapsed() < BULK_ACTIVE)
+    }
+}
+
+fn is_key_frame(vf: &VideoFrame) -> bool {
+    use video_frame::Union::*;
+    match &vf.union {
+        Some(Vp9s(f) | H264s(f) | H265s(f) | Vp8s(f) | Av1s(f)) => f.frames.iter().any(|f| f.key),
+        // Raw frames do not depend on the ones before.
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::message_proto::{AudioFrame, EncodedVideoFrame, EncodedVideoFrames, KeyEvent};
+
+    fn video(key: bool) -> Arc<Message> {
+        let mut frames = EncodedVideoFrames::new();
+        frames.frames.push(EncodedVideoFrame {
+            key,
+            ..Default::default()
+        });
+        let mut vf = VideoFrame::new();
+        vf.set_vp9s(frames);
+        let mut msg = Message::new();
+        msg.set_video_frame(vf);
+        Arc::new(msg)
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut queue = SendQueue::default();
+        let now = Instant::now();
+        let mut audio = Message::new();
+        audio.set_audio_frame(AudioFrame::new());
+        let mut key = Message::new();
+        key.set_key_event(KeyEvent::new());
+        queue.push(now, video(true));
+        queue.push(now, Arc::new(audio));
+        queue.push(now, Arc::new(key));
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop(|_| {}))
+            .map(|(priority, _, _)| priority)
+            .collect();
+        assert_eq!(
+            order,
+            vec![Priority::Control, Priority::Audio, Priority::Video]
+        );
+        assert!(queue.bulk_ready(None));
+    }
+
+    #[test]
+    fn test_drop_stale_video() {
+        let mut queue = SendQueue::default();
+        let stale = Instant::now() - STALE_VIDEO * 2;
+        queue.push(stale, video(false));
+        queue.push(stale, video(false));
+        queue.push(Instant::now(), video(true));
+        // Nothing is dropped without file transfer.
+        assert!(queue.pop(|_| panic!()).is_some());
+        queue.bulk_sent();
+        assert!(!queue.bulk_ready(None));
+        let mut dropped = 0;
+        assert!(queue.pop(|_| dropped += 1).is_some());
+        assert_eq!(dropped, 1);
+        assert!(queue.take_key_frame_request());
+        assert!(!queue.take_key_frame_request());
+        assert!(queue.is_empty());
+        assert!(queue.bulk_ready(None));
+        assert!(!queue.bulk_ready(Some(HIGH_DELAY + 1)));
+    }
+}
@@ -0,0 +1,151 @@
+//! Absolute limits of incoming sessions, unlike the idle timer they apply however busy the
+//! session is.
+//!
+//! - `session-max-duration`: minutes a session may last, unlimited if empty or 0.
+//! - `access-hours`: "HH:MM-HH:MM" windows in local time, separated by `,`, any time if empty
+//!   or malformed.
+//! - `access-weekdays`: days separated by `,`, 1 is Monday and 7 is Sunday, every day if empty.
+//!
+//! The remote side is warned a few minutes before the session is closed.
+
+use hbb_common::{
+    chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveTime, Timelike},
+    config::Config,
+};
+use std::time::{Duration, Instant};
+
+pub const OUTSIDE_ACCESS_HOURS: &str = "Access is not allowed at this time";
+pub const MAX_DURATION_REACHED: &str = "The maximum session duration is reached";
+// Minutes before the end, in descending order.
+const WARNINGS: [u64; 2] = [5, 1];
+
+pub enum Check {
+    Ok,
+    // Minutes left.
+    Warn(u64),
+    Close(&'static str),
+}
+
+pub struct SessionLimit {
+    start: Instant,
+    max_duration: Option<Duration>,
+    // Number of warnings sent.
+    warned: usize,
+}
+
+impl SessionLimit {
+    pub fn new() -> Self {
+        let minutes: u64 = Config::get_option("session-max-duration")
+            .parse()
+            .unwrap_or(0);
+        Self {
+            start: Instant::now(),
+            max_duration: (minutes > 0).then(|| Duration::from_secs(minutes * 60)),
+            warned: 0,
+        }
+    }
+
+    /// Called every second, `allowed_at` tells if the session may still run at a given time.
+    pub fn check(&mut self, allowed_at: impl Fn(&DateTime<Local>) -> bool) -> Check {
+        let now = Local::now();
+        if !allowed_at(&now) {
+            return Check::Close(OUTSIDE_ACCESS_HOURS);
+        }
+        let left = self
+            .max_duration
+            .map(|max| max.saturating_sub(self.start.elapsed()));
+        if left == Some(Duration::ZERO) {
+            return Check::Close(MAX_DURATION_REACHED);
+        }
+        let ends_within = |minutes: u64| {
+            left.map_or(false, |left| left <= Duration::from_secs(minutes * 60))
+                || !allowed_at(&(now + ChronoDuration::minutes(minutes as _)))
+        };
+        let mut warning = None;
+        // Only the last one if the session starts close to its end.
+        while self.warned < WARNINGS.len() && ends_within(WARNINGS[self.warned]) {
+            warning = Some(WARNINGS[self.warned]);
+            self.warned += 1;
+        }
+        match warning {
+            Some(minutes) => Check::Warn(minutes),
+            None => Check::Ok,
+        }
+    }
+}
+
+fn split_option(name: &str) -> Vec<String> {
+    Config::get_option(name)
+        .split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// The access window configured for all clients.
+pub fn in_access_window(at: &DateTime<Local>) -> bool {
+    let weekdays: Vec<u32> = split_option("access-weekdays")
+        .iter()
+        .filter_map(|x| x.parse().ok())
+        .collect();
+    let mut hours = split_option("access-hours");
+    if let Some(h) = hours.iter().find(|h| parse_hours(h).is_none()) {
+        log::warn!("Ignore access-hours, {} is not HH:MM-HH:MM", h);
+        hours.clear();
+    }
+    in_time_window(&hours, &weekdays, at)
+}
+
+fn parse_hours(hours: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = hours.split_once('-')?;
+    Some((
+        NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?,
+        NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?,
+    ))
+}
+
+/// `hours` are "HH:MM-HH:MM" and may wrap around midnight.
+pub fn in_time_window(hours: &[String], weekdays: &[u32], at: &DateTime<Local>) -> bool {
+    if !weekdays.is_empty() && !weekdays.contains(&at.weekday().number_from_monday()) {
+        return false;
+    }
+    if hours.is_empty() {
+        return true;
+    }
+    let now = NaiveTime::from_hms_opt(at.hour(), at.minute(), 0).unwrap_or_default();
+    hours.iter().any(|h| {
+        let Some((start, end)) = parse_hours(h) else {
+            return false;
+        };
+        if start <= end {
+            start <= now && now < end
+        } else {
+            now >= start || now < end
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::chrono::TimeZone;
+
+    #[test]
+    fn test_in_time_window() {
+        // A Wednesday.
+        let at = Local.with_ymd_and_hms(2023, 5, 10, 23, 30, 0).unwrap();
+        let hours = |v: &[&str]| v.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert!(in_time_window(&[], &[], &at));
+        assert!(in_time_window(&[], &[1, 2, 3, 4, 5], &at));
+        assert!(!in_time_window(&[], &[6, 7], &at));
+        assert!(!in_time_window(&hours(&["09:00-18:00"]), &[], &at));
+        assert!(in_time_window(
+            &hours(&["09:00-18:00", "22:00-06:00"]),
+            &[],
+            &at
+        ));
+        assert!(!in_time_window(&hours(&["22:00-23:30"]), &[], &at));
+        assert!(parse_hours("9-18").is_none());
+        assert!(parse_hours("09:00-24:00").is_none());
+    }
+}
@@ -1,16 +1,29 @@
-use crate::ipc::{self, new_listener, Connection, Data, DataKeyboard, DataMouse};
+use crate::ipc::{
+    self, new_listener, Connection, Data, DataGamepad, DataKeyboard, DataMouse, DataStylus,
+};
 use enigo::{Key, KeyboardControllable, MouseButton, MouseControllable};
 use evdev::{
     uinput::{VirtualDevice, VirtualDeviceBuilder},
     AttributeSet, EventType, InputEvent,
 };
-use hbb_common::{allow_err, bail, log, tokio::{self, runtime::Runtime}, ResultType};
+use hbb_common::{
+    allow_err, bail, log,
+    message_proto::{
+        gamepad_event, GamepadAxis, GamepadButton, GamepadEvent, GamepadRumble, Message,
+        StylusEvent,
+    },
+    protobuf::{Enum as _, Message as _},
+    tokio::{self, runtime::Runtime},
+    ResultType,
+};
 
 static IPC_CONN_TIMEOUT: u64 = 1000;
 static IPC_REQUEST_TIMEOUT: u64 = 1000;
 static IPC_POSTFIX_KEYBOARD: &str = "_uinput_keyboard";
 static IPC_POSTFIX_MOUSE: &str = "_uinput_mouse";
 static IPC_POSTFIX_CONTROL: &str = "_uinput_control";
+static IPC_POSTFIX_GAMEPAD: &str = "_uinput_gamepad";
+static IPC_POSTFIX_STYLUS: &str = "_uinput_stylus";
 
 pub mod client {
     use super::*;
@@ -166,6 +179,101 @@ pub mod client {
         let _ = conn.next().await?;
         Ok(())
     }
+
+    pub struct UInputStylus {
+        conn: Connection,
+        rt: Runtime,
+    }
+
+    impl UInputStylus {
+        /// Create a pen tablet covering the desktop, fails if the uinput service is unavailable.
+        pub fn new(minx: i32, maxx: i32, miny: i32, maxy: i32) -> ResultType<Self> {
+            let rt = Runtime::new()?;
+            let mut conn = rt.block_on(ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_STYLUS))?;
+            rt.block_on(conn.send(&Data::Stylus(DataStylus::Resolution {
+                minx,
+                maxx,
+                miny,
+                maxy,
+            })))?;
+            match rt.block_on(conn.next_timeout(IPC_REQUEST_TIMEOUT)) {
+                Ok(Some(Data::Empty)) => Ok(Self { conn, rt }),
+                Ok(_) => bail!("Failed to create uinput stylus"),
+                Err(e) => bail!("Failed to create uinput stylus, {}", e),
+            }
+        }
+
+        pub fn send(&mut self, evt: &StylusEvent) -> ResultType<()> {
+            let bytes = evt.write_to_bytes()?;
+            self.rt
+                .block_on(self.conn.send(&Data::Stylus(DataStylus::Event(bytes))))
+        }
+    }
+
+    /// Forwards the gamepad events of one connection to the uinput service,
+    /// and the rumble requested by the applications back to the peer.
+    ///
+    /// The virtual gamepads are removed when this is dropped.
+    pub struct UInputGamepad {
+        tx: tokio::sync::mpsc::UnboundedSender<GamepadEvent>,
+    }
+
+    impl UInputGamepad {
+        pub fn new(peer: crate::server::connection::Sender) -> Self {
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<GamepadEvent>();
+            tokio::spawn(async move {
+                let mut conn = match ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_GAMEPAD).await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        log::error!("Failed to connect uinput gamepad service: {}", e);
+                        return;
+                    }
+                };
+                loop {
+                    tokio::select! {
+                        evt = rx.recv() => {
+                            let Some(evt) = evt else {
+                                break;
+                            };
+                            match evt.write_to_bytes() {
+                                Ok(bytes) => {
+                                    if let Err(e) = conn.send(&Data::Gamepad(DataGamepad::Event(bytes))).await {
+                                        log::error!("Failed to send gamepad event: {}", e);
+                                        break;
+                                    }
+                                }
+                                Err(e) => log::error!("Failed to serialize gamepad event: {}", e),
+                            }
+                        }
+                        res = conn.next() => {
+                            match res {
+                                Err(err) => {
+                                    log::info!("UInput gamepad ipc connection closed: {}", err);
+                                    break;
+                                }
+                                Ok(Some(Data::Gamepad(DataGamepad::Rumble(bytes)))) => {
+                                    if let Ok(rumble) = GamepadRumble::parse_from_bytes(&bytes) {
+                                        let mut msg = Message::new();
+                                        msg.set_gamepad_rumble(rumble);
+                                        if peer.send((std::time::Instant::now(), std::sync::Arc::new(msg))).is_err() {
+                                            break;
+                                        }
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            });
+            Self { tx }
+        }
+
+        #[inline]
+        pub fn send(&self, evt: GamepadEvent) {
+            allow_err!(self.tx.send(evt));
+        }
+    }
 }
 
 pub mod service {
@@ -655,6 +763,441 @@ pub mod service {
         });
     }
 
+    // The fork used for the keyboard lacks force feedback and absolute axes for uinput devices.
+    mod gamepad {
+        use super::*;
+        use evdev_ff::{
+            uinput::{VirtualDevice, VirtualDeviceBuilder},
+            AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, FFEffectData, FFEffectKind,
+            FFEffectType, InputEvent, InputEventKind, InputId, UInputEventType, UinputAbsSetup,
+        };
+        use std::os::unix::io::AsRawFd;
+
+        // Xbox 360 controller ids, so that games pick a known button mapping.
+        const GAMEPAD_VENDOR: u16 = 0x045e;
+        const GAMEPAD_PRODUCT: u16 = 0x028e;
+        const GAMEPAD_FF_POLL_INTERVAL: u64 = 10;
+
+        struct VirtualGamepad {
+            device: VirtualDevice,
+            effects: HashMap<i16, FFEffectData>,
+        }
+
+        fn create_uinput_gamepad(index: u32) -> ResultType<VirtualGamepad> {
+            let mut keys = AttributeSet::<evdev_ff::Key>::new();
+            for button in GamepadButton::VALUES {
+                keys.insert(map_gamepad_button(*button));
+            }
+            let stick = AbsInfo::new(0, i16::MIN as _, i16::MAX as _, 16, 128, 0);
+            let trigger = AbsInfo::new(0, 0, i16::MAX as _, 0, 0, 0);
+            let hat = AbsInfo::new(0, -1, 1, 0, 0, 0);
+            let mut ff = AttributeSet::<FFEffectType>::new();
+            ff.insert(FFEffectType::FF_RUMBLE);
+            let name = format!("RustDesk UInput Gamepad {}", index);
+            let mut builder = VirtualDeviceBuilder::new()?
+                .name(&name)
+                .input_id(InputId::new(
+                    BusType::BUS_USB,
+                    GAMEPAD_VENDOR,
+                    GAMEPAD_PRODUCT,
+                    0x110,
+                ))
+                .with_keys(&keys)?;
+            for axis in GamepadAxis::VALUES {
+                let info = match axis {
+                    GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => trigger,
+                    _ => stick,
+                };
+                builder = builder
+                    .with_absolute_axis(&UinputAbsSetup::new(map_gamepad_axis(*axis), info))?;
+            }
+            let gamepad = builder
+                .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_HAT0X, hat))?
+                .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_HAT0Y, hat))?
+                .with_ff(&ff)?
+                .with_ff_effects_max(16)
+                .build()?;
+            // Force feedback requests are polled together with the ipc messages.
+            unsafe {
+                let fd = gamepad.as_raw_fd();
+                let flags = hbb_common::libc::fcntl(fd, hbb_common::libc::F_GETFL);
+                hbb_common::libc::fcntl(
+                    fd,
+                    hbb_common::libc::F_SETFL,
+                    flags | hbb_common::libc::O_NONBLOCK,
+                );
+            }
+            Ok(VirtualGamepad {
+                device: gamepad,
+                effects: HashMap::new(),
+            })
+        }
+
+        fn map_gamepad_button(button: GamepadButton) -> evdev_ff::Key {
+            match button {
+                GamepadButton::South => evdev_ff::Key::BTN_SOUTH,
+                GamepadButton::East => evdev_ff::Key::BTN_EAST,
+                GamepadButton::North => evdev_ff::Key::BTN_NORTH,
+                GamepadButton::West => evdev_ff::Key::BTN_WEST,
+                GamepadButton::LeftBumper => evdev_ff::Key::BTN_TL,
+                GamepadButton::RightBumper => evdev_ff::Key::BTN_TR,
+                GamepadButton::Back => evdev_ff::Key::BTN_SELECT,
+                GamepadButton::Start => evdev_ff::Key::BTN_START,
+                GamepadButton::Guide => evdev_ff::Key::BTN_MODE,
+                GamepadButton::LeftThumb => evdev_ff::Key::BTN_THUMBL,
+                GamepadButton::RightThumb => evdev_ff::Key::BTN_THUMBR,
+            }
+        }
+
+        fn map_gamepad_axis(axis: GamepadAxis) -> AbsoluteAxisType {
+            match axis {
+                GamepadAxis::LeftX => AbsoluteAxisType::ABS_X,
+                GamepadAxis::LeftY => AbsoluteAxisType::ABS_Y,
+                GamepadAxis::RightX => AbsoluteAxisType::ABS_RX,
+                GamepadAxis::RightY => AbsoluteAxisType::ABS_RY,
+                GamepadAxis::LeftTrigger => AbsoluteAxisType::ABS_Z,
+                GamepadAxis::RightTrigger => AbsoluteAxisType::ABS_RZ,
+            }
+        }
+
+        fn handle_gamepad(gamepads: &mut HashMap<u32, VirtualGamepad>, evt: GamepadEvent) {
+            let index = evt.index;
+            let events = match evt.union {
+                Some(gamepad_event::Union::Connected(false)) => {
+                    gamepads.remove(&index);
+                    return;
+                }
+                Some(gamepad_event::Union::Connected(true)) => vec![],
+                Some(gamepad_event::Union::Button(button)) => {
+                    let key = map_gamepad_button(button.button.enum_value_or_default());
+                    vec![InputEvent::new(EventType::KEY, key.code(), button.down as i32)]
+                }
+                Some(gamepad_event::Union::Axis(axis)) => {
+                    let abs = map_gamepad_axis(axis.axis.enum_value_or_default());
+                    vec![InputEvent::new(EventType::ABSOLUTE, abs.0, axis.value)]
+                }
+                Some(gamepad_event::Union::Hat(hat)) => vec![
+                    InputEvent::new(
+                        EventType::ABSOLUTE,
+                        AbsoluteAxisType::ABS_HAT0X.0,
+                        hat.x.clamp(-1, 1),
+                    ),
+                    InputEvent::new(
+                        EventType::ABSOLUTE,
+                        AbsoluteAxisType::ABS_HAT0Y.0,
+                        hat.y.clamp(-1, 1),
+                    ),
+                ],
+                _ => return,
+            };
+            if !gamepads.contains_key(&index) {
+                match create_uinput_gamepad(index) {
+                    Ok(gamepad) => {
+                        log::info!("Created uinput gamepad {}", index);
+                        gamepads.insert(index, gamepad);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to create gamepad {}, {}", index, e);
+                        return;
+                    }
+                }
+            }
+            if let Some(gamepad) = gamepads.get_mut(&index) {
+                if !events.is_empty() {
+                    allow_err!(gamepad.device.emit(&events));
+                }
+            }
+        }
+
+        /// Handle the force feedback requests of the applications, return the rumble to play on the peer.
+        fn poll_gamepad_ff(index: u32, gamepad: &mut VirtualGamepad) -> Vec<GamepadRumble> {
+            let mut rumbles = vec![];
+            let events: Vec<_> = match gamepad.device.fetch_events() {
+                Ok(events) => events.collect(),
+                Err(_) => return rumbles,
+            };
+            for evt in events {
+                match evt.kind() {
+                    InputEventKind::UInput(code) if code == UInputEventType::UI_FF_UPLOAD.0 => {
+                        if let Ok(upload) = gamepad.device.process_ff_upload(evt) {
+                            gamepad.effects.insert(upload.effect_id(), upload.effect());
+                        }
+                    }
+                    InputEventKind::UInput(code) if code == UInputEventType::UI_FF_ERASE.0 => {
+                        if let Ok(erase) = gamepad.device.process_ff_erase(evt) {
+                            gamepad.effects.remove(&(erase.effect_id() as i16));
+                        }
+                    }
+                    InputEventKind::ForceFeedback(id) => {
+                        let mut rumble = GamepadRumble {
+                            index,
+                            ..Default::default()
+                        };
+                        // A value of 0 stops the effect.
+                        if evt.value() > 0 {
+                            match gamepad.effects.get(&(id as i16)) {
+                                Some(FFEffectData {
+                                    kind:
+                                        FFEffectKind::Rumble {
+                                            strong_magnitude,
+                                            weak_magnitude,
+                                        },
+                                    replay,
+                                    ..
+                                }) => {
+                                    rumble.strong_magnitude = *strong_magnitude as _;
+                                    rumble.weak_magnitude = *weak_magnitude as _;
+                                    rumble.duration_ms = replay.length as _;
+                                }
+                                _ => continue,
+                            }
+                        }
+                        rumbles.push(rumble);
+                    }
+                    _ => {}
+                }
+            }
+            rumbles
+        }
+
+        pub(super) fn spawn_gamepad_handler(mut stream: ipc::Connection) {
+            tokio::spawn(async move {
+                let mut gamepads: HashMap<u32, VirtualGamepad> = HashMap::new();
+                let mut timer = tokio::time::interval(std::time::Duration::from_millis(
+                    GAMEPAD_FF_POLL_INTERVAL,
+                ));
+                loop {
+                    tokio::select! {
+                        res = stream.next() => {
+                            match res {
+                                Err(err) => {
+                                    log::info!("UInput gamepad ipc connection closed: {}", err);
+                                    break;
+                                }
+                                Ok(Some(data)) => {
+                                    match data {
+                                        Data::Gamepad(DataGamepad::Event(bytes)) => {
+                                            if let Ok(evt) = GamepadEvent::parse_from_bytes(&bytes) {
+                                                handle_gamepad(&mut gamepads, evt);
+                                            }
+                                        }
+                                        _ => {
+                                        }
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                        _ = timer.tick(), if !gamepads.is_empty() => {
+                            for (index, gamepad) in gamepads.iter_mut() {
+                                for rumble in poll_gamepad_ff(*index, gamepad) {
+                                    if let Ok(bytes) = rumble.write_to_bytes() {
+                                        allow_err!(stream.send(&Data::Gamepad(DataGamepad::Rumble(bytes))).await);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    mod stylus {
+        use super::*;
+        use evdev_ff::{
+            uinput::{VirtualDevice, VirtualDeviceBuilder},
+            AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, InputEvent, InputId,
+            UinputAbsSetup,
+        };
+
+        // Tablet coordinates, the desktop is scaled to this range.
+        const STYLUS_ABS_MAX: i32 = 32767;
+        const STYLUS_PRESSURE_MAX: i32 = 4095;
+        // Without hovering events for this long, the pen is regarded as out of range.
+        const STYLUS_PROXIMITY_TIMEOUT: u64 = 500;
+
+        struct VirtualStylus {
+            device: VirtualDevice,
+            rect: (i32, i32, i32, i32),
+            tool: Option<evdev_ff::Key>,
+            tip_down: bool,
+            last_event: std::time::Instant,
+        }
+
+        impl VirtualStylus {
+            fn new(minx: i32, maxx: i32, miny: i32, maxy: i32) -> ResultType<Self> {
+                if minx >= maxx || miny >= maxy {
+                    bail!("Invalid stylus resolution");
+                }
+                let mut keys = AttributeSet::<evdev_ff::Key>::new();
+                keys.insert(evdev_ff::Key::BTN_TOOL_PEN);
+                keys.insert(evdev_ff::Key::BTN_TOOL_RUBBER);
+                keys.insert(evdev_ff::Key::BTN_TOUCH);
+                keys.insert(evdev_ff::Key::BTN_STYLUS);
+                keys.insert(evdev_ff::Key::BTN_STYLUS2);
+                let mut props = AttributeSet::<evdev_ff::PropType>::new();
+                // Map the tablet to the screen.
+                props.insert(evdev_ff::PropType::DIRECT);
+                // Resolutions are in units per millimeter and units per radian for tilt.
+                let pos = AbsInfo::new(0, 0, STYLUS_ABS_MAX, 0, 0, 100);
+                let pressure = AbsInfo::new(0, 0, STYLUS_PRESSURE_MAX, 0, 0, 0);
+                let tilt = AbsInfo::new(0, -90, 90, 0, 0, 57);
+                let device = VirtualDeviceBuilder::new()?
+                    .name("RustDesk UInput Stylus")
+                    .input_id(InputId::new(BusType::BUS_VIRTUAL, 0, 0, 0))
+                    .with_keys(&keys)?
+                    .with_properties(&props)?
+                    .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_X, pos))?
+                    .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, pos))?
+                    .with_absolute_axis(&UinputAbsSetup::new(
+                        AbsoluteAxisType::ABS_PRESSURE,
+                        pressure,
+                    ))?
+                    .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_TILT_X, tilt))?
+                    .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_TILT_Y, tilt))?
+                    .build()?;
+                Ok(Self {
+                    device,
+                    rect: (minx, maxx, miny, maxy),
+                    tool: None,
+                    tip_down: false,
+                    last_event: std::time::Instant::now(),
+                })
+            }
+
+            fn scale(v: i32, min: i32, max: i32) -> i32 {
+                ((v.clamp(min, max) - min) as i64 * STYLUS_ABS_MAX as i64 / (max - min) as i64)
+                    as i32
+            }
+
+            fn key(key: evdev_ff::Key, down: bool) -> InputEvent {
+                InputEvent::new(EventType::KEY, key.code(), down as i32)
+            }
+
+            fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+                InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+            }
+
+            fn handle(&mut self, evt: &StylusEvent) -> std::io::Result<()> {
+                self.last_event = std::time::Instant::now();
+                if !evt.in_range && !evt.tip_down {
+                    return self.leave();
+                }
+                let (minx, maxx, miny, maxy) = self.rect;
+                let tool = if evt.eraser {
+                    evdev_ff::Key::BTN_TOOL_RUBBER
+                } else {
+                    evdev_ff::Key::BTN_TOOL_PEN
+                };
+                let mut events = vec![];
+                if self.tool != Some(tool) {
+                    // Switching between the pen and the eraser needs leaving the proximity first.
+                    self.leave()?;
+                    events.push(Self::key(tool, true));
+                    self.tool = Some(tool);
+                }
+                let pressure = if evt.tip_down {
+                    (evt.pressure.clamp(0., 1.) * STYLUS_PRESSURE_MAX as f32).round() as i32
+                } else {
+                    0
+                };
+                events.extend([
+                    Self::abs(AbsoluteAxisType::ABS_X, Self::scale(evt.x, minx, maxx)),
+                    Self::abs(AbsoluteAxisType::ABS_Y, Self::scale(evt.y, miny, maxy)),
+                    Self::abs(AbsoluteAxisType::ABS_PRESSURE, pressure),
+                    Self::abs(AbsoluteAxisType::ABS_TILT_X, evt.tilt_x.clamp(-90, 90)),
+                    Self::abs(AbsoluteAxisType::ABS_TILT_Y, evt.tilt_y.clamp(-90, 90)),
+                    Self::key(evdev_ff::Key::BTN_TOUCH, evt.tip_down),
+                    Self::key(evdev_ff::Key::BTN_STYLUS, evt.barrel_button),
+                    Self::key(evdev_ff::Key::BTN_STYLUS2, evt.barrel_button2),
+                ]);
+                self.tip_down = evt.tip_down;
+                self.device.emit(&events)
+            }
+
+            fn leave(&mut self) -> std::io::Result<()> {
+                if let Some(tool) = self.tool.take() {
+                    self.tip_down = false;
+                    self.device.emit(&[
+                        Self::abs(AbsoluteAxisType::ABS_PRESSURE, 0),
+                        Self::key(evdev_ff::Key::BTN_TOUCH, false),
+                        Self::key(evdev_ff::Key::BTN_STYLUS, false),
+                        Self::key(evdev_ff::Key::BTN_STYLUS2, false),
+                        Self::key(tool, false),
+                    ])?;
+                }
+                Ok(())
+            }
+
+            fn check_proximity(&mut self) {
+                if self.tool.is_some()
+                    && !self.tip_down
+                    && self.last_event.elapsed().as_millis() as u64 > STYLUS_PROXIMITY_TIMEOUT
+                {
+                    allow_err!(self.leave());
+                }
+            }
+        }
+
+        pub(super) fn spawn_stylus_handler(mut stream: ipc::Connection) {
+            tokio::spawn(async move {
+                let mut stylus: Option<VirtualStylus> = None;
+                let mut timer = tokio::time::interval(std::time::Duration::from_millis(
+                    STYLUS_PROXIMITY_TIMEOUT,
+                ));
+                loop {
+                    tokio::select! {
+                        res = stream.next() => {
+                            match res {
+                                Err(err) => {
+                                    log::info!("UInput stylus ipc connection closed: {}", err);
+                                    break;
+                                }
+                                Ok(Some(data)) => {
+                                    match data {
+                                        Data::Stylus(DataStylus::Resolution { minx, maxx, miny, maxy }) => {
+                                            log::info!(
+                                                "Create uinput stylus with rng_x: ({}, {}), rng_y: ({}, {})",
+                                                minx,
+                                                maxx,
+                                                miny,
+                                                maxy
+                                            );
+                                            match VirtualStylus::new(minx, maxx, miny, maxy) {
+                                                Ok(s) => {
+                                                    stylus = Some(s);
+                                                    allow_err!(stream.send(&Data::Empty).await);
+                                                }
+                                                Err(e) => {
+                                                    log::error!("Failed to create stylus, {}", e);
+                                                    break;
+                                                }
+                                            }
+                                        }
+                                        Data::Stylus(DataStylus::Event(bytes)) => {
+                                            if let (Some(stylus), Ok(evt)) = (stylus.as_mut(), StylusEvent::parse_from_bytes(&bytes)) {
+                                                allow_err!(stylus.handle(&evt));
+                                            }
+                                        }
+                                        _ => {
+                                        }
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                        _ = timer.tick() => {
+                            if let Some(stylus) = stylus.as_mut() {
+                                stylus.check_proximity();
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    }
+
     /// Start uinput service.
     async fn start_service<F: FnOnce(ipc::Connection) + Copy>(postfix: &str, handler: F) {
         match new_listener(postfix).await {
@@ -698,6 +1241,20 @@ pub mod service {
         start_service(IPC_POSTFIX_CONTROL, spawn_controller_handler).await;
     }
 
+    /// Start uinput gamepad service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_gamepad() {
+        log::info!("start uinput gamepad service");
+        start_service(IPC_POSTFIX_GAMEPAD, gamepad::spawn_gamepad_handler).await;
+    }
+
+    /// Start uinput stylus service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_stylus() {
+        log::info!("start uinput stylus service");
+        start_service(IPC_POSTFIX_STYLUS, stylus::spawn_stylus_handler).await;
+    }
+
     pub fn stop_service_keyboard() {
         log::info!("stop uinput keyboard service");
     }
@@ -707,4 +1264,10 @@ pub mod service {
     pub fn stop_service_control() {
         log::info!("stop uinput control service");
     }
+    pub fn stop_service_gamepad() {
+        log::info!("stop uinput gamepad service");
+    }
+    pub fn stop_service_stylus() {
+        log::info!("stop uinput stylus service");
+    }
 }
@@ -61,6 +61,7 @@ lazy_static::lazy_static! {
     pub static ref CURRENT_DISPLAY: Arc<Mutex<usize>> = Arc::new(Mutex::new(usize::MAX));
     static ref LAST_ACTIVE: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
     static ref SWITCH: Arc<Mutex<bool>> = Default::default();
+    static ref KEY_FRAME_REQUESTS: Mutex<HashSet<i32>> = Default::default();
     static ref FRAME_FETCHED_NOTIFIER: (UnboundedSender<(i32, Option<Instant>)>, Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>) = {
         let (tx, rx) = unbounded_channel();
         (tx, Arc::new(TokioMutex::new(rx)))
@@ -72,6 +73,15 @@ lazy_static::lazy_static! {
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
     pub static ref LAST_SYNC_DISPLAYS: Arc<RwLock<Vec<DisplayInfo>>> = Default::default();
     static ref CHANGED_RESOLUTIONS: Arc<RwLock<HashMap<String, ChangedResolution>>> = Default::default();
+    static ref MEDIA_CLOCK_START: Instant = Instant::now();
+}
+
+/// Presentation timestamp in milliseconds for a frame captured at `tm`.
+///
+/// Video and audio frames share this clock, so the peer can align them.
+#[inline]
+pub fn media_pts(tm: Instant) -> i64 {
+    tm.saturating_duration_since(*MEDIA_CLOCK_START).as_millis() as _
 }
 
 #[inline]
@@ -499,7 +509,7 @@ fn run(sp: GenericService) -> ResultType<()> {
     let encoder_cfg = get_encoder_config(&c, quality, last_recording);
 
     let mut encoder;
-    match Encoder::new(encoder_cfg) {
+    match Encoder::new(encoder_cfg.clone()) {
         Ok(x) => encoder = x,
         Err(err) => bail!("Failed to create encoder: {}", err),
     }
@@ -541,7 +551,6 @@ fn run(sp: GenericService) -> ResultType<()> {
 
     let mut frame_controller = VideoFrameController::new();
 
-    let start = time::Instant::now();
     let mut last_check_displays = time::Instant::now();
     #[cfg(windows)]
     let mut try_gdi = 1;
@@ -588,6 +597,15 @@ fn run(sp: GenericService) -> ResultType<()> {
         if last_portable_service_running != crate::portable_service::client::running() {
             bail!("SWITCH");
         }
+        if take_key_frame_request(&sp) {
+            // A new encoder starts with a key frame, the capturer keeps running.
+            match Encoder::new(encoder_cfg.clone()) {
+                Ok(x) => encoder = x,
+                Err(err) => bail!("Failed to create encoder: {}", err),
+            }
+            allow_err!(encoder.set_quality(quality));
+            VIDEO_QOS.lock().unwrap().store_bitrate(encoder.bitrate());
+        }
         check_privacy_mode_changed(&sp, c.privacy_mode_id)?;
         #[cfg(windows)]
         {
@@ -626,8 +644,7 @@ fn run(sp: GenericService) -> ResultType<()> {
         #[cfg(any(target_os = "android", target_os = "ios"))]
         let res = match c.frame(spf) {
             Ok(frame) => {
-                let time = now - start;
-                let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+                let ms = media_pts(now);
                 match frame {
                     scrap::Frame::RAW(data) => {
                         if data.len() != 0 {
@@ -646,8 +663,7 @@ fn run(sp: GenericService) -> ResultType<()> {
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let res = match c.frame(spf) {
             Ok(frame) => {
-                let time = now - start;
-                let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+                let ms = media_pts(now);
                 let send_conn_ids =
                     handle_one_frame(&sp, &frame, ms, &mut encoder, recorder.clone())?;
                 frame_controller.set_send(now, send_conn_ids);
@@ -801,6 +817,7 @@ fn get_recorder(
             width,
             height,
             format: codec_name.into(),
+            audio_format: None,
             tx,
         })
         .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
@@ -955,6 +972,20 @@ pub async fn switch_display(i: i32) {
     }
 }
 
+/// Ask for a key frame for the connection `conn_id`, e.g. after video frames to it were dropped.
+/// Unlike `refresh`, the capturer is not restarted and the display is not switched again.
+pub fn request_key_frame(conn_id: i32) {
+    KEY_FRAME_REQUESTS.lock().unwrap().insert(conn_id);
+}
+
+// Whether a subscriber of the service asked for a key frame. The requests of the others are
+// dropped, they do not watch the video.
+fn take_key_frame_request(sp: &GenericService) -> bool {
+    std::mem::take(&mut *KEY_FRAME_REQUESTS.lock().unwrap())
+        .iter()
+        .any(|id| sp.is_subed(*id))
+}
+
 #[inline]
 pub fn refresh() {
     #[cfg(target_os = "android")]
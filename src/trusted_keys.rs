@@ -0,0 +1,176 @@
+//! Keys trusted to sign the peer keys (`IdPk`) handed out by the rendezvous server.
+//!
+//! The configured `key`, or the built-in `RS_PUB_KEY`, is always trusted. The rendezvous server
+//! announces rotated keys in `ConfigUpdate`, each signed by a key which is trusted already, and
+//! they are used only within their validity period. They are stored along with the key they
+//! derive from, and dropped once another key is configured.
+//!
+//! Clients that do not know about rotation keep verifying with the configured key, so the server
+//! has to keep signing with it until they are updated.
+
+use hbb_common::{
+    config::{Config, RS_PUB_KEY},
+    get_time, log,
+    protobuf::Message as _,
+    rendezvous_proto::TrustedKey,
+    sodiumoxide::crypto::sign,
+};
+use serde_derive::{Deserialize, Serialize};
+
+const OPTION: &str = "trusted-rs-keys";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct StoredKey {
+    // base64
+    pk: String,
+    not_before: i64,
+    not_after: i64,
+}
+
+impl StoredKey {
+    fn is_valid(&self, now: i64) -> bool {
+        (self.not_before == 0 || now >= self.not_before)
+            && (self.not_after == 0 || now < self.not_after)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Stored {
+    // The configured key, or `RS_PUB_KEY`, the keys were announced under.
+    anchor: String,
+    keys: Vec<StoredKey>,
+}
+
+#[inline]
+fn now() -> i64 {
+    get_time() / 1000
+}
+
+fn decode_pk(s: &str) -> Option<sign::PublicKey> {
+    sign::PublicKey::from_slice(&crate::decode64(s.trim()).ok()?)
+}
+
+#[inline]
+fn anchor(key: &str) -> &str {
+    let key = key.trim();
+    if key.is_empty() {
+        RS_PUB_KEY
+    } else {
+        key
+    }
+}
+
+// Keys stored under another anchor belong to another server.
+fn load(stored: &str, anchor: &str) -> Vec<StoredKey> {
+    serde_json::from_str::<Stored>(stored)
+        .ok()
+        .filter(|s| s.anchor == anchor)
+        .map(|s| s.keys)
+        .unwrap_or_default()
+}
+
+/// The stored keys, read from the server process on desktop.
+pub async fn get_stored() -> String {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return Config::get_option(OPTION);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return crate::ipc::get_options_async()
+        .await
+        .remove(OPTION)
+        .unwrap_or_default();
+}
+
+/// The keys `IdPk` may currently be signed with, `key` is the configured key.
+pub fn valid_keys(key: &str, stored: &str) -> Vec<sign::PublicKey> {
+    let key = anchor(key);
+    let now = now();
+    let mut keys: Vec<sign::PublicKey> = decode_pk(key).into_iter().collect();
+    keys.extend(
+        load(stored, key)
+            .iter()
+            .filter(|k| k.is_valid(now))
+            .filter_map(|k| decode_pk(&k.pk)),
+    );
+    keys
+}
+
+/// Store the keys announced in `ConfigUpdate`, those not signed by a trusted key are ignored.
+pub fn update(announced: &[Vec<u8>]) {
+    if announced.is_empty() {
+        return;
+    }
+    let stored = Config::get_option(OPTION);
+    let now = now();
+    let key = Config::get_option("key");
+    let anchor = anchor(&key);
+    let mut trusted = valid_keys(anchor, &stored);
+    let mut keys = load(&stored, anchor);
+    keys.retain(|k| k.not_after == 0 || k.not_after > now);
+    for signed in announced {
+        let Some(key) = trusted
+            .iter()
+            .find_map(|t| sign::verify(signed, t).ok())
+            .and_then(|v| TrustedKey::parse_from_bytes(&v).ok())
+        else {
+            log::warn!("Ignored a rendezvous key not signed by a trusted key");
+            continue;
+        };
+        let Some(pk) = sign::PublicKey::from_slice(&key.pk) else {
+            continue;
+        };
+        let key = StoredKey {
+            pk: crate::encode64(&key.pk),
+            not_before: key.not_before,
+            not_after: key.not_after,
+        };
+        // A chain of keys may be announced at once.
+        if key.is_valid(now) {
+            trusted.push(pk);
+        }
+        keys.retain(|k| k.pk != key.pk);
+        keys.push(key);
+    }
+    if keys != load(&stored, anchor) {
+        log::info!("Trusted rendezvous keys updated: {}", keys.len());
+        let stored = Stored {
+            anchor: anchor.to_owned(),
+            keys,
+        };
+        Config::set_option(
+            OPTION.to_owned(),
+            serde_json::to_string(&stored).unwrap_or_default(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid() {
+        let key = |not_before, not_after| StoredKey {
+            not_before,
+            not_after,
+            ..Default::default()
+        };
+        assert!(key(0, 0).is_valid(100));
+        assert!(key(100, 200).is_valid(100));
+        assert!(!key(100, 200).is_valid(200));
+        assert!(!key(101, 0).is_valid(100));
+    }
+
+    #[test]
+    fn test_load() {
+        let stored = serde_json::to_string(&Stored {
+            anchor: "a".to_owned(),
+            keys: vec![StoredKey::default()],
+        })
+        .unwrap();
+        assert_eq!(load(&stored, "a").len(), 1);
+        assert!(load(&stored, "b").is_empty());
+        assert!(load("[]", "a").is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+//! Time-based one-time password (RFC 6238) as the second factor of incoming connections.
+//!
+//! The secret is kept encrypted in `Config`. A client that passed the check may get a
+//! trusted device token, so it can skip the code for `trusted-device-days`.
+
+use hbb_common::{
+    config::{Config, TrustedDevice},
+    get_time,
+    sodiumoxide::{crypto::hash::sha256, randombytes::randombytes},
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::{collections::HashMap, sync::Mutex};
+
+const SECRET_LEN: usize = 20;
+const TOKEN_LEN: usize = 32;
+const DIGITS: u32 = 6;
+const PERIOD: i64 = 30;
+// Accept the previous and the next code for the clock drift.
+const SKEW: i64 = 1;
+const DEFAULT_TRUSTED_DAYS: i64 = 30;
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+lazy_static::lazy_static! {
+    // The time steps of the accepted codes of each secret and peer id, each code is accepted only once.
+    static ref USED_STEPS: Mutex<HashMap<(String, String), Vec<i64>>> = Default::default();
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    !Config::get_totp_secret().is_empty()
+}
+
+/// A new base32 secret, not stored until it is confirmed by `enable`.
+pub fn generate_secret() -> String {
+    base32_encode(&randombytes(SECRET_LEN))
+}
+
+/// The url encoded in the QR code scanned by authenticator apps.
+pub fn provisioning_url(secret: &str) -> String {
+    let app_name = crate::common::get_app_name();
+    format!(
+        "otpauth://totp/{app}:{id}?secret={secret}&issuer={app}&digits={DIGITS}&period={PERIOD}",
+        app = app_name.replace(' ', "%20"),
+        id = Config::get_id(),
+    )
+}
+
+/// Store `secret` if `code` proves the authenticator app has been set up with it.
+pub fn enable(secret: &str, code: &str) -> bool {
+    if matched_step(secret, code).is_none() {
+        return false;
+    }
+    set_secret(secret);
+    true
+}
+
+pub fn disable() {
+    set_secret("");
+}
+
+// The secret is used by the server process, which may run as another user.
+fn set_secret(secret: &str) {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    Config::set_totp_secret(secret);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    hbb_common::allow_err!(crate::ipc::set_totp_secret(secret.to_owned()));
+}
+
+pub fn verify(id: &str, code: &str) -> bool {
+    let secret = Config::get_totp_secret();
+    match matched_step(&secret, code) {
+        Some(step) => use_step(&secret, id, step, current_step()),
+        None => false,
+    }
+}
+
+// A code can not be replayed, neither can an older one after a newer one is used.
+// The next code accepted for the clock drift does not reject the current one.
+fn use_step(secret: &str, id: &str, step: i64, now: i64) -> bool {
+    let mut used = USED_STEPS.lock().unwrap();
+    used.retain(|_, steps| {
+        steps.retain(|s| *s >= now - SKEW);
+        !steps.is_empty()
+    });
+    let steps = used.entry((secret.to_owned(), id.to_owned())).or_default();
+    if steps.contains(&step) || steps.iter().any(|s| *s > step && *s <= now) {
+        return false;
+    }
+    steps.push(step);
+    true
+}
+
+#[inline]
+fn current_step() -> i64 {
+    get_time() / 1000 / PERIOD
+}
+
+// The time step of `code`, if it is valid now.
+fn matched_step(secret: &str, code: &str) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let key = base32_decode(secret)?;
+    if key.is_empty() {
+        return None;
+    }
+    let step = current_step();
+    let mut matched = None;
+    for i in -SKEW..=SKEW {
+        // Do not return early, all candidates take the same time.
+        if constant_time_eq(totp(&key, (step + i) as u64).as_bytes(), code.as_bytes()) {
+            matched = Some(step + i);
+        }
+    }
+    matched
+}
+
+fn totp(key: &[u8], counter: u64) -> String {
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(key) else {
+        return "".to_owned();
+    };
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+fn trusted_days() -> i64 {
+    Config::get_option("trusted-device-days")
+        .parse()
+        .unwrap_or(DEFAULT_TRUSTED_DAYS)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(sha256::hash(token.as_bytes()).0)
+}
+
+/// Issue a token for the client `id`, empty if trusting devices is disabled.
+pub fn trust_device(id: &str) -> String {
+    let days = trusted_days();
+    if days <= 0 {
+        return "".to_owned();
+    }
+    let token = hex::encode(randombytes(TOKEN_LEN));
+    let now = get_time();
+    let mut devices = Config::get_trusted_devices();
+    devices.retain(|d| d.expiry > now && d.id != id);
+    devices.push(TrustedDevice {
+        id: id.to_owned(),
+        token_hash: hash_token(&token),
+        expiry: now + days * 24 * 3600 * 1000,
+    });
+    Config::set_trusted_devices(devices);
+    token
+}
+
+pub fn is_trusted_device(id: &str, token: &str) -> bool {
+    if token.is_empty() {
+        return false;
+    }
+    let now = get_time();
+    let mut devices = Config::get_trusted_devices();
+    let len = devices.len();
+    devices.retain(|d| d.expiry > now);
+    let trusted = devices.iter().any(|d| {
+        d.id == id && constant_time_eq(d.token_hash.as_bytes(), hash_token(token).as_bytes())
+    });
+    if devices.len() != len {
+        Config::set_trusted_devices(devices);
+    }
+    trusted
+}
+
+pub fn clear_trusted_devices() {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    Config::set_trusted_devices(vec![]);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    hbb_common::allow_err!(crate::ipc::clear_trusted_devices());
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &b in data {
+        buffer = (buffer << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes().filter(|c| *c != b'=' && *c != b' ') {
+        let v = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | v;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp() {
+        // RFC 6238 SHA1 test vectors, truncated to 6 digits.
+        let key = b"12345678901234567890";
+        assert_eq!(totp(key, 59 / 30), "287082");
+        assert_eq!(totp(key, 1111111109 / 30), "081804");
+        assert_eq!(totp(key, 2000000000 / 30), "279037");
+        let secret = base32_encode(key);
+        assert_eq!(secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode(&secret).unwrap(), key);
+    }
+
+    #[test]
+    fn test_replay() {
+        let key = b"12345678901234567890";
+        let secret = base32_encode(key);
+        let step = current_step();
+        let code = totp(key, step as u64);
+        assert_eq!(matched_step(&secret, &code), Some(step));
+        assert!(use_step(&secret, "1", step, step));
+        assert!(!use_step(&secret, "1", step, step));
+        assert!(!use_step(&secret, "1", step - 1, step));
+        assert!(use_step(&secret, "2", step - 1, step));
+        assert!(use_step("other", "1", step, step));
+        // The next code does not reject the current one, but is not accepted twice.
+        assert!(use_step(&secret, "3", step + 1, step));
+        assert!(use_step(&secret, "3", step, step));
+        assert!(!use_step(&secret, "3", step + 1, step + 1));
+        assert!(!use_step(&secret, "3", step, step + 1));
+        assert!(use_step(&secret, "1", step + 1, step + 1));
+    }
+}
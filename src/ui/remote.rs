@@ -287,6 +287,14 @@ impl InvokeUiSession for SciterHandler {
 
     fn portable_service_running(&self, _running: bool) {}
 
+    fn set_audio_inputs(&self, inputs: &AudioInputs) {
+        let mut v = Value::array(0);
+        for x in inputs.inputs.iter() {
+            v.push(x);
+        }
+        self.call("setAudioInputs", &make_args!(v, inputs.current.clone()));
+    }
+
     fn on_voice_call_started(&self) {
         self.call("onVoiceCallStart", &make_args!());
     }
@@ -452,6 +460,8 @@ impl sciter::EventHandler for SciterSession {
         fn refresh_video();
         fn record_screen(bool, i32, i32);
         fn record_status(bool);
+        fn get_remote_audio_inputs();
+        fn select_remote_audio_input(String);
         fn get_toggle_option(String);
         fn is_privacy_mode_supported();
         fn toggle_option(String);
@@ -694,6 +694,7 @@ async fn handle_fs(
             overwrite_detection,
             total_size,
             conn_id,
+            roots,
         } => {
             // cm has no show_hidden context
             // dummy remote, show_hidden, is_remote
@@ -716,6 +717,7 @@ async fn handle_fs(
             );
             job.total_size = total_size;
             job.conn_id = conn_id;
+            job.set_roots(roots);
             write_jobs.push(job);
         }
         ipc::FS::CancelWrite { id } => {
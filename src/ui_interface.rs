@@ -268,6 +268,21 @@ pub fn test_if_valid_server(host: String) -> String {
     hbb_common::socket_client::test_if_valid_server(&host)
 }
 
+/// The JSON report of the connection path to `id`, from the service if it runs.
+pub fn diagnose(id: String) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    match ipc::diagnose(id.clone()) {
+        Ok(report) => return report,
+        Err(err) => log::info!("Failed to diagnose in the service: {}", err),
+    }
+    diagnose_(id)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn diagnose_(id: String) -> String {
+    crate::diagnose::diagnose(&id).await.to_json()
+}
+
 #[inline]
 #[cfg(feature = "flutter")]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -507,6 +522,103 @@ pub fn set_permanent_password(password: String) {
     allow_err!(ipc::set_permanent_password(password));
 }
 
+/// The trusted client keys as json.
+#[inline]
+pub fn get_authorized_keys() -> String {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    let keys = Config::get_authorized_keys();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    let keys = ipc::get_authorized_keys();
+    serde_json::to_string(&keys).unwrap_or_default()
+}
+
+#[inline]
+pub fn add_authorized_key(name: String, id: String, pk: String) -> String {
+    let pk = pk.trim().to_owned();
+    match crate::decode64(&pk) {
+        Ok(v) if v.len() == hbb_common::sodiumoxide::crypto::sign::PUBLICKEYBYTES => {}
+        _ => return "Invalid public key".to_owned(),
+    }
+    let mut keys: Vec<config::AuthorizedKey> =
+        serde_json::from_str(&get_authorized_keys()).unwrap_or_default();
+    keys.retain(|k| k.pk != pk);
+    keys.push(config::AuthorizedKey {
+        name,
+        id: id.trim().to_owned(),
+        pk,
+    });
+    set_authorized_keys(keys);
+    "".to_owned()
+}
+
+#[inline]
+pub fn remove_authorized_key(pk: String) {
+    let mut keys: Vec<config::AuthorizedKey> =
+        serde_json::from_str(&get_authorized_keys()).unwrap_or_default();
+    keys.retain(|k| k.pk != pk);
+    set_authorized_keys(keys);
+}
+
+fn set_authorized_keys(keys: Vec<config::AuthorizedKey>) {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    Config::set_authorized_keys(keys);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    allow_err!(ipc::set_authorized_keys(keys));
+}
+
+/// The key this device signs with when connecting to hosts that trust it.
+#[inline]
+pub fn get_client_public_key() -> String {
+    crate::encode64(Config::get_key_pair().1)
+}
+
+/// The first invalid rule of the access list, empty if all are valid.
+#[inline]
+pub fn check_access_list(list: String) -> String {
+    #[cfg(not(target_os = "ios"))]
+    for line in list.lines().map(|x| x.trim()) {
+        if !line.is_empty()
+            && !line.starts_with('#')
+            && crate::server::access_list::parse_rule(line).is_none()
+        {
+            return line.to_owned();
+        }
+    }
+    let _ = list;
+    "".to_owned()
+}
+
+#[inline]
+pub fn has_two_factor_auth() -> bool {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return crate::two_factor_auth::is_enabled();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return ipc::is_totp_enabled();
+}
+
+/// A new secret and its provisioning url as json, enabled only after `enable_two_factor_auth`.
+#[inline]
+pub fn generate_two_factor_auth() -> String {
+    let secret = crate::two_factor_auth::generate_secret();
+    let url = crate::two_factor_auth::provisioning_url(&secret);
+    serde_json::json!({ "secret": secret, "url": url }).to_string()
+}
+
+#[inline]
+pub fn enable_two_factor_auth(secret: String, code: String) -> bool {
+    crate::two_factor_auth::enable(&secret, &code)
+}
+
+#[inline]
+pub fn disable_two_factor_auth() {
+    crate::two_factor_auth::disable();
+}
+
+#[inline]
+pub fn clear_trusted_devices() {
+    crate::two_factor_auth::clear_trusted_devices();
+}
+
 #[inline]
 pub fn get_peer(id: String) -> PeerConfig {
     PeerConfig::load(&id)
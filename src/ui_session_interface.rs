@@ -265,6 +265,10 @@ impl<T: InvokeUiSession> Session<T> {
         if name == "enable-file-transfer" {
             self.send(Data::ToggleClipboardFile);
         }
+        #[cfg(target_os = "linux")]
+        if name == "forward-gamepad" {
+            self.send(Data::ForwardGamepad(self.get_toggle_option(name.clone())));
+        }
         if let Some(msg) = msg {
             self.send(Data::Message(msg));
         }
@@ -294,6 +298,22 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::RecordScreen(start, w, h, self.id.clone()));
     }
 
+    pub fn get_remote_audio_inputs(&self) {
+        let mut misc = Misc::new();
+        misc.set_get_audio_inputs(true);
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        self.send(Data::Message(msg));
+    }
+
+    pub fn select_remote_audio_input(&self, input: String) {
+        let mut misc = Misc::new();
+        misc.set_select_audio_input(input);
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        self.send(Data::Message(msg));
+    }
+
     pub fn record_status(&self, status: bool) {
         let mut misc = Misc::new();
         misc.set_client_record_status(status);
@@ -813,6 +833,19 @@ impl<T: InvokeUiSession> Session<T> {
         send_pointer_device_event(evt, alt, ctrl, shift, command, self);
     }
 
+    pub fn send_stylus_event(
+        &self,
+        stylus: StylusEvent,
+        alt: bool,
+        ctrl: bool,
+        shift: bool,
+        command: bool,
+    ) {
+        let mut evt = PointerDeviceEvent::new();
+        evt.set_stylus_event(stylus);
+        send_pointer_device_event(evt, alt, ctrl, shift, command, self);
+    }
+
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn is_scroll_reverse_mode(&self) -> bool {
@@ -971,6 +1004,16 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Login((os_username, os_password, password, remember)));
     }
 
+    pub fn send_two_factor_code(&self, code: String, trust_this_device: bool) {
+        let mut msg_out = Message::new();
+        msg_out.set_two_factor_auth(TwoFactorAuth {
+            code,
+            trust_this_device,
+            ..Default::default()
+        });
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn new_rdp(&self) {
         self.send(Data::NewRDP);
     }
@@ -1171,6 +1214,7 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn cancel_msgbox(&self, tag: &str);
     fn switch_back(&self, id: &str);
     fn portable_service_running(&self, running: bool);
+    fn set_audio_inputs(&self, inputs: &AudioInputs);
     fn on_voice_call_started(&self);
     fn on_voice_call_closed(&self, reason: &str);
     fn on_voice_call_waiting(&self);